@@ -1,85 +1,747 @@
 use {
-  egui::{Context, CursorIcon, ScrollArea, Window, text::LayoutJob},
-  egui_extras::syntax_highlighting::{self, CodeTheme},
-  raki::{Instruction, Isa, OpcodeKind},
+  super::{
+    assembler, breakpoints::Breakpoints, highlight::highlight, lines::Lines,
+    machine::Machine, symbols::Symbols,
+  },
+  egui::{
+    Color32, Context, CursorIcon, Galley, Key, Response, RichText, ScrollArea,
+    Sense, Shape, Stroke, TextEdit, TextStyle, Ui, Visuals, Window,
+    text::LayoutJob, vec2,
+  },
+  raki::{BaseIOpcode, COpcode, Instruction, OpcodeKind, PrivOpcode},
+  std::{
+    collections::{HashMap, HashSet},
+    mem,
+    ops::Range,
+    sync::Arc,
+  },
 };
 
+/// Address a branch or a direct jump at `pc` goes to.
+fn target(inst: &Instruction, pc: u64) -> Option<u64> {
+  use BaseIOpcode::{BEQ, BGE, BGEU, BLT, BLTU, BNE, JAL};
+
+  match inst.opc {
+    OpcodeKind::BaseI(JAL | BEQ | BNE | BLT | BGE | BLTU | BGEU)
+    | OpcodeKind::C(COpcode::J | COpcode::BEQZ | COpcode::BNEZ) => {
+      Some(pc.wrapping_add_signed(inst.imm? as i64))
+    }
+    _ => None,
+  }
+}
+
+/// Whether execution may go on to the instruction after `inst`.
+fn falls_through(inst: &Instruction) -> bool {
+  use BaseIOpcode::{JAL, JALR};
+
+  match inst.opc {
+    // Calls return, plain jumps do not
+    OpcodeKind::BaseI(JAL | JALR) => inst.rd != Some(0),
+    OpcodeKind::C(COpcode::J | COpcode::JR) => false,
+    OpcodeKind::Priv(PrivOpcode::MRET | PrivOpcode::SRET) => false,
+    _ => true,
+  }
+}
+
+/// Float instructions, which do not decode but are code all the same.
+fn is_float(raw: u32, len: usize) -> bool {
+  match len {
+    // `c.fld`, `c.fsd`, `c.fldsp` and `c.fsdsp`
+    2 => matches!((raw >> 13 & 7, raw & 3), (1 | 5, 0 | 2)),
+    _ => matches!(raw & 0x7f, 0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53),
+  }
+}
+
+/// Raw instruction at `off` and its length, `None` past the end.
+fn fetch(bytes: &[u8], off: usize) -> Option<(u32, usize)> {
+  let low = u16::from_le_bytes(bytes.get(off..off + 2)?.try_into().ok()?);
+  if low & 0b11 != 0b11 {
+    return Some((low as u32, 2));
+  }
+  let raw = u32::from_le_bytes(bytes.get(off..off + 4)?.try_into().ok()?);
+  Some((raw, 4))
+}
+
+/// Where decoding starts: `pc`, the entry point and every function.
+pub fn seeds(pc: u64, entry: Option<u64>, symbols: &Symbols) -> Vec<u64> {
+  let mut seeds = vec![pc];
+  seeds.extend(entry);
+  let functions = symbols.iter().filter(|sym| sym.code);
+  seeds.extend(functions.map(|sym| sym.addr));
+  seeds
+}
+
+/// Marks of the decoded bytes, where instructions start and the other
+/// bytes they take.
+const START: u8 = 1;
+const REST: u8 = 2;
+
+/// What a run of bytes shows as.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+  /// Reached as code, an instruction or not one known to the decoder.
+  Code,
+  Word,
+  Byte,
+  /// Zeros, too many to list.
+  Zero,
+}
+
+#[derive(Clone, Copy)]
+struct Item {
+  addr: u64,
+  size: usize,
+  kind: Kind,
+}
+
+impl Item {
+  fn is_code(&self) -> bool {
+    self.kind == Kind::Code
+  }
+
+  /// What the item shows of `bytes` at `base`, the same value shows the
+  /// same text.
+  fn value(&self, base: u64, bytes: &[u8]) -> u64 {
+    let off = (self.addr - base) as usize;
+    match self.kind {
+      Kind::Code => fetch(bytes, off).map_or(0, |(raw, _)| raw as u64),
+      Kind::Word => {
+        u32::from_le_bytes(bytes[off..off + 4].try_into().unwrap()) as u64
+      }
+      Kind::Byte => bytes[off] as u64,
+      Kind::Zero => self.size as u64,
+    }
+  }
+
+  /// Encoding as `objdump` shows it, nothing for runs of zeros.
+  fn raw(&self, value: u64) -> String {
+    match self.kind {
+      Kind::Code | Kind::Byte => {
+        format!("{value:0width$x}", width = self.size * 2)
+      }
+      Kind::Word => format!("{value:08x}"),
+      Kind::Zero => String::new(),
+    }
+  }
+
+  /// Where the branch or jump the item holds goes.
+  fn target(&self, value: u64) -> Option<u64> {
+    if !self.is_code() {
+      return None;
+    }
+    let inst = Machine::decode(value as u32, self.size as u64).ok()?;
+    target(&inst, self.addr)
+  }
+
+  fn text(&self, value: u64) -> String {
+    match self.kind {
+      Kind::Code => Machine::disassemble(value as u32, self.size as u64)
+        .unwrap_or_else(|| String::from("unknown instruction")),
+      Kind::Word => format!(".word {value:#010x}"),
+      Kind::Byte => format!(".byte {value:#04x}"),
+      Kind::Zero => format!(".zero {value}"),
+    }
+  }
+}
+
+/// Data items do not cross pages, so a page lays out the same whatever
+/// is around it.
+const PAGE: u64 = 4096;
+
+/// Items of data in `bytes` at `addr`, words where aligned.
+fn data(addr: u64, bytes: &[u8], items: &mut Vec<Item>) {
+  let mut off = 0;
+  while off < bytes.len() {
+    let at = addr + off as u64;
+    let rest = &bytes[off..];
+    let page = (PAGE - at % PAGE) as usize;
+    let zeros = rest.iter().take(page).take_while(|&&byte| byte == 0).count();
+    let (size, kind) = if zeros >= 16 {
+      (zeros, Kind::Zero)
+    } else if at & 3 == 0 && rest.len() >= 4 {
+      (4, Kind::Word)
+    } else {
+      (1, Kind::Byte)
+    };
+    items.push(Item { addr: at, size, kind });
+    off += size;
+  }
+}
+
+/// A line of the window, all as high as the monospace font.
+#[derive(Clone, Copy)]
+enum Row {
+  /// Label of the symbol at an address.
+  Symbol(u64),
+  /// Source line the code at an address was compiled from.
+  Source(u64),
+  Item(Item),
+}
+
+impl Row {
+  fn addr(&self) -> u64 {
+    match *self {
+      Self::Symbol(addr) | Self::Source(addr) => addr,
+      Self::Item(item) => item.addr,
+    }
+  }
+
+  fn end(&self) -> u64 {
+    match *self {
+      Self::Item(item) => item.addr + item.size as u64,
+      _ => self.addr(),
+    }
+  }
+}
+
+/// The bytes `old` and `new` differ in, from the first to the last.
+fn dirty(old: &[u8], new: &[u8]) -> Option<Range<usize>> {
+  const CHUNK: usize = 4096;
+
+  // Whole chunks compare much faster than bytes one by one
+  let chunks = || old.chunks(CHUNK).zip(new.chunks(CHUNK)).enumerate();
+  let differ = |(_, (old, new)): &(usize, (&[u8], &[u8]))| old != new;
+  let (first, _) = chunks().find(differ)?;
+  let (last, _) = chunks().rev().find(differ)?;
+  let start = (first * CHUNK..).find(|&idx| old[idx] != new[idx])?;
+  let end = (last * CHUNK..old.len().min((last + 1) * CHUNK))
+    .rfind(|&idx| old[idx] != new[idx])?;
+  Some(start..end + 1)
+}
+
+fn join(range: Option<Range<usize>>, other: Range<usize>) -> Range<usize> {
+  match range {
+    Some(range) => range.start.min(other.start)..range.end.max(other.end),
+    None => other,
+  }
+}
+
+/// Arrow in front of the instruction at `pc`.
+fn marker(ui: &mut Ui, current: bool) {
+  let size = ui.text_style_height(&TextStyle::Monospace);
+  let (rect, _) = ui.allocate_exact_size(vec2(size, size), Sense::hover());
+  if current {
+    let rect = rect.shrink(size * 0.2);
+    let points = vec![rect.left_top(), rect.right_center(), rect.left_bottom()];
+    let color = ui.visuals().warn_fg_color;
+    ui.painter().add(Shape::convex_polygon(points, color, Stroke::NONE));
+  }
+}
+
+/// Breakpoint marker in front of an instruction, `state` as in
+/// [`Breakpoints::at`].
+fn gutter(ui: &mut Ui, state: Option<bool>) -> Response {
+  let size = ui.text_style_height(&egui::TextStyle::Monospace);
+  let (rect, response) =
+    ui.allocate_exact_size(vec2(size, size), Sense::click());
+  let (center, radius) = (rect.center(), size * 0.35);
+  let red = Color32::from_rgb(220, 50, 50);
+  let painter = ui.painter();
+  match state {
+    Some(true) => {
+      painter.circle_filled(center, radius, red);
+    }
+    Some(false) => {
+      painter.circle_stroke(center, radius, Stroke::new(1.5_f32, red));
+    }
+    None if response.hovered() => {
+      painter.circle_filled(center, radius, red.gamma_multiply(0.4));
+    }
+    None => {}
+  }
+  response.on_hover_text("Toggle breakpoint")
+}
+
+/// `nop` of the given size, `c.nop` for two bytes.
+fn nop(size: usize) -> Vec<u8> {
+  match size {
+    2 => 0x0001_u16.to_le_bytes().to_vec(),
+    _ => 0x0000_0013_u32.to_le_bytes().to_vec(),
+  }
+}
+
+/// An instruction being rewritten in place.
+struct Edit {
+  addr: u64,
+  size: usize,
+  text: String,
+  error: Option<String>,
+  focus: bool,
+}
+
+impl Edit {
+  /// Bytes to write over the `size` of the old instruction, padded with a
+  /// `c.nop` when the new one is shorter.
+  fn encode(&self, symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let symbols =
+      symbols.iter().map(|sym| (sym.name.clone(), sym.addr as i64)).collect();
+    let mut bytes = assembler::instruction(&self.text, self.addr, &symbols)
+      .map_err(|err| err.to_string())?;
+    if bytes.len() > self.size {
+      return Err(format!(
+        "takes {} bytes, only {} fit here",
+        bytes.len(),
+        self.size
+      ));
+    }
+    bytes.extend(nop(self.size - bytes.len()));
+    Ok(bytes)
+  }
+}
+
 pub struct Asm {
-  asm: Vec<(usize, Option<Instruction>)>,
+  /// Address of the first decoded byte.
+  base: u64,
+  /// Memory as last decoded, to find what changed since.
+  bytes: Vec<u8>,
+  /// `START` or `REST` for each byte of code, zero for data.
+  marks: Vec<u8>,
+  /// Every address decoding started from, to decode again what changed.
+  entries: HashSet<u64>,
+  /// Decode everything again, what the rows show changed.
+  stale: bool,
+  rows: Vec<Row>,
+  /// Highlighted text of the items by address, kind and value.
+  galleys: HashMap<(u64, Kind, u64), Arc<Galley>>,
+  /// Colors and scale the galleys were laid out for.
+  look: (Visuals, f32),
+  /// Keep the instruction at `pc` in view as it moves.
+  pub follow: bool,
+  /// `pc` as last shown.
+  pc: u64,
+  /// Address to scroll to, unless its row is in view already.
+  goto: Option<u64>,
+  /// Rows shown in the last frame.
+  visible: Range<usize>,
+  /// Ranges the user marked as code, disassembled whatever they hold.
+  pub code: Vec<Range<u64>>,
+  /// `code` changed and the memory needs decoding again.
+  marked: bool,
+  edit: Option<Edit>,
+  /// Bytes to write to memory at an address.
+  patch: Option<(u64, Vec<u8>)>,
   pub open: bool,
 }
 
 impl Default for Asm {
   fn default() -> Self {
-    Self { asm: vec![], open: true }
+    Self {
+      base: 0,
+      bytes: vec![],
+      marks: vec![],
+      entries: HashSet::new(),
+      stale: false,
+      rows: vec![],
+      galleys: HashMap::new(),
+      look: (Visuals::default(), 0.0),
+      follow: true,
+      pc: 0,
+      goto: None,
+      visible: 0..0,
+      code: vec![],
+      marked: false,
+      edit: None,
+      patch: None,
+      open: true,
+    }
   }
 }
 
 impl Asm {
-  pub fn decode(&mut self, bytes: &[u8]) {
-    use raki::Decode;
+  /// Most galleys kept, they are laid out again once dropped.
+  const GALLEYS: usize = 4096;
+
+  /// Edit or `nop` out to apply, once the machine is not running.
+  pub fn take_patch(&mut self) -> Option<(u64, Vec<u8>)> {
+    self.patch.take()
+  }
+
+  /// Whether marked code changed since the last call.
+  pub fn take_marked(&mut self) -> bool {
+    mem::take(&mut self.marked)
+  }
+
+  /// Whether decoding neither reached `addr` nor started from it, like where
+  /// a jump through a register lands.
+  pub fn unreached(&self, addr: u64) -> bool {
+    let off = addr.checked_sub(self.base);
+    let mark = off.and_then(|off| self.marks.get(usize::try_from(off).ok()?));
+    mark != Some(&START) && !self.entries.contains(&addr)
+  }
+
+  /// Decode all of the memory next time, as symbols or lines changed.
+  pub fn reload(&mut self) {
+    self.stale = true;
+    self.galleys.clear();
+  }
+
+  /// Disassemble what is reachable from `seeds` and the marked code, the
+  /// rest of `bytes` shows as data.
+  ///
+  /// Decoding follows fall-through, branches and direct jumps, and stops at
+  /// whatever is not an instruction. Changes to data are decoded where they
+  /// are, only changes to code decode everything again.
+  pub fn decode(
+    &mut self,
+    base: u64,
+    bytes: &[u8],
+    seeds: &[u64],
+    symbols: &Symbols,
+    lines: &Lines,
+  ) {
+    let mut work = seeds.to_vec();
+    work.extend(self.code.iter().map(|code| code.start));
 
-    fn read16(bytes: &[u8], addr: usize) -> u64 {
-      return (bytes[addr] as u64) | ((bytes[addr + 1] as u64) << 8);
+    if mem::take(&mut self.stale)
+      || base != self.base
+      || bytes.len() != self.bytes.len()
+    {
+      self.base = base;
+      self.bytes = bytes.to_vec();
+      self.marks = vec![0; bytes.len()];
+      self.entries.clear();
+      self.descend(work);
+      self.rows = self.rows(0..bytes.len(), symbols, lines);
+      return;
     }
 
-    fn read32(bytes: &[u8], addr: usize) -> u64 {
-      return (bytes[addr] as u64)
-        | ((bytes[addr + 1] as u64) << 8)
-        | ((bytes[addr + 2] as u64) << 16)
-        | ((bytes[addr + 3] as u64) << 24);
+    let mut touched = None;
+    if let Some(changed) = dirty(&self.bytes, bytes) {
+      self.bytes[changed.clone()].copy_from_slice(&bytes[changed.clone()]);
+      if self.marks[changed.clone()].iter().any(|&mark| mark != 0) {
+        // Changed code may no longer reach what it did, so start over
+        let old = mem::replace(&mut self.marks, vec![0; bytes.len()]);
+        self.entries.clear();
+        self.descend(mem::take(&mut work));
+        touched = dirty(&old, &self.marks);
+      } else {
+        // Data may decode now, going on from the instruction before it
+        let mut start = changed.start;
+        if start > 0 && self.marks[start - 1] != 0 {
+          start -= 1;
+          while start > 0 && self.marks[start] == REST {
+            start -= 1;
+          }
+          self.marks[start..changed.start].fill(0);
+          work.push(base + start as u64);
+        }
+        // Or jumping to it
+        let range = base + changed.start as u64..base + changed.end as u64;
+        work.extend(self.entries.iter().filter(|addr| range.contains(addr)));
+      }
+      touched = Some(join(touched, changed));
+    }
+    if let Some(marked) = self.descend(work) {
+      touched = Some(join(touched, marked));
     }
+    if let Some(touched) = touched {
+      self.splice(touched, symbols, lines);
+    }
+  }
 
-    let mut asm = Vec::new();
+  /// Mark the code reachable from `work`, and return the bytes marked.
+  fn descend(&mut self, mut work: Vec<u64>) -> Option<Range<usize>> {
+    let (base, bytes, marks) = (self.base, &self.bytes, &mut self.marks);
+    let marked = |addr: u64| self.code.iter().any(|code| code.contains(&addr));
+    let mut touched = None;
 
-    let mut pc = 0;
-    loop {
-      if bytes.len() - pc < 2 {
-        break;
-      }
-      let inst16 = read16(&bytes, pc);
-      if let 0 | 1 | 2 = inst16 & 0b11 {
-        let inst = (inst16 as u16).decode(Isa::Rv64).ok();
-        asm.push((2, inst));
-        pc += 2;
-      } else {
-        if bytes.len() - pc < 4 {
+    while let Some(mut addr) = work.pop() {
+      self.entries.insert(addr);
+      // Register an `auipc` just set and its value
+      let mut upper: Option<(usize, u64)> = None;
+      while let Some(off) = addr.checked_sub(base).map(|off| off as usize) {
+        // Instructions are two byte aligned, others come from bad seeds
+        let Some((raw, len)) = fetch(bytes, off).filter(|_| off & 1 == 0)
+        else {
+          break;
+        };
+        if marks[off..off + len].iter().any(|&mark| mark != 0) {
+          break;
+        }
+        let falls = match Machine::decode(raw, len as u64) {
+          Ok(inst) => {
+            work.extend(target(&inst, addr));
+            // Far calls and jumps pair `auipc` with `jalr`
+            if let OpcodeKind::BaseI(BaseIOpcode::JALR) = inst.opc
+              && let Some((reg, high)) = upper
+              && inst.rs1 == Some(reg)
+            {
+              let offset = inst.imm.unwrap_or_default() as i64;
+              work.push(high.wrapping_add_signed(offset));
+            }
+            upper = match inst.opc {
+              OpcodeKind::BaseI(BaseIOpcode::AUIPC) => (inst.rd.zip(inst.imm))
+                .map(|(rd, imm)| (rd, addr.wrapping_add_signed(imm as i64))),
+              _ => None,
+            };
+            falls_through(&inst)
+          }
+          Err(_) if is_float(raw, len) || marked(addr) => {
+            upper = None;
+            true
+          }
+          Err(_) => break,
+        };
+        marks[off] = START;
+        marks[off + 1..off + len].fill(REST);
+        touched = Some(join(touched, off..off + len));
+        addr += len as u64;
+        // Marked code goes on past jumps, to its end
+        if !falls && !marked(addr) {
           break;
         }
-        let inst = (read32(&bytes, pc) as u32).decode(Isa::Rv64).ok();
-        asm.push((4, inst));
-        pc += 4;
       }
     }
+    touched
+  }
 
-    self.asm = asm;
+  /// Rows of the bytes in `range`, which starts and ends between items.
+  fn rows(
+    &self,
+    range: Range<usize>,
+    symbols: &Symbols,
+    lines: &Lines,
+  ) -> Vec<Row> {
+    let (base, bytes, marks) = (self.base, &self.bytes, &self.marks);
+    let mut items = Vec::new();
+    let mut off = range.start;
+    while off < range.end {
+      if marks[off] == START {
+        let (_, size) = fetch(bytes, off).unwrap();
+        items.push(Item { addr: base + off as u64, size, kind: Kind::Code });
+        off += size;
+      } else {
+        let end = (off..range.end).find(|&idx| marks[idx] == START);
+        let end = end.unwrap_or(range.end);
+        data(base + off as u64, &bytes[off..end], &mut items);
+        off = end;
+      }
+    }
+
+    let mut rows = Vec::with_capacity(items.len());
+    for item in items {
+      if symbols.at(item.addr).is_some() {
+        rows.push(Row::Symbol(item.addr));
+      }
+      // Like `objdump -S`, each source line before its first instruction
+      let at = lines.lookup(item.addr);
+      if at.is_some() && at != lines.lookup(item.addr.wrapping_sub(1)) {
+        rows.push(Row::Source(item.addr));
+      }
+      rows.push(Row::Item(item));
+    }
+    rows
+  }
+
+  /// Lay out again the rows of the pages `touched` is in.
+  fn splice(
+    &mut self,
+    touched: Range<usize>,
+    symbols: &Symbols,
+    lines: &Lines,
+  ) {
+    let (base, rows) = (self.base, &self.rows);
+    let end = base + self.bytes.len() as u64;
+    let lo = (base + touched.start as u64) / PAGE * PAGE;
+    let hi = (base + touched.end as u64).div_ceil(PAGE) * PAGE;
+    let (lo, hi) = (lo.max(base), hi.min(end));
+
+    // From the labels of the item at `lo` to the item at the end
+    let mut start = rows.partition_point(|row| row.end() <= lo);
+    while start > 0 && !matches!(rows[start - 1], Row::Item(_)) {
+      start -= 1;
+    }
+    let end = rows.partition_point(|row| row.addr() < hi);
+
+    let from = (rows[start].addr() - base) as usize;
+    let to = (rows[end - 1].end() - base) as usize;
+    let new = self.rows(from..to, symbols, lines);
+    self.rows.splice(start..end, new);
   }
 
-  pub fn ui(&mut self, ctx: &Context) -> Option<usize> {
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    symbols: &Symbols,
+    lines: &Lines,
+    breakpoints: &mut Breakpoints,
+    pc: u64,
+  ) -> Option<usize> {
     let mut ret = None;
 
-    if !self.open || self.asm.is_empty() {
+    if !self.open || self.rows.is_empty() {
       return None;
     }
 
-    Window::new("Instructions").show(ctx, |ui| {
-      ScrollArea::vertical().show(ui, |ui| {
-        let style = ctx.style();
-        let theme = CodeTheme::from_style(&style);
+    if self.follow && pc != self.pc {
+      self.goto = Some(pc);
+    }
+    self.pc = pc;
+
+    let style = ctx.style();
+    let look = (style.visuals.clone(), ctx.pixels_per_point());
+    if look != self.look || self.galleys.len() > Self::GALLEYS {
+      self.look = look;
+      self.galleys.clear();
+    }
+
+    Window::new("Instructions").default_height(480.0).show(ctx, |ui| {
+      ui.checkbox(&mut self.follow, "Follow pc")
+        .on_hover_text("Scroll to pc as it moves");
 
-        let mut pc = 0;
+      let height = ui.text_style_height(&TextStyle::Monospace);
+      let (mut done, mut edit_at, mut goto) = (false, None, None);
 
-        for &(size, ref line) in self.asm.iter() {
-          let line = if let Some(inst) = line {
-            format!("{inst}")
-          } else {
-            String::from("unknown instruction")
+      let mut scroll = ScrollArea::vertical().auto_shrink(false);
+      // Bring the row of `goto` into view, a few rows below the top
+      if let Some(addr) = self.goto.take() {
+        let idx = self.rows.partition_point(|row| row.end() <= addr);
+        let (start, end) = (self.visible.start, self.visible.end);
+        if !(start + 1..end.saturating_sub(1)).contains(&idx) {
+          let line = idx.saturating_sub(3) as f32;
+          let offset = (height + ui.spacing().item_spacing.y) * line;
+          scroll = scroll.vertical_scroll_offset(offset);
+        }
+      }
+
+      scroll.show_rows(ui, height, self.rows.len(), |ui, range| {
+        self.visible = range.clone();
+        for idx in range {
+          let item = match self.rows[idx] {
+            Row::Symbol(addr) => {
+              if let Some(symbol) = symbols.at(addr) {
+                let name = format!("{}:", symbol.name);
+                ui.label(RichText::new(name).monospace().strong());
+              }
+              continue;
+            }
+            Row::Source(addr) => {
+              if let Some((file, line)) = lines.lookup(addr) {
+                let text = lines.text(file, line).unwrap_or_default().trim();
+                let path = lines.path(file);
+                let name = path.rsplit('/').next().unwrap_or(path);
+                ui.label(
+                  RichText::new(format!("{name}:{line}  {text}"))
+                    .monospace()
+                    .weak(),
+                )
+                .on_hover_text(path);
+              }
+              continue;
+            }
+            Row::Item(item) => item,
           };
-          let job =
-            syntax_highlighting::highlight(ctx, &style, &theme, &line, "rs");
-          let galley = ui.fonts(|f| f.layout_job(job));
+          let Item { addr, size, .. } = item;
+          let code = item.is_code();
+
+          let value = item.value(self.base, &self.bytes);
+          let galley = (self.galleys.entry((addr, item.kind, value)))
+            .or_insert_with(|| {
+              let text = item.text(value);
+              ui.fonts(|f| f.layout_job(highlight(&style, &text)))
+            })
+            .clone();
 
-          let mut response = ui.label(galley);
+          let edit = self.edit.as_mut().filter(|edit| edit.addr == addr);
+          let response = ui
+            .horizontal(|ui| {
+              marker(ui, addr == pc);
+              if gutter(ui, breakpoints.at(addr)).clicked() {
+                breakpoints.toggle(addr);
+              }
+              let columns = format!("{addr:08x}  {:<8}  ", item.raw(value));
+              ui.label(RichText::new(columns).monospace().weak());
+
+              let Some(edit) = edit else {
+                let response = ui.label(galley);
+                if let Some(to) = item.target(value) {
+                  let note = symbols.annotate(to);
+                  let text = note.unwrap_or_else(|| format!("{to:#x}"));
+                  let link = ui.link(RichText::new(text).monospace());
+                  if link.on_hover_text("Go to target").clicked() {
+                    goto = Some(to);
+                  }
+                }
+                return Some(response);
+              };
+
+              let input = ui.add(
+                TextEdit::singleline(&mut edit.text)
+                  .font(TextStyle::Monospace)
+                  .hint_text(item.text(value))
+                  .margin(vec2(0.0, 0.0))
+                  .desired_width(240.0),
+              );
+              if mem::take(&mut edit.focus) {
+                input.request_focus();
+              }
+              if let Some(error) = &edit.error {
+                ui.colored_label(Color32::LIGHT_RED, error);
+              }
+              // Enter writes it, Escape, clicking away or no text drops it
+              if input.lost_focus() {
+                let enter = ui.input(|i| i.key_pressed(Key::Enter));
+                if enter && !edit.text.trim().is_empty() {
+                  match edit.encode(symbols) {
+                    Ok(bytes) => {
+                      self.patch = Some((addr, bytes));
+                      done = true;
+                    }
+                    Err(err) => {
+                      edit.error = Some(err);
+                      edit.focus = true;
+                    }
+                  }
+                } else {
+                  done = true;
+                }
+              }
+              None
+            })
+            .inner;
+          let Some(mut response) = response else { continue };
+
+          if code && response.double_clicked() {
+            edit_at = Some((addr, size));
+          }
+          let range = addr..addr + size as u64;
+          response.context_menu(|ui| {
+            if code {
+              if ui.button("Edit").clicked() {
+                edit_at = Some((addr, size));
+                ui.close_menu();
+              }
+              if ui.button("NOP out").clicked() {
+                self.patch = Some((addr, nop(size)));
+                ui.close_menu();
+              }
+            } else if ui.button("Disassemble from here").clicked() {
+              // Up to the code that follows
+              let size: usize = (self.rows[idx..].iter())
+                .filter_map(|row| match row {
+                  Row::Item(item) => Some(item),
+                  _ => None,
+                })
+                .take_while(|item| !item.is_code())
+                .map(|item| item.size)
+                .sum();
+              self.code.push(addr..addr + size as u64);
+              self.marked = true;
+              self.stale = true;
+              ui.close_menu();
+            }
+            let overlaps = |code: &Range<u64>| {
+              code.start < range.end && range.start < code.end
+            };
+            if self.code.iter().any(overlaps)
+              && ui.button("Unmark code").clicked()
+            {
+              self.code.retain(|code| !overlaps(code));
+              self.marked = true;
+              self.stale = true;
+              ui.close_menu();
+            }
+          });
 
           if response.hovered() {
             response = response.highlight();
@@ -87,11 +749,21 @@ impl Asm {
           }
 
           if response.clicked() {
-            ret = Some(pc);
+            ret = Some(addr as usize);
           }
-          pc += size;
         }
-      })
+      });
+
+      if goto.is_some() {
+        self.goto = goto;
+      }
+      if done {
+        self.edit = None;
+      }
+      if let Some((addr, size)) = edit_at {
+        let (text, error, focus) = (String::new(), None, true);
+        self.edit = Some(Edit { addr, size, text, error, focus });
+      }
     });
 
     ret
@@ -110,6 +782,209 @@ fn parse_job(ctx: &Context, asm: &[Option<Instruction>]) -> LayoutJob {
     .intersperse("\n".into())
     .collect();
 
-  let theme = CodeTheme::from_style(&ctx.style());
-  syntax_highlighting::highlight(ctx, &ctx.style(), &theme, &asm, "rs")
+  highlight(&ctx.style(), &asm)
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::repr::session::Symbol};
+
+  const BASE: u64 = 0x1000;
+  const RET: u32 = 0x00008067;
+  const NOP: u32 = 0x00000013;
+
+  fn words(code: &[u32]) -> Vec<u8> {
+    code.iter().flat_map(|word| word.to_le_bytes()).collect()
+  }
+
+  fn symbol(name: &str, addr: u64, code: bool) -> Symbol {
+    Symbol { name: name.into(), addr, size: 4, code }
+  }
+
+  /// The rows, instructions as `code` and data as they read.
+  fn listing(asm: &Asm, symbols: &Symbols) -> Vec<String> {
+    (asm.rows.iter())
+      .map(|row| match *row {
+        Row::Symbol(addr) => format!("{}:", symbols.at(addr).unwrap().name),
+        Row::Source(addr) => format!("{addr:#x} source"),
+        Row::Item(item) if item.is_code() => format!("{:#x} code", item.addr),
+        Row::Item(item) => {
+          let value = item.value(asm.base, &asm.bytes);
+          format!("{:#x} {}", item.addr, item.text(value))
+        }
+      })
+      .collect()
+  }
+
+  fn decoded(bytes: &[u8], seeds: &[u64]) -> Vec<String> {
+    let (symbols, lines) = (Symbols::default(), Lines::default());
+    let mut asm = Asm::default();
+    asm.decode(BASE, bytes, seeds, &symbols, &lines);
+    listing(&asm, &symbols)
+  }
+
+  #[test]
+  fn follows_control_flow() {
+    let bytes = words(&[
+      0x00b50663, // beq a0, a1, 12
+      0x00150513, // addi a0, a0, 1
+      RET, 0x00000513, // li a0, 0
+      0x0080006f, // j 8
+      0xdeadbeef, 0x00000297, // auipc t0, 0
+      0x01028067, // jr 16(t0)
+      0x12345678, 0xffffffff, RET,
+    ]);
+    let mut bytes = bytes;
+    bytes.extend([0x11, 0x22]);
+    assert_eq!(
+      decoded(&bytes, &[BASE]),
+      [
+        "0x1000 code",
+        "0x1004 code",
+        "0x1008 code",
+        "0x100c code",
+        "0x1010 code",
+        "0x1014 .word 0xdeadbeef",
+        "0x1018 code",
+        "0x101c code",
+        "0x1020 .word 0x12345678",
+        "0x1024 .word 0xffffffff",
+        "0x1028 code",
+        "0x102c .byte 0x11",
+        "0x102d .byte 0x22",
+      ]
+    );
+  }
+
+  #[test]
+  fn stops_at_data() {
+    let bytes = words(&[0x00150513, 0xffffffff, RET, NOP]);
+    assert_eq!(
+      decoded(&bytes, &[BASE]),
+      [
+        "0x1000 code",
+        "0x1004 .word 0xffffffff",
+        "0x1008 .word 0x00008067",
+        "0x100c .word 0x00000013",
+      ]
+    );
+    // Decoding picks up again from any other seed
+    assert_eq!(
+      decoded(&bytes, &[BASE, BASE + 8])[2..],
+      ["0x1008 code", "0x100c .word 0x00000013",]
+    );
+    // Odd seeds are not instructions
+    assert_eq!(decoded(&bytes, &[BASE + 1])[0], "0x1000 .word 0x00150513");
+  }
+
+  #[test]
+  fn resynchronizes_after_data() {
+    // `c.j 6` over a hole, to a word instruction halfway through a word
+    let mut bytes = vec![0x19, 0xa0, 0x00, 0x00, 0xff, 0xff];
+    bytes.extend(0x00150513_u32.to_le_bytes());
+    bytes.extend([0x82, 0x80]); // ret
+    assert_eq!(
+      decoded(&bytes, &[BASE]),
+      [
+        "0x1000 code",
+        "0x1002 .byte 0x00",
+        "0x1003 .byte 0x00",
+        "0x1004 .byte 0xff",
+        "0x1005 .byte 0xff",
+        "0x1006 code",
+        "0x100a code",
+      ]
+    );
+  }
+
+  #[test]
+  fn seeds_functions() {
+    let bytes = words(&[RET, RET, RET, RET]);
+    let symbols = Symbols::new(vec![
+      symbol("func", BASE + 8, true),
+      symbol("object", BASE + 12, false),
+    ]);
+    let seeds = seeds(BASE + 4, Some(BASE), &symbols);
+    assert_eq!(seeds, [BASE + 4, BASE, BASE + 8]);
+
+    let mut asm = Asm::default();
+    asm.decode(BASE, &bytes, &seeds, &symbols, &Lines::default());
+    assert_eq!(
+      listing(&asm, &symbols),
+      [
+        "0x1000 code",
+        "0x1004 code",
+        "func:",
+        "0x1008 code",
+        "object:",
+        "0x100c .word 0x00008067",
+      ]
+    );
+    assert!(!asm.unreached(BASE + 8));
+    assert!(asm.unreached(BASE + 12));
+  }
+
+  #[test]
+  fn marked_code_goes_past_jumps() {
+    let bytes = words(&[RET, 0xffffffff, RET, 0]);
+    let mut asm = Asm::default();
+    asm.code.push(BASE..BASE + 8);
+    let (symbols, lines) = (Symbols::default(), Lines::default());
+    asm.decode(BASE, &bytes, &[], &symbols, &lines);
+    assert_eq!(
+      listing(&asm, &symbols),
+      ["0x1000 code", "0x1004 code", "0x1008 code", "0x100c .word 0x00000000",]
+    );
+  }
+
+  #[test]
+  fn dirty_ranges() {
+    let old = vec![0; 3 * 4096];
+    let mut new = old.clone();
+    assert_eq!(dirty(&old, &new), None);
+    new[5] = 1;
+    assert_eq!(dirty(&old, &new), Some(5..6));
+    new[4095] = 1;
+    new[3 * 4096 - 1] = 1;
+    assert_eq!(dirty(&old, &new), Some(5..3 * 4096));
+  }
+
+  /// Patches decode the same as the memory decoded afresh.
+  #[test]
+  fn redecodes_changes() {
+    let (symbols, lines) = (Symbols::default(), Lines::default());
+    // Code on every page, jumping over data in between
+    let mut code = vec![0; 3 * 1024];
+    for page in 0..3 {
+      code[page * 1024..page * 1024 + 4].copy_from_slice(&[
+        0x00150513, // addi a0, a0, 1
+        0x0080006f, // j 8
+        0xffffffff, NOP,
+      ]);
+    }
+    code[1023] = 0x0040006f; // j 4, into the next page
+    code[3 * 1024 - 1] = RET;
+    let mut bytes = words(&code);
+    let seeds = [BASE, BASE + 4096, BASE + 2 * 4096, BASE + 3 * 4096 - 4];
+
+    let mut asm = Asm::default();
+    asm.decode(BASE, &bytes, &seeds, &symbols, &lines);
+    let patches = [
+      // Data into code, going on from the instruction before
+      (8, NOP),
+      // Code into data, and back
+      (4096 + 4, 0xffffffff),
+      (4096 + 4, 0x0080006f),
+      // The last word, and the first
+      (3 * 4096 - 4, NOP),
+      (0, RET),
+      // A word ahead of a page jumping to the next
+      (4092, 0x00000297),
+    ];
+    for (off, word) in patches {
+      bytes[off..off + 4].copy_from_slice(&word.to_le_bytes());
+      asm.decode(BASE, &bytes, &seeds, &symbols, &lines);
+      assert_eq!(listing(&asm, &symbols), decoded(&bytes, &seeds), "{off:#x}");
+    }
+  }
 }
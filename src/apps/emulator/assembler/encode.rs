@@ -0,0 +1,792 @@
+use super::{
+  AsmError,
+  parse::{self, Env},
+};
+
+const LOAD: u32 = 0x03;
+const LOAD_FP: u32 = 0x07;
+const MISC_MEM: u32 = 0x0f;
+const OP_IMM: u32 = 0x13;
+const AUIPC: u32 = 0x17;
+const OP_IMM_32: u32 = 0x1b;
+const STORE: u32 = 0x23;
+const STORE_FP: u32 = 0x27;
+const AMO: u32 = 0x2f;
+const OP: u32 = 0x33;
+const LUI: u32 = 0x37;
+const OP_32: u32 = 0x3b;
+const MADD: u32 = 0x43;
+const MSUB: u32 = 0x47;
+const NMSUB: u32 = 0x4b;
+const NMADD: u32 = 0x4f;
+const OP_FP: u32 = 0x53;
+const BRANCH: u32 = 0x63;
+const JALR: u32 = 0x67;
+const JAL: u32 = 0x6f;
+const SYSTEM: u32 = 0x73;
+
+/// Dynamic rounding, the default of most float operations.
+const DYN: u32 = 7 << 12;
+
+const fn i(op: u32, f3: u32) -> u32 {
+  op | f3 << 12
+}
+
+const fn r(op: u32, f3: u32, f7: u32) -> u32 {
+  op | f3 << 12 | f7 << 25
+}
+
+/// Float operation with `f5` and the format, single 0 or double 1.
+const fn fp(f5: u32, fmt: u32, rs2: u32) -> u32 {
+  OP_FP | (f5 << 2 | fmt) << 25 | rs2 << 20
+}
+
+const fn amo(f5: u32, f3: u32) -> u32 {
+  AMO | f3 << 12 | f5 << 27
+}
+
+const fn csr(f3: u32, csr: u32) -> u32 {
+  SYSTEM | f3 << 12 | csr << 20
+}
+
+/// Instructions as fixed bits and the operands that fill in the rest, in
+/// the manner of binutils' opcode table:
+///
+/// - `d` `s` `t` integer `rd` `rs1` `rs2`, `D` `S` `T` `R` float `rd` `rs1`
+///   `rs2` `rs3`, `U` a float as both `rs1` and `rs2`
+/// - `j` 12-bit immediate, `u` upper 20 bits, `>` `<` 6 and 5-bit shifts
+/// - `o(s)` load and `q(s)` store addresses, `(s)` an atomic's address
+/// - `a` jump and `p` branch targets
+/// - `E` a CSR, `Z` its 5-bit immediate, `P` `Q` fence sets, `m` optional
+///   rounding mode
+///
+/// A name may appear more than once, the first form that fits is used.
+#[rustfmt::skip]
+const OPCODES: &[(&str, u32, &str)] = &[
+  // RV64I
+  ("lui", LUI, "d,u"),
+  ("auipc", AUIPC, "d,u"),
+  ("jal", JAL, "d,a"),
+  ("jal", JAL | 1 << 7, "a"),
+  ("jalr", JALR, "d,o(s)"),
+  ("jalr", JALR, "d,s,j"),
+  ("jalr", JALR, "d,s"),
+  ("jalr", JALR | 1 << 7, "s"),
+  ("beq", i(BRANCH, 0), "s,t,p"),
+  ("bne", i(BRANCH, 1), "s,t,p"),
+  ("blt", i(BRANCH, 4), "s,t,p"),
+  ("bge", i(BRANCH, 5), "s,t,p"),
+  ("bltu", i(BRANCH, 6), "s,t,p"),
+  ("bgeu", i(BRANCH, 7), "s,t,p"),
+  ("lb", i(LOAD, 0), "d,o(s)"),
+  ("lh", i(LOAD, 1), "d,o(s)"),
+  ("lw", i(LOAD, 2), "d,o(s)"),
+  ("ld", i(LOAD, 3), "d,o(s)"),
+  ("lbu", i(LOAD, 4), "d,o(s)"),
+  ("lhu", i(LOAD, 5), "d,o(s)"),
+  ("lwu", i(LOAD, 6), "d,o(s)"),
+  ("sb", i(STORE, 0), "t,q(s)"),
+  ("sh", i(STORE, 1), "t,q(s)"),
+  ("sw", i(STORE, 2), "t,q(s)"),
+  ("sd", i(STORE, 3), "t,q(s)"),
+  ("addi", i(OP_IMM, 0), "d,s,j"),
+  ("slti", i(OP_IMM, 2), "d,s,j"),
+  ("sltiu", i(OP_IMM, 3), "d,s,j"),
+  ("xori", i(OP_IMM, 4), "d,s,j"),
+  ("ori", i(OP_IMM, 6), "d,s,j"),
+  ("andi", i(OP_IMM, 7), "d,s,j"),
+  ("slli", i(OP_IMM, 1), "d,s,>"),
+  ("srli", i(OP_IMM, 5), "d,s,>"),
+  ("srai", r(OP_IMM, 5, 0x20), "d,s,>"),
+  ("add", r(OP, 0, 0), "d,s,t"),
+  ("sub", r(OP, 0, 0x20), "d,s,t"),
+  ("sll", r(OP, 1, 0), "d,s,t"),
+  ("slt", r(OP, 2, 0), "d,s,t"),
+  ("sltu", r(OP, 3, 0), "d,s,t"),
+  ("xor", r(OP, 4, 0), "d,s,t"),
+  ("srl", r(OP, 5, 0), "d,s,t"),
+  ("sra", r(OP, 5, 0x20), "d,s,t"),
+  ("or", r(OP, 6, 0), "d,s,t"),
+  ("and", r(OP, 7, 0), "d,s,t"),
+  ("addiw", i(OP_IMM_32, 0), "d,s,j"),
+  ("slliw", i(OP_IMM_32, 1), "d,s,<"),
+  ("srliw", i(OP_IMM_32, 5), "d,s,<"),
+  ("sraiw", r(OP_IMM_32, 5, 0x20), "d,s,<"),
+  ("addw", r(OP_32, 0, 0), "d,s,t"),
+  ("subw", r(OP_32, 0, 0x20), "d,s,t"),
+  ("sllw", r(OP_32, 1, 0), "d,s,t"),
+  ("srlw", r(OP_32, 5, 0), "d,s,t"),
+  ("sraw", r(OP_32, 5, 0x20), "d,s,t"),
+  ("fence", MISC_MEM | 0xff << 20, ""),
+  ("fence", MISC_MEM, "P,Q"),
+  ("fence.tso", MISC_MEM | 0x833 << 20, ""),
+  ("fence.i", i(MISC_MEM, 1), ""),
+  ("ecall", SYSTEM, ""),
+  ("ebreak", SYSTEM | 1 << 20, ""),
+  ("sret", SYSTEM | 0x102 << 20, ""),
+  ("mret", SYSTEM | 0x302 << 20, ""),
+  ("wfi", SYSTEM | 0x105 << 20, ""),
+  ("sfence.vma", r(SYSTEM, 0, 0x09), ""),
+  ("sfence.vma", r(SYSTEM, 0, 0x09), "s"),
+  ("sfence.vma", r(SYSTEM, 0, 0x09), "s,t"),
+  // Zicsr
+  ("csrrw", csr(1, 0), "d,E,s"),
+  ("csrrs", csr(2, 0), "d,E,s"),
+  ("csrrc", csr(3, 0), "d,E,s"),
+  ("csrrwi", csr(5, 0), "d,E,Z"),
+  ("csrrsi", csr(6, 0), "d,E,Z"),
+  ("csrrci", csr(7, 0), "d,E,Z"),
+  // RV64M
+  ("mul", r(OP, 0, 1), "d,s,t"),
+  ("mulh", r(OP, 1, 1), "d,s,t"),
+  ("mulhsu", r(OP, 2, 1), "d,s,t"),
+  ("mulhu", r(OP, 3, 1), "d,s,t"),
+  ("div", r(OP, 4, 1), "d,s,t"),
+  ("divu", r(OP, 5, 1), "d,s,t"),
+  ("rem", r(OP, 6, 1), "d,s,t"),
+  ("remu", r(OP, 7, 1), "d,s,t"),
+  ("mulw", r(OP_32, 0, 1), "d,s,t"),
+  ("divw", r(OP_32, 4, 1), "d,s,t"),
+  ("divuw", r(OP_32, 5, 1), "d,s,t"),
+  ("remw", r(OP_32, 6, 1), "d,s,t"),
+  ("remuw", r(OP_32, 7, 1), "d,s,t"),
+  // RV64A, `.aq` and `.rl` are added by `atomic`
+  ("lr.w", amo(0x02, 2), "d,(s)"),
+  ("sc.w", amo(0x03, 2), "d,t,(s)"),
+  ("amoswap.w", amo(0x01, 2), "d,t,(s)"),
+  ("amoadd.w", amo(0x00, 2), "d,t,(s)"),
+  ("amoxor.w", amo(0x04, 2), "d,t,(s)"),
+  ("amoand.w", amo(0x0c, 2), "d,t,(s)"),
+  ("amoor.w", amo(0x08, 2), "d,t,(s)"),
+  ("amomin.w", amo(0x10, 2), "d,t,(s)"),
+  ("amomax.w", amo(0x14, 2), "d,t,(s)"),
+  ("amominu.w", amo(0x18, 2), "d,t,(s)"),
+  ("amomaxu.w", amo(0x1c, 2), "d,t,(s)"),
+  ("lr.d", amo(0x02, 3), "d,(s)"),
+  ("sc.d", amo(0x03, 3), "d,t,(s)"),
+  ("amoswap.d", amo(0x01, 3), "d,t,(s)"),
+  ("amoadd.d", amo(0x00, 3), "d,t,(s)"),
+  ("amoxor.d", amo(0x04, 3), "d,t,(s)"),
+  ("amoand.d", amo(0x0c, 3), "d,t,(s)"),
+  ("amoor.d", amo(0x08, 3), "d,t,(s)"),
+  ("amomin.d", amo(0x10, 3), "d,t,(s)"),
+  ("amomax.d", amo(0x14, 3), "d,t,(s)"),
+  ("amominu.d", amo(0x18, 3), "d,t,(s)"),
+  ("amomaxu.d", amo(0x1c, 3), "d,t,(s)"),
+  // RV64F
+  ("flw", i(LOAD_FP, 2), "D,o(s)"),
+  ("fsw", i(STORE_FP, 2), "T,q(s)"),
+  ("fmadd.s", MADD | DYN, "D,S,T,R,m"),
+  ("fmsub.s", MSUB | DYN, "D,S,T,R,m"),
+  ("fnmsub.s", NMSUB | DYN, "D,S,T,R,m"),
+  ("fnmadd.s", NMADD | DYN, "D,S,T,R,m"),
+  ("fadd.s", fp(0x00, 0, 0) | DYN, "D,S,T,m"),
+  ("fsub.s", fp(0x01, 0, 0) | DYN, "D,S,T,m"),
+  ("fmul.s", fp(0x02, 0, 0) | DYN, "D,S,T,m"),
+  ("fdiv.s", fp(0x03, 0, 0) | DYN, "D,S,T,m"),
+  ("fsqrt.s", fp(0x0b, 0, 0) | DYN, "D,S,m"),
+  ("fsgnj.s", fp(0x04, 0, 0), "D,S,T"),
+  ("fsgnjn.s", fp(0x04, 0, 0) | 1 << 12, "D,S,T"),
+  ("fsgnjx.s", fp(0x04, 0, 0) | 2 << 12, "D,S,T"),
+  ("fmin.s", fp(0x05, 0, 0), "D,S,T"),
+  ("fmax.s", fp(0x05, 0, 0) | 1 << 12, "D,S,T"),
+  ("fcvt.w.s", fp(0x18, 0, 0) | DYN, "d,S,m"),
+  ("fcvt.wu.s", fp(0x18, 0, 1) | DYN, "d,S,m"),
+  ("fcvt.l.s", fp(0x18, 0, 2) | DYN, "d,S,m"),
+  ("fcvt.lu.s", fp(0x18, 0, 3) | DYN, "d,S,m"),
+  ("fcvt.s.w", fp(0x1a, 0, 0) | DYN, "D,s,m"),
+  ("fcvt.s.wu", fp(0x1a, 0, 1) | DYN, "D,s,m"),
+  ("fcvt.s.l", fp(0x1a, 0, 2) | DYN, "D,s,m"),
+  ("fcvt.s.lu", fp(0x1a, 0, 3) | DYN, "D,s,m"),
+  ("fmv.x.w", fp(0x1c, 0, 0), "d,S"),
+  ("fclass.s", fp(0x1c, 0, 0) | 1 << 12, "d,S"),
+  ("fmv.w.x", fp(0x1e, 0, 0), "D,s"),
+  ("feq.s", fp(0x14, 0, 0) | 2 << 12, "d,S,T"),
+  ("flt.s", fp(0x14, 0, 0) | 1 << 12, "d,S,T"),
+  ("fle.s", fp(0x14, 0, 0), "d,S,T"),
+  // RV64D
+  ("fld", i(LOAD_FP, 3), "D,o(s)"),
+  ("fsd", i(STORE_FP, 3), "T,q(s)"),
+  ("fmadd.d", MADD | 1 << 25 | DYN, "D,S,T,R,m"),
+  ("fmsub.d", MSUB | 1 << 25 | DYN, "D,S,T,R,m"),
+  ("fnmsub.d", NMSUB | 1 << 25 | DYN, "D,S,T,R,m"),
+  ("fnmadd.d", NMADD | 1 << 25 | DYN, "D,S,T,R,m"),
+  ("fadd.d", fp(0x00, 1, 0) | DYN, "D,S,T,m"),
+  ("fsub.d", fp(0x01, 1, 0) | DYN, "D,S,T,m"),
+  ("fmul.d", fp(0x02, 1, 0) | DYN, "D,S,T,m"),
+  ("fdiv.d", fp(0x03, 1, 0) | DYN, "D,S,T,m"),
+  ("fsqrt.d", fp(0x0b, 1, 0) | DYN, "D,S,m"),
+  ("fsgnj.d", fp(0x04, 1, 0), "D,S,T"),
+  ("fsgnjn.d", fp(0x04, 1, 0) | 1 << 12, "D,S,T"),
+  ("fsgnjx.d", fp(0x04, 1, 0) | 2 << 12, "D,S,T"),
+  ("fmin.d", fp(0x05, 1, 0), "D,S,T"),
+  ("fmax.d", fp(0x05, 1, 0) | 1 << 12, "D,S,T"),
+  ("fcvt.s.d", fp(0x08, 0, 1) | DYN, "D,S,m"),
+  // Exact conversions round nowhere, so default to `rne` like binutils
+  ("fcvt.d.s", fp(0x08, 1, 0), "D,S,m"),
+  ("fcvt.w.d", fp(0x18, 1, 0) | DYN, "d,S,m"),
+  ("fcvt.wu.d", fp(0x18, 1, 1) | DYN, "d,S,m"),
+  ("fcvt.l.d", fp(0x18, 1, 2) | DYN, "d,S,m"),
+  ("fcvt.lu.d", fp(0x18, 1, 3) | DYN, "d,S,m"),
+  ("fcvt.d.w", fp(0x1a, 1, 0), "D,s,m"),
+  ("fcvt.d.wu", fp(0x1a, 1, 1), "D,s,m"),
+  ("fcvt.d.l", fp(0x1a, 1, 2) | DYN, "D,s,m"),
+  ("fcvt.d.lu", fp(0x1a, 1, 3) | DYN, "D,s,m"),
+  ("fmv.x.d", fp(0x1c, 1, 0), "d,S"),
+  ("fclass.d", fp(0x1c, 1, 0) | 1 << 12, "d,S"),
+  ("fmv.d.x", fp(0x1e, 1, 0), "D,s"),
+  ("feq.d", fp(0x14, 1, 0) | 2 << 12, "d,S,T"),
+  ("flt.d", fp(0x14, 1, 0) | 1 << 12, "d,S,T"),
+  ("fle.d", fp(0x14, 1, 0), "d,S,T"),
+  // Pseudo-instructions
+  ("nop", OP_IMM, ""),
+  ("mv", OP_IMM, "d,s"),
+  ("not", i(OP_IMM, 4) | 0xfff << 20, "d,s"),
+  ("neg", r(OP, 0, 0x20), "d,t"),
+  ("negw", r(OP_32, 0, 0x20), "d,t"),
+  ("sext.w", OP_IMM_32, "d,s"),
+  ("seqz", i(OP_IMM, 3) | 1 << 20, "d,s"),
+  ("snez", r(OP, 3, 0), "d,t"),
+  ("sltz", r(OP, 2, 0), "d,s"),
+  ("sgtz", r(OP, 2, 0), "d,t"),
+  ("j", JAL, "a"),
+  ("jr", JALR, "s"),
+  ("jr", JALR, "o(s)"),
+  ("ret", JALR | 1 << 15, ""),
+  ("beqz", i(BRANCH, 0), "s,p"),
+  ("bnez", i(BRANCH, 1), "s,p"),
+  ("blez", i(BRANCH, 5), "t,p"),
+  ("bgez", i(BRANCH, 5), "s,p"),
+  ("bltz", i(BRANCH, 4), "s,p"),
+  ("bgtz", i(BRANCH, 4), "t,p"),
+  ("bgt", i(BRANCH, 4), "t,s,p"),
+  ("ble", i(BRANCH, 5), "t,s,p"),
+  ("bgtu", i(BRANCH, 6), "t,s,p"),
+  ("bleu", i(BRANCH, 7), "t,s,p"),
+  ("csrr", csr(2, 0), "d,E"),
+  ("csrw", csr(1, 0), "E,s"),
+  ("csrs", csr(2, 0), "E,s"),
+  ("csrc", csr(3, 0), "E,s"),
+  ("csrwi", csr(5, 0), "E,Z"),
+  ("csrsi", csr(6, 0), "E,Z"),
+  ("csrci", csr(7, 0), "E,Z"),
+  ("rdcycle", csr(2, 0xc00), "d"),
+  ("rdtime", csr(2, 0xc01), "d"),
+  ("rdinstret", csr(2, 0xc02), "d"),
+  ("frcsr", csr(2, 0x003), "d"),
+  ("fscsr", csr(1, 0x003), "d,s"),
+  ("fscsr", csr(1, 0x003), "s"),
+  ("frrm", csr(2, 0x002), "d"),
+  ("fsrm", csr(1, 0x002), "d,s"),
+  ("fsrm", csr(1, 0x002), "s"),
+  ("frflags", csr(2, 0x001), "d"),
+  ("fsflags", csr(1, 0x001), "d,s"),
+  ("fsflags", csr(1, 0x001), "s"),
+  ("fmv.s", fp(0x04, 0, 0), "D,U"),
+  ("fneg.s", fp(0x04, 0, 0) | 1 << 12, "D,U"),
+  ("fabs.s", fp(0x04, 0, 0) | 2 << 12, "D,U"),
+  ("fmv.d", fp(0x04, 1, 0), "D,U"),
+  ("fneg.d", fp(0x04, 1, 0) | 1 << 12, "D,U"),
+  ("fabs.d", fp(0x04, 1, 0) | 2 << 12, "D,U"),
+];
+
+/// Immediate of `bits` bits, sign-extended unless `unsigned`.
+fn imm(val: i64, bits: u32, what: &'static str) -> Result<u32, AsmError> {
+  let (min, max) = (-(1 << (bits - 1)), (1 << (bits - 1)) - 1);
+  if (min..=max).contains(&val) {
+    Ok(val as u32 & ((1 << bits) - 1))
+  } else {
+    Err(AsmError::Range(val, what))
+  }
+}
+
+fn uimm(val: i64, bits: u32, what: &'static str) -> Result<u32, AsmError> {
+  if (0..1 << bits).contains(&val) {
+    Ok(val as u32)
+  } else {
+    Err(AsmError::Range(val, what))
+  }
+}
+
+/// Offset from the instruction to `target`, checked to be even.
+fn relative(text: &str, env: &Env) -> Result<i64, AsmError> {
+  let pc = env.pc.ok_or_else(|| AsmError::Constant(".".into()))?;
+  let offset = parse::eval(text, env)?.wrapping_sub(pc as i64);
+  if offset % 2 != 0 {
+    return Err(AsmError::Misaligned(offset, 2));
+  }
+  Ok(offset)
+}
+
+fn i_imm(val: i64) -> Result<u32, AsmError> {
+  Ok(imm(val, 12, "12 bits")? << 20)
+}
+
+fn s_imm(val: i64) -> Result<u32, AsmError> {
+  let val = imm(val, 12, "12 bits")?;
+  Ok((val >> 5) << 25 | (val & 0x1f) << 7)
+}
+
+fn b_imm(val: i64) -> Result<u32, AsmError> {
+  let val = imm(val, 13, "a branch's ±4 KiB")?;
+  Ok(
+    (val >> 12 & 1) << 31
+      | (val >> 5 & 0x3f) << 25
+      | (val >> 1 & 0xf) << 8
+      | (val >> 11 & 1) << 7,
+  )
+}
+
+fn j_imm(val: i64) -> Result<u32, AsmError> {
+  let val = imm(val, 21, "a jump's ±1 MiB")?;
+  Ok(
+    (val >> 20 & 1) << 31
+      | (val >> 1 & 0x3ff) << 21
+      | (val >> 11 & 1) << 20
+      | (val >> 12 & 0xff) << 12,
+  )
+}
+
+/// Upper immediate, either as the 20 bits or sign-extended.
+fn u_imm(val: i64) -> Result<u32, AsmError> {
+  if (-(1 << 19)..1 << 20).contains(&val) {
+    Ok((val as u32 & 0xfffff) << 12)
+  } else {
+    Err(AsmError::Range(val, "20 bits"))
+  }
+}
+
+/// Fill the operand `text` in per the pattern `kind`.
+fn operand(kind: &str, text: &str, env: &Env) -> Result<u32, AsmError> {
+  let eval = |text: &str| parse::eval(text, env);
+  let offset = |text: &str| if text.is_empty() { Ok(0) } else { eval(text) };
+  Ok(match kind {
+    "d" => parse::xreg(text)? << 7,
+    "s" => parse::xreg(text)? << 15,
+    "t" => parse::xreg(text)? << 20,
+    "D" => parse::freg(text)? << 7,
+    "S" => parse::freg(text)? << 15,
+    "T" => parse::freg(text)? << 20,
+    "R" => parse::freg(text)? << 27,
+    "U" => parse::freg(text)? * (1 << 15 | 1 << 20),
+    "j" => i_imm(eval(text)?)?,
+    "u" => u_imm(eval(text)?)?,
+    ">" => uimm(eval(text)?, 6, "a shift of 0..63")? << 20,
+    "<" => uimm(eval(text)?, 5, "a shift of 0..31")? << 20,
+    "o(s)" => {
+      let (off, rs1) = parse::mem(text)?;
+      i_imm(offset(off)?)? | rs1 << 15
+    }
+    "q(s)" => {
+      let (off, rs1) = parse::mem(text)?;
+      s_imm(offset(off)?)? | rs1 << 15
+    }
+    "(s)" => {
+      let (off, rs1) = parse::mem(text)?;
+      match offset(off)? {
+        0 => rs1 << 15,
+        off => return Err(AsmError::Range(off, "an atomic's zero offset")),
+      }
+    }
+    "a" => j_imm(relative(text, env)?)?,
+    "p" => b_imm(relative(text, env)?)?,
+    "E" => parse::csr(text, env)? << 20,
+    "Z" => uimm(eval(text)?, 5, "5 bits")? << 15,
+    "m" => parse::rounding(text)? << 12,
+    "P" => parse::fence_set(text)? << 24,
+    "Q" => parse::fence_set(text)? << 20,
+    _ => unreachable!("operand kind `{kind}`"),
+  })
+}
+
+/// Bits of `lr`/`sc`/`amo*` with an ordering suffix, and the base name.
+fn atomic(name: &str) -> (&str, u32) {
+  if name.starts_with("lr.")
+    || name.starts_with("sc.")
+    || name.starts_with("amo")
+  {
+    for (suffix, bits) in
+      [(".aqrl", 3 << 25), (".aq", 1 << 26), (".rl", 1 << 25)]
+    {
+      if let Some(base) = name.strip_suffix(suffix) {
+        return (base, bits);
+      }
+    }
+  }
+  (name, 0)
+}
+
+/// Encode with the first form of `name` in [`OPCODES`] that fits.
+fn base(name: &str, ops: &[&str], env: &Env) -> Result<u32, AsmError> {
+  let (name, order) = atomic(name);
+  let mut forms = OPCODES.iter().filter(|(op, ..)| *op == name).peekable();
+  if forms.peek().is_none() {
+    return Err(AsmError::Mnemonic(name.to_string()));
+  }
+
+  let (mut first, mut counts) = (None, vec![]);
+  for &(_, bits, pattern) in forms {
+    let kinds: Vec<_> = pattern.split(',').filter(|k| !k.is_empty()).collect();
+    // The rounding mode may be left out
+    let optional = kinds.last() == Some(&"m");
+    let fits =
+      ops.len() == kinds.len() || optional && ops.len() + 1 == kinds.len();
+    counts.extend(optional.then(|| kinds.len() - 1));
+    counts.push(kinds.len());
+    if !fits {
+      continue;
+    }
+    let mut inst: Result<u32, AsmError> = Ok(bits | order);
+    for (kind, text) in kinds.iter().zip(ops) {
+      // A given rounding mode replaces the default one
+      let mask = if *kind == "m" { !(7 << 12) } else { !0 };
+      inst = inst.and_then(|inst| Ok(inst & mask | operand(kind, text, env)?));
+    }
+    match inst {
+      Ok(inst) => return Ok(inst),
+      Err(err) => first = first.or(Some(err)),
+    }
+  }
+
+  Err(first.unwrap_or_else(|| {
+    counts.sort();
+    counts.dedup();
+    let counts: Vec<_> = counts.iter().map(ToString::to_string).collect();
+    let counts = counts.join(" or ");
+    AsmError::Operands(name.to_string(), format!("{counts} operands"))
+  }))
+}
+
+fn expect(name: &str, ops: &[&str], want: usize) -> Result<(), AsmError> {
+  if ops.len() == want {
+    Ok(())
+  } else {
+    Err(AsmError::Operands(name.to_string(), format!("{want} operands")))
+  }
+}
+
+/// Instructions that build `val` in `rd`, as `lui`/`addiw` for 32-bit
+/// values and shifted in 12 bits at a time beyond.
+fn li(rd: u32, val: i64, out: &mut Vec<u32>) {
+  let lo = val << 52 >> 52;
+  if val == val as i32 as i64 {
+    let hi = (val.wrapping_add(0x800) >> 12) as u32 & 0xfffff;
+    if hi != 0 {
+      out.push(LUI | rd << 7 | hi << 12);
+      if lo != 0 {
+        out.push(OP_IMM_32 | rd << 7 | rd << 15 | (lo as u32) << 20);
+      }
+    } else {
+      out.push(OP_IMM | rd << 7 | (lo as u32) << 20);
+    }
+    return;
+  }
+  let hi = val.wrapping_sub(lo) >> 12;
+  let zeros = hi.trailing_zeros();
+  li(rd, hi >> zeros, out);
+  out.push(i(OP_IMM, 1) | rd << 7 | rd << 15 | (12 + zeros) << 20);
+  if lo != 0 {
+    out.push(OP_IMM | rd << 7 | rd << 15 | (lo as u32) << 20);
+  }
+}
+
+/// `auipc` and the instruction after it that add up to `target`.
+fn pcrel(text: &str, env: &Env) -> Result<(u32, u32), AsmError> {
+  let offset = relative(text, env)?;
+  if offset != offset as i32 as i64 {
+    return Err(AsmError::Range(offset, "±2 GiB of the instruction"));
+  }
+  let hi = (offset.wrapping_add(0x800) >> 12) as u32 & 0xfffff;
+  let lo = (offset << 52 >> 52) as u32 & 0xfff;
+  Ok((AUIPC | hi << 12, lo << 20))
+}
+
+fn li_words(ops: &[&str], env: &Env) -> Result<Vec<u32>, AsmError> {
+  expect("li", ops, 2)?;
+  let rd = parse::xreg(ops[0])?;
+  let mut out = vec![];
+  li(rd, parse::eval(ops[1], env)?, &mut out);
+  Ok(out)
+}
+
+/// Bytes `name` takes, ahead of knowing where labels are.
+pub fn size(name: &str, ops: &[&str], env: &Env) -> Result<u64, AsmError> {
+  Ok(match name {
+    _ if name.starts_with("c.") => 2,
+    "li" => 4 * li_words(ops, env)?.len() as u64,
+    "la" | "lla" | "call" | "tail" => 8,
+    _ if OPCODES.iter().any(|&(op, ..)| op == atomic(name).0) => 4,
+    _ => return Err(AsmError::Mnemonic(name.to_string())),
+  })
+}
+
+/// Machine code of the instruction `name` with operands `ops`.
+pub fn encode(
+  name: &str,
+  ops: &[&str],
+  env: &Env,
+) -> Result<Vec<u8>, AsmError> {
+  let words = match name {
+    _ if name.starts_with("c.") => {
+      return Ok(compressed(name, ops, env)?.to_le_bytes().to_vec());
+    }
+    "li" => li_words(ops, env)?,
+    "la" | "lla" => {
+      expect(name, ops, 2)?;
+      let rd = parse::xreg(ops[0])?;
+      let (auipc, lo) = pcrel(ops[1], env)?;
+      vec![auipc | rd << 7, OP_IMM | rd << 7 | rd << 15 | lo]
+    }
+    "call" | "tail" => {
+      expect(name, ops, 1)?;
+      // `call` links through `ra`, `tail` clobbers `t1` without linking
+      let (tmp, link) = if name == "call" { (1, 1) } else { (6, 0) };
+      let (auipc, lo) = pcrel(ops[0], env)?;
+      vec![auipc | tmp << 7, JALR | link << 7 | tmp << 15 | lo]
+    }
+    _ => vec![base(name, ops, env)?],
+  };
+  Ok(words.iter().flat_map(|word| word.to_le_bytes()).collect())
+}
+
+/// One of `x8..x15`, which 3-bit register fields name.
+fn creg(text: &str, reg: u32) -> Result<u32, AsmError> {
+  match reg {
+    8..16 => Ok(reg - 8),
+    _ => Err(AsmError::Compressed(text.trim().to_string())),
+  }
+}
+
+/// Bits `hi..=lo` of `val`, moved down to bit 0.
+fn bits(val: u32, hi: u32, lo: u32) -> u32 {
+  val >> lo & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Encode an explicit RVC instruction.
+fn compressed(name: &str, ops: &[&str], env: &Env) -> Result<u16, AsmError> {
+  let eval = |text: &str| parse::eval(text, env);
+  let x = |idx: usize| parse::xreg(ops[idx]);
+  let f = |idx: usize| parse::freg(ops[idx]);
+  let xc = |idx: usize| creg(ops[idx], parse::xreg(ops[idx])?);
+  let fc = |idx: usize| creg(ops[idx], parse::freg(ops[idx])?);
+  let nonzero = |val: i64| {
+    if val != 0 { Ok(val) } else { Err(AsmError::Range(0, "a nonzero value")) }
+  };
+  // Offset of a load or store scaled by `scale`, and its base register
+  let scaled = |idx: usize, scale: u64, bits: u32| {
+    let (off, rs1) = parse::mem(ops[idx])?;
+    let off = if off.is_empty() { 0 } else { eval(off)? };
+    if off % scale as i64 != 0 {
+      return Err(AsmError::Misaligned(off, scale));
+    }
+    Ok((uimm(off, bits, "the compressed offset")?, rs1))
+  };
+  let sp = |idx: usize, rs1: u32| match rs1 {
+    2 => Ok(()),
+    _ => Err(AsmError::Register(format!("{}, expected sp", ops[idx]))),
+  };
+
+  let count = match name {
+    "c.nop" | "c.ebreak" => 0,
+    "c.j" | "c.jr" | "c.jalr" => 1,
+    _ => 2,
+  };
+  let count = match name {
+    "c.addi4spn" => 3,
+    _ => count,
+  };
+  expect(name, ops, count)?;
+
+  let inst = match name {
+    "c.nop" => 0x0001,
+    "c.ebreak" => 0x9002,
+    "c.addi4spn" => {
+      sp(1, x(1)?)?;
+      let val = nonzero(eval(ops[2])?)?;
+      if val % 4 != 0 {
+        return Err(AsmError::Misaligned(val, 4));
+      }
+      let val = uimm(val, 10, "10 bits")?;
+      bits(val, 5, 4) << 11
+        | bits(val, 9, 6) << 7
+        | bits(val, 2, 2) << 6
+        | bits(val, 3, 3) << 5
+        | xc(0)? << 2
+    }
+    "c.fld" | "c.ld" | "c.fsd" | "c.sd" => {
+      let (off, rs1) = scaled(1, 8, 8)?;
+      let reg = if name.starts_with("c.f") { fc(0)? } else { xc(0)? };
+      let f3 = match name {
+        "c.fld" => 1,
+        "c.ld" => 3,
+        "c.fsd" => 5,
+        _ => 7,
+      };
+      f3 << 13
+        | bits(off, 5, 3) << 10
+        | creg(ops[1], rs1)? << 7
+        | bits(off, 7, 6) << 5
+        | reg << 2
+    }
+    "c.lw" | "c.sw" => {
+      let (off, rs1) = scaled(1, 4, 7)?;
+      let f3 = if name == "c.lw" { 2 } else { 6 };
+      f3 << 13
+        | bits(off, 5, 3) << 10
+        | creg(ops[1], rs1)? << 7
+        | bits(off, 2, 2) << 6
+        | bits(off, 6, 6) << 5
+        | xc(0)? << 2
+    }
+    "c.addi" | "c.addiw" | "c.li" => {
+      let rd = x(0)?;
+      if rd == 0 && name != "c.addi" {
+        return Err(AsmError::Register(ops[0].trim().to_string()));
+      }
+      let val = imm(eval(ops[1])?, 6, "6 bits")?;
+      let f3 = match name {
+        "c.addi" => 0,
+        "c.addiw" => 1,
+        _ => 2,
+      };
+      1 | f3 << 13 | bits(val, 5, 5) << 12 | rd << 7 | bits(val, 4, 0) << 2
+    }
+    "c.addi16sp" => {
+      sp(0, x(0)?)?;
+      let val = nonzero(eval(ops[1])?)?;
+      if val % 16 != 0 {
+        return Err(AsmError::Misaligned(val, 16));
+      }
+      let val = imm(val, 10, "±512")?;
+      1 | 3 << 13
+        | bits(val, 9, 9) << 12
+        | 2 << 7
+        | bits(val, 4, 4) << 6
+        | bits(val, 6, 6) << 5
+        | bits(val, 8, 7) << 3
+        | bits(val, 5, 5) << 2
+    }
+    "c.lui" => {
+      let rd = x(0)?;
+      if rd == 0 || rd == 2 {
+        return Err(AsmError::Register(ops[0].trim().to_string()));
+      }
+      // The upper 20 bits, of which the low 6 sign-extend
+      let val = nonzero(eval(ops[1])?)?;
+      let val = match val {
+        1..0x20 | 0xfffe0..0x100000 => val as u32 & 0x3f,
+        _ => return Err(AsmError::Range(val, "6 sign-extended upper bits")),
+      };
+      1 | 3 << 13 | bits(val, 5, 5) << 12 | rd << 7 | bits(val, 4, 0) << 2
+    }
+    "c.srli" | "c.srai" | "c.andi" => {
+      let val = eval(ops[1])?;
+      let (funct2, val) = match name {
+        "c.srli" => (0, uimm(nonzero(val)?, 6, "a shift of 1..63")?),
+        "c.srai" => (1, uimm(nonzero(val)?, 6, "a shift of 1..63")?),
+        _ => (2, imm(val, 6, "6 bits")?),
+      };
+      1 | 4 << 13
+        | bits(val, 5, 5) << 12
+        | funct2 << 10
+        | xc(0)? << 7
+        | bits(val, 4, 0) << 2
+    }
+    "c.sub" | "c.xor" | "c.or" | "c.and" | "c.subw" | "c.addw" => {
+      let (word, funct2) = match name {
+        "c.sub" => (0, 0),
+        "c.xor" => (0, 1),
+        "c.or" => (0, 2),
+        "c.and" => (0, 3),
+        "c.subw" => (1, 0),
+        _ => (1, 1),
+      };
+      1 | 4 << 13
+        | word << 12
+        | 3 << 10
+        | xc(0)? << 7
+        | funct2 << 5
+        | xc(1)? << 2
+    }
+    "c.j" => {
+      let val = imm(relative(ops[0], env)?, 12, "a jump's ±2 KiB")?;
+      1 | 5 << 13
+        | bits(val, 11, 11) << 12
+        | bits(val, 4, 4) << 11
+        | bits(val, 9, 8) << 9
+        | bits(val, 10, 10) << 8
+        | bits(val, 6, 6) << 7
+        | bits(val, 7, 7) << 6
+        | bits(val, 3, 1) << 3
+        | bits(val, 5, 5) << 2
+    }
+    "c.beqz" | "c.bnez" => {
+      let val = imm(relative(ops[1], env)?, 9, "a branch's ±256")?;
+      let f3 = if name == "c.beqz" { 6 } else { 7 };
+      1 | f3 << 13
+        | bits(val, 8, 8) << 12
+        | bits(val, 4, 3) << 10
+        | xc(0)? << 7
+        | bits(val, 7, 6) << 5
+        | bits(val, 2, 1) << 3
+        | bits(val, 5, 5) << 2
+    }
+    "c.slli" => {
+      let val = uimm(nonzero(eval(ops[1])?)?, 6, "a shift of 1..63")?;
+      2 | bits(val, 5, 5) << 12 | x(0)? << 7 | bits(val, 4, 0) << 2
+    }
+    "c.fldsp" | "c.ldsp" => {
+      let (off, rs1) = scaled(1, 8, 9)?;
+      sp(1, rs1)?;
+      let (f3, rd) = if name == "c.fldsp" { (1, f(0)?) } else { (3, x(0)?) };
+      if name == "c.ldsp" && rd == 0 {
+        return Err(AsmError::Register(ops[0].trim().to_string()));
+      }
+      2 | f3 << 13
+        | bits(off, 5, 5) << 12
+        | rd << 7
+        | bits(off, 4, 3) << 5
+        | bits(off, 8, 6) << 2
+    }
+    "c.lwsp" => {
+      let (off, rs1) = scaled(1, 4, 8)?;
+      sp(1, rs1)?;
+      let rd = x(0)?;
+      if rd == 0 {
+        return Err(AsmError::Register(ops[0].trim().to_string()));
+      }
+      2 | 2 << 13
+        | bits(off, 5, 5) << 12
+        | rd << 7
+        | bits(off, 4, 2) << 4
+        | bits(off, 7, 6) << 2
+    }
+    "c.jr" | "c.jalr" => {
+      let rs1 = x(0)?;
+      if rs1 == 0 {
+        return Err(AsmError::Register(ops[0].trim().to_string()));
+      }
+      2 | 4 << 13 | ((name == "c.jalr") as u32) << 12 | rs1 << 7
+    }
+    "c.mv" | "c.add" => {
+      let (rd, rs2) = (x(0)?, x(1)?);
+      if rs2 == 0 {
+        return Err(AsmError::Register(ops[1].trim().to_string()));
+      }
+      2 | 4 << 13 | ((name == "c.add") as u32) << 12 | rd << 7 | rs2 << 2
+    }
+    "c.fsdsp" | "c.sdsp" => {
+      let (off, rs1) = scaled(1, 8, 9)?;
+      sp(1, rs1)?;
+      let (f3, rs2) = if name == "c.fsdsp" { (5, f(0)?) } else { (7, x(0)?) };
+      2 | f3 << 13 | bits(off, 5, 3) << 10 | bits(off, 8, 6) << 7 | rs2 << 2
+    }
+    "c.swsp" => {
+      let (off, rs1) = scaled(1, 4, 8)?;
+      sp(1, rs1)?;
+      2 | 6 << 13 | bits(off, 5, 2) << 9 | bits(off, 7, 6) << 7 | x(0)? << 2
+    }
+    _ => return Err(AsmError::Mnemonic(name.to_string())),
+  };
+  Ok(inst as u16)
+}
@@ -0,0 +1,585 @@
+use {
+  crate::repr::session::Symbol,
+  parse::Env,
+  std::{collections::HashMap, fmt},
+};
+
+mod encode;
+mod parse;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+  #[error("unknown instruction `{0}`")]
+  Mnemonic(String),
+  #[error("unknown directive `{0}`")]
+  Directive(String),
+  #[error("`{0}` takes {1}")]
+  Operands(String, String),
+  #[error("`{0}` is not an integer register")]
+  Register(String),
+  #[error("`{0}` is not a float register")]
+  FloatRegister(String),
+  #[error("`{0}` is not one of x8..x15 that compressed instructions reach")]
+  Compressed(String),
+  #[error("expected `offset(register)`, found `{0}`")]
+  Memory(String),
+  #[error("invalid expression `{0}`")]
+  Expr(String),
+  #[error("undefined symbol `{0}`")]
+  Undefined(String),
+  #[error("`{0}` is not known yet, only constants defined above may be used")]
+  Constant(String),
+  #[error("label `{0}` is defined twice")]
+  Duplicate(String),
+  #[error("`{0}` is not a valid label name")]
+  Label(String),
+  #[error("{0} does not fit in {1}")]
+  Range(i64, &'static str),
+  #[error("{0} is not a multiple of {1}")]
+  Misaligned(i64, u64),
+  #[error("invalid string literal {0}")]
+  String(String),
+  #[error("unknown CSR `{0}`")]
+  Csr(String),
+  #[error("unknown rounding mode `{0}`")]
+  Rounding(String),
+  #[error("invalid fence set `{0}`")]
+  Fence(String),
+}
+
+/// Error of the source line it was found on, counting from 1.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub line: usize,
+  pub error: AsmError,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.error)
+  }
+}
+
+/// Assembled image, to be placed at `base`.
+#[derive(Debug, Clone)]
+pub struct Program {
+  pub base: u64,
+  pub bytes: Vec<u8>,
+  pub labels: Vec<Symbol>,
+  /// `_start`, else `main`, else the first instruction.
+  pub entry: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Section {
+  Text,
+  Data,
+}
+
+enum Item<'a> {
+  Inst {
+    name: &'a str,
+    ops: Vec<&'a str>,
+  },
+  /// `.byte` to `.dword`, resolved once labels are placed.
+  Values {
+    width: usize,
+    ops: Vec<&'a str>,
+  },
+  Bytes(Vec<u8>),
+}
+
+/// An item laid out at `offset` into its section.
+struct Stmt<'a> {
+  line: usize,
+  section: Section,
+  offset: u64,
+  item: Item<'a>,
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+  addr.div_ceil(align) * align
+}
+
+/// Padding up to a `align` boundary, with `nop`s in code.
+fn padding(section: Section, offset: u64, align: u64) -> Vec<u8> {
+  let len = align_up(offset, align) - offset;
+  if section == Section::Data || offset & 1 != 0 {
+    return vec![0; len as usize];
+  }
+  let mut bytes = vec![];
+  let mut at = offset;
+  while at < offset + len {
+    if at & 3 == 0 && offset + len - at >= 4 {
+      bytes.extend(0x0000_0013_u32.to_le_bytes());
+      at += 4;
+    } else {
+      bytes.extend(0x0001_u16.to_le_bytes());
+      at += 2;
+    }
+  }
+  bytes
+}
+
+/// Mnemonic or directive of a statement and its operands.
+fn split(text: &str) -> (&str, Vec<&str>) {
+  let (name, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+  (name, parse::operands(rest))
+}
+
+/// State of the first pass, which lays everything out.
+struct Layout<'a> {
+  stmts: Vec<Stmt<'a>>,
+  /// Labels by section and offset, `.equ` constants by value.
+  labels: Vec<(&'a str, Section, u64, usize)>,
+  constants: HashMap<String, i64>,
+  section: Section,
+  sizes: [u64; 2],
+  /// Largest alignment asked for in the data section.
+  data_align: u64,
+}
+
+impl<'a> Layout<'a> {
+  fn offset(&mut self) -> &mut u64 {
+    &mut self.sizes[self.section as usize]
+  }
+
+  fn push(&mut self, line: usize, item: Item<'a>, size: u64) {
+    let (section, offset) = (self.section, *self.offset());
+    self.stmts.push(Stmt { line, section, offset, item });
+    *self.offset() += size;
+  }
+
+  fn constant(&self, text: &str) -> Result<i64, AsmError> {
+    let env = Env { symbols: &self.constants, pc: None };
+    parse::eval(text, &env).map_err(|err| match err {
+      AsmError::Undefined(name) => AsmError::Constant(name),
+      err => err,
+    })
+  }
+
+  fn align(&mut self, line: usize, align: i64) -> Result<(), AsmError> {
+    if !(1..=1 << 12).contains(&align) || align.count_ones() != 1 {
+      return Err(AsmError::Range(align, "a power of two alignment"));
+    }
+    let align = align as u64;
+    if self.section == Section::Data {
+      self.data_align = self.data_align.max(align);
+    }
+    let bytes = padding(self.section, *self.offset(), align);
+    let size = bytes.len() as u64;
+    self.push(line, Item::Bytes(bytes), size);
+    Ok(())
+  }
+
+  fn directive(
+    &mut self,
+    line: usize,
+    name: &str,
+    ops: Vec<&'a str>,
+  ) -> Result<(), AsmError> {
+    let count = |want: usize, what: &str| {
+      if ops.len() == want {
+        Ok(())
+      } else {
+        Err(AsmError::Operands(name.to_string(), what.to_string()))
+      }
+    };
+    match name {
+      ".text" => self.section = Section::Text,
+      ".data" | ".rodata" | ".bss" => self.section = Section::Data,
+      ".section" => {
+        let section = ops.first().copied().unwrap_or_default();
+        self.section = if section.starts_with(".text") {
+          Section::Text
+        } else {
+          Section::Data
+        };
+      }
+      ".byte" | ".half" | ".2byte" | ".short" | ".word" | ".4byte"
+      | ".long" | ".dword" | ".8byte" | ".quad" => {
+        let width = match name {
+          ".byte" => 1,
+          ".half" | ".2byte" | ".short" => 2,
+          ".word" | ".4byte" | ".long" => 4,
+          _ => 8,
+        };
+        let size = (width * ops.len()) as u64;
+        self.push(line, Item::Values { width, ops }, size);
+      }
+      ".string" | ".asciz" | ".ascii" => {
+        let mut bytes = vec![];
+        for op in ops {
+          bytes.extend(parse::string(op)?);
+          if name != ".ascii" {
+            bytes.push(0);
+          }
+        }
+        let size = bytes.len() as u64;
+        self.push(line, Item::Bytes(bytes), size);
+      }
+      ".zero" | ".space" | ".skip" => {
+        if ops.is_empty() || ops.len() > 2 {
+          count(1, "a size and an optional fill byte")?;
+        }
+        let len = self.constant(ops[0])?;
+        let len =
+          usize::try_from(len).map_err(|_| AsmError::Range(len, "a size"))?;
+        let fill = ops.get(1).map(|op| self.constant(op)).transpose()?;
+        let bytes = vec![fill.unwrap_or(0) as u8; len];
+        self.push(line, Item::Bytes(bytes), len as u64);
+      }
+      ".align" | ".p2align" => {
+        count(1, "a power of two")?;
+        let pow = self.constant(ops[0])?;
+        if !(0..=12).contains(&pow) {
+          return Err(AsmError::Range(pow, "a power of two alignment"));
+        }
+        self.align(line, 1 << pow)?;
+      }
+      ".balign" => {
+        count(1, "an alignment")?;
+        let align = self.constant(ops[0])?;
+        self.align(line, align)?;
+      }
+      ".equ" | ".set" => {
+        count(2, "a name and a value")?;
+        if !parse::is_ident(ops[0]) {
+          return Err(AsmError::Label(ops[0].to_string()));
+        }
+        let val = self.constant(ops[1])?;
+        self.constants.insert(ops[0].to_string(), val);
+      }
+      // Only matter to linkers and debuggers
+      ".globl" | ".global" | ".local" | ".type" | ".size" | ".option"
+      | ".file" | ".ident" | ".attribute" => {}
+      _ => return Err(AsmError::Directive(name.to_string())),
+    }
+    Ok(())
+  }
+
+  fn statement(&mut self, line: usize, text: &'a str) -> Result<(), AsmError> {
+    let (name, ops) = split(text);
+    if name.starts_with('.') {
+      return self.directive(line, name, ops);
+    }
+    let env = Env { symbols: &self.constants, pc: None };
+    let size = match encode::size(name, &ops, &env) {
+      Err(AsmError::Undefined(name)) => return Err(AsmError::Constant(name)),
+      size => size?,
+    };
+    self.push(line, Item::Inst { name, ops }, size);
+    Ok(())
+  }
+}
+
+/// Assemble RV64GC `source` to run from `base`, code first and data after.
+///
+/// Reports every line that failed, not just the first.
+pub fn assemble(source: &str, base: u64) -> Result<Program, Vec<Diagnostic>> {
+  let mut errors = vec![];
+  let mut layout = Layout {
+    stmts: vec![],
+    labels: vec![],
+    constants: HashMap::new(),
+    section: Section::Text,
+    sizes: [0; 2],
+    data_align: 8,
+  };
+
+  for (idx, text) in source.lines().enumerate() {
+    let line = idx + 1;
+    let mut text = parse::strip_comment(text).trim();
+    // Any number of labels may lead a statement
+    while let Some((label, rest)) = text.split_once(':')
+      && !label.contains(['"', '\''])
+      && !label.trim().contains(char::is_whitespace)
+    {
+      let label = label.trim();
+      if parse::is_ident(label) {
+        let (section, offset) = (layout.section, *layout.offset());
+        layout.labels.push((label, section, offset, line));
+      } else {
+        errors.push(Diagnostic { line, error: AsmError::Label(label.into()) });
+      }
+      text = rest.trim();
+    }
+    if text.is_empty() {
+      continue;
+    }
+    if let Err(error) = layout.statement(line, text) {
+      errors.push(Diagnostic { line, error });
+    }
+  }
+
+  let text_end = base + layout.sizes[0];
+  let data = match layout.sizes[1] {
+    0 => text_end,
+    _ => align_up(text_end, layout.data_align),
+  };
+  let start = |section| match section {
+    Section::Text => base,
+    Section::Data => data,
+  };
+
+  let mut symbols = layout.constants.clone();
+  let mut labels = vec![];
+  for &(name, section, offset, line) in &layout.labels {
+    let addr = start(section) + offset;
+    if symbols.contains_key(name) {
+      errors.push(Diagnostic { line, error: AsmError::Duplicate(name.into()) });
+    } else {
+      symbols.insert(name.to_string(), addr as i64);
+    }
+    let code = section == Section::Text;
+    labels.push(Symbol { name: name.to_string(), addr, size: 0, code });
+  }
+
+  let mut bytes = vec![0; (data + layout.sizes[1] - base) as usize];
+  for Stmt { line, section, offset, item } in &layout.stmts {
+    let pc = start(*section) + offset;
+    let env = Env { symbols: &symbols, pc: Some(pc) };
+    let at = (pc - base) as usize;
+    let emitted = match item {
+      Item::Inst { name, ops } => encode::encode(name, ops, &env),
+      Item::Values { width, ops } => (ops.iter())
+        .map(|op| {
+          let val = parse::eval(op, &env)?;
+          Ok(val.to_le_bytes()[..*width].to_vec())
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|vals| vals.concat()),
+      Item::Bytes(bytes) => Ok(bytes.clone()),
+    };
+    match emitted {
+      Ok(emitted) => bytes[at..at + emitted.len()].copy_from_slice(&emitted),
+      Err(error) => errors.push(Diagnostic { line: *line, error }),
+    }
+  }
+
+  if !errors.is_empty() {
+    errors.sort_by_key(|diag| diag.line);
+    return Err(errors);
+  }
+  let entry = ["_start", "main"]
+    .iter()
+    .find_map(|name| symbols.get(*name))
+    .map_or(base, |&addr| addr as u64);
+  Ok(Program { base, bytes, labels, entry })
+}
+
+/// Encode the single instruction `text` to run at `pc`, which may refer to
+/// `symbols`.
+pub fn instruction(
+  text: &str,
+  pc: u64,
+  symbols: &HashMap<String, i64>,
+) -> Result<Vec<u8>, AsmError> {
+  let (name, ops) = split(parse::strip_comment(text).trim());
+  if name.starts_with('.') {
+    return Err(AsmError::Mnemonic(name.to_string()));
+  }
+  encode::encode(name, &ops, &Env { symbols, pc: Some(pc) })
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::apps::emulator::machine::Machine};
+
+  fn encode(text: &str, pc: u64) -> Result<Vec<u8>, AsmError> {
+    instruction(text, pc, &HashMap::new())
+  }
+
+  #[test]
+  fn matches_llvm() {
+    let words = [
+      ("addi a0, sp, -16", 0xff010513),
+      ("slli t0, t1, 63", 0x03f31293),
+      ("sraiw a1, a2, 31", 0x41f6559b),
+      ("lui a0, 0xfffff", 0xfffff537),
+      ("auipc t0, 1", 0x00001297),
+      ("sd ra, 8(sp)", 0x00113423),
+      ("lbu a0, -1(a1)", 0xfff5c503),
+      ("beq a0, a1, -4096", 0x80b50063),
+      ("bgeu t0, t1, 8", 0x0062f463),
+      ("jal ra, 2048", 0x001000ef),
+      ("jalr zero, 0(ra)", 0x00008067),
+      ("mulhsu a0, a1, a2", 0x02c5a533),
+      ("remuw a3, a4, a5", 0x02f776bb),
+      ("lr.d.aq a0, (a1)", 0x1405b52f),
+      ("sc.w.rl a2, a3, (a4)", 0x1ad7262f),
+      ("amoswap.d.aqrl t0, t1, (t2)", 0x0e63b2af),
+      ("csrrw a0, mscratch, a1", 0x34059573),
+      ("csrrci zero, mstatus, 8", 0x30047073),
+      ("fence rw, w", 0x0310000f),
+      ("ecall", 0x00000073),
+      ("fadd.d fa0, fa1, fa2", 0x02c5f553),
+      ("fmadd.s ft0, ft1, ft2, ft3, rtz", 0x18209043),
+      ("fcvt.l.d a0, fa0, rtz", 0xc2251553),
+      ("fld fs0, 16(sp)", 0x01013407),
+      ("fsw ft0, -4(a0)", 0xfe052e27),
+      ("fmv.x.d a0, fa0", 0xe2050553),
+    ];
+    for (text, raw) in words {
+      let raw: u32 = raw;
+      assert_eq!(encode(text, 0), Ok(raw.to_le_bytes().to_vec()), "{text}");
+    }
+
+    let halves = [
+      ("c.addi4spn a0, sp, 1020", 0x1fe8),
+      ("c.ld s1, 248(a5)", 0x7fe4),
+      ("c.sw a0, 124(a1)", 0xdde8),
+      ("c.addi a0, -32", 0x1501),
+      ("c.addiw a1, 31", 0x25fd),
+      ("c.li t0, -1", 0x52fd),
+      ("c.addi16sp sp, -512", 0x7101),
+      ("c.lui a2, 0xfffe0", 0x7601),
+      ("c.srli s0, 63", 0x907d),
+      ("c.andi a5, -1", 0x9bfd),
+      ("c.subw a0, a1", 0x9d0d),
+      ("c.and s1, a5", 0x8cfd),
+      ("c.j -2048", 0xb001),
+      ("c.beqz a0, 254", 0xcd7d),
+      ("c.slli t0, 1", 0x0286),
+      ("c.fldsp ft0, 504(sp)", 0x307e),
+      ("c.swsp ra, 252(sp)", 0xdf86),
+      ("c.mv a0, t6", 0x857e),
+      ("c.add ra, sp", 0x908a),
+      ("c.ebreak", 0x9002),
+    ];
+    for (text, raw) in halves {
+      let raw: u16 = raw;
+      assert_eq!(encode(text, 0), Ok(raw.to_le_bytes().to_vec()), "{text}");
+    }
+  }
+
+  #[test]
+  fn decodes_back() {
+    let word = |text| {
+      let raw = encode(text, 0).unwrap();
+      u32::from_le_bytes(raw.try_into().unwrap())
+    };
+    let fields = [
+      ("sd ra, 8(sp)", (None, Some(2), Some(1), Some(8))),
+      ("beq a0, a1, -4096", (None, Some(10), Some(11), Some(-4096))),
+      ("jal ra, 2048", (Some(1), None, None, Some(2048))),
+      ("mulhsu a0, a1, a2", (Some(10), Some(11), Some(12), None)),
+    ];
+    for (text, fields) in fields {
+      let inst = Machine::decode(word(text), 4).unwrap();
+      assert_eq!((inst.rd, inst.rs1, inst.rs2, inst.imm), fields, "{text}");
+    }
+    // Float instructions go through the emulator's own decoder
+    let text = "fmadd.s ft0, ft1, ft2, ft3, rtz";
+    assert_eq!(Machine::disassemble(word(text), 4).as_deref(), Some(text));
+  }
+
+  #[test]
+  fn li_builds_every_value() {
+    let vals = [
+      0,
+      -1,
+      2047,
+      -2048,
+      0x800,
+      0x7fff_ffff,
+      -0x8000_0000,
+      0x8000_0000,
+      0xffff_ffff,
+      0x1234_5678_9abc_def0,
+      0x7ff0_0000_0000_0000,
+      i64::MIN,
+      i64::MAX,
+    ];
+    for val in vals {
+      let code = encode(&format!("li a0, {val}"), 0).unwrap();
+      let mut machine = Machine::default();
+      machine.bus.write(Machine::RAM, &code).unwrap();
+      machine.cpu.pc = Machine::RAM;
+      for _ in 0..code.len() / 4 {
+        machine.step().unwrap();
+      }
+      assert_eq!(machine.cpu.x(10), val as u64, "li a0, {val:#x}");
+    }
+    assert_eq!(encode("li a0, 5", 0).unwrap().len(), 4);
+    assert_eq!(encode("li a0, 0x12345678", 0).unwrap().len(), 8);
+  }
+
+  #[test]
+  fn operand_errors() {
+    let range = |val, what| Err(AsmError::Range(val, what));
+    assert_eq!(encode("addi a0, a0, 2048", 0), range(2048, "12 bits"));
+    assert_eq!(encode("beq a0, a1, 3", 0), Err(AsmError::Misaligned(3, 2)));
+    assert_eq!(
+      encode("add a0, a1", 0),
+      Err(AsmError::Operands("add".into(), "3 operands".into()))
+    );
+    assert_eq!(
+      encode("add a0, a1, a16", 0),
+      Err(AsmError::Register("a16".into()))
+    );
+    assert_eq!(
+      encode("c.lw a0, 4(a6)", 0),
+      Err(AsmError::Compressed("4(a6)".into()))
+    );
+    assert_eq!(encode("frob a0", 0), Err(AsmError::Mnemonic("frob".into())));
+    assert_eq!(encode(".word 1", 0), Err(AsmError::Mnemonic(".word".into())));
+  }
+
+  #[test]
+  fn assembles_a_program() {
+    let source = "
+      .equ COUNT, 3
+      .text
+      _start:
+        la a0, msg
+        li a1, COUNT
+      loop: addi a1, a1, -1
+        bnez a1, loop
+        c.ebreak
+      .data
+      msg: .asciz \"hi\"
+      .align 3
+      table: .dword loop, _start
+    ";
+    let program = assemble(source, Machine::RAM).unwrap();
+    assert_eq!(program.entry, Machine::RAM);
+    let label = |name| {
+      program.labels.iter().find(|label| label.name == name).unwrap().addr
+    };
+    let (text_end, msg) = (Machine::RAM + 22, Machine::RAM + 24);
+    assert_eq!(label("loop"), Machine::RAM + 12);
+    assert_eq!(label("msg"), msg);
+    assert_eq!(label("table"), msg + 8);
+    assert_eq!(program.bytes.len() as u64, msg + 24 - Machine::RAM);
+    assert_eq!(&program.bytes[22..24], [0; 2]);
+    assert_eq!(&program.bytes[24..27], b"hi\0");
+    let table = &program.bytes[(msg + 8 - Machine::RAM) as usize..];
+    assert_eq!(table[..8], label("loop").to_le_bytes());
+    assert_eq!(table[8..], Machine::RAM.to_le_bytes());
+
+    let mut machine = Machine::default();
+    machine.bus.write(Machine::RAM, &program.bytes).unwrap();
+    machine.cpu.pc = program.entry;
+    while machine.cpu.pc != text_end - 2 {
+      machine.step().unwrap();
+    }
+    assert_eq!((machine.cpu.x(10), machine.cpu.x(11)), (msg, 0));
+  }
+
+  #[test]
+  fn reports_every_line() {
+    let source = "addi a0, a0, 1\nfrob\n.word undefined\nx: nop\nx: nop";
+    let errors = assemble(source, 0).unwrap_err();
+    let errors: Vec<_> =
+      errors.into_iter().map(|diag| (diag.line, diag.error)).collect();
+    assert_eq!(
+      errors,
+      [
+        (2, AsmError::Mnemonic("frob".into())),
+        (3, AsmError::Undefined("undefined".into())),
+        (5, AsmError::Duplicate("x".into())),
+      ]
+    );
+  }
+}
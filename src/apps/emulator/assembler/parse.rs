@@ -0,0 +1,358 @@
+use {
+  super::AsmError,
+  crate::apps::emulator::machine::{CSRS, FREGS, XREGS},
+  std::collections::HashMap,
+};
+
+/// Values of the names an expression may use.
+pub struct Env<'a> {
+  pub symbols: &'a HashMap<String, i64>,
+  /// Address of the statement, `None` where it is not known yet.
+  pub pc: Option<u64>,
+}
+
+/// Cut a comment off `line`, minding `#` within quotes.
+pub fn strip_comment(line: &str) -> &str {
+  let mut quote = None;
+  let mut escaped = false;
+  for (idx, ch) in line.char_indices() {
+    match quote {
+      Some(_) if escaped => escaped = false,
+      Some(_) if ch == '\\' => escaped = true,
+      Some(open) if ch == open => quote = None,
+      Some(_) => {}
+      None if ch == '"' || ch == '\'' => quote = Some(ch),
+      None if ch == '#' || ch == ';' || line[idx..].starts_with("//") => {
+        return &line[..idx];
+      }
+      None => {}
+    }
+  }
+  line
+}
+
+/// Split operands on the commas outside of quotes and parentheses.
+pub fn operands(text: &str) -> Vec<&str> {
+  let text = text.trim();
+  if text.is_empty() {
+    return vec![];
+  }
+  let (mut parts, mut start, mut depth) = (vec![], 0, 0);
+  let (mut quote, mut escaped) = (None, false);
+  for (idx, ch) in text.char_indices() {
+    match quote {
+      Some(_) if escaped => escaped = false,
+      Some(_) if ch == '\\' => escaped = true,
+      Some(open) if ch == open => quote = None,
+      Some(_) => {}
+      None => match ch {
+        '"' | '\'' => quote = Some(ch),
+        '(' => depth += 1,
+        ')' => depth -= 1,
+        ',' if depth == 0 => {
+          parts.push(text[start..idx].trim());
+          start = idx + 1;
+        }
+        _ => {}
+      },
+    }
+  }
+  parts.push(text[start..].trim());
+  parts
+}
+
+pub fn is_ident(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars.next().is_some_and(|ch| ch.is_ascii_alphabetic() || "_.$".contains(ch))
+    && chars.all(|ch| ch.is_ascii_alphanumeric() || "_.$".contains(ch))
+}
+
+/// Integer register by number or ABI name.
+pub fn xreg(text: &str) -> Result<u32, AsmError> {
+  let text = text.trim();
+  let idx = match text.strip_prefix('x').map(str::parse::<u32>) {
+    Some(Ok(idx)) if idx < 32 => Some(idx),
+    _ if text == "fp" || text == "s0" => Some(8),
+    _ => XREGS.iter().position(|&abi| abi == text).map(|idx| idx as u32),
+  };
+  idx.ok_or_else(|| AsmError::Register(text.to_string()))
+}
+
+/// Float register by number or ABI name.
+pub fn freg(text: &str) -> Result<u32, AsmError> {
+  let text = text.trim();
+  let idx = match text.strip_prefix('f').map(str::parse::<u32>) {
+    Some(Ok(idx)) if idx < 32 => Some(idx),
+    _ => FREGS.iter().position(|&abi| abi == text).map(|idx| idx as u32),
+  };
+  idx.ok_or_else(|| AsmError::FloatRegister(text.to_string()))
+}
+
+/// `offset(reg)` into its offset, empty when left out, and register.
+pub fn mem(text: &str) -> Result<(&str, u32), AsmError> {
+  let text = text.trim();
+  let reg = text.strip_suffix(')').and_then(|rest| {
+    let open = rest.rfind('(')?;
+    Some((rest[..open].trim(), &rest[open + 1..]))
+  });
+  let (offset, reg) = reg.ok_or_else(|| AsmError::Memory(text.to_string()))?;
+  Ok((offset, xreg(reg)?))
+}
+
+/// CSR by name or number.
+pub fn csr(text: &str, env: &Env) -> Result<u32, AsmError> {
+  let text = text.trim();
+  if let Some(&(addr, _)) = CSRS.iter().find(|&&(_, name)| name == text) {
+    return Ok(addr as u32);
+  }
+  match eval(text, env) {
+    Ok(addr @ 0..0x1000) => Ok(addr as u32),
+    _ => Err(AsmError::Csr(text.to_string())),
+  }
+}
+
+pub fn rounding(text: &str) -> Result<u32, AsmError> {
+  Ok(match text.trim() {
+    "rne" => 0,
+    "rtz" => 1,
+    "rdn" => 2,
+    "rup" => 3,
+    "rmm" => 4,
+    "dyn" => 7,
+    text => return Err(AsmError::Rounding(text.to_string())),
+  })
+}
+
+/// `iorw` and its subsets, as the four bits of a fence.
+pub fn fence_set(text: &str) -> Result<u32, AsmError> {
+  let text = text.trim();
+  let mut bits = 0;
+  for ch in text.chars() {
+    let bit = match ch {
+      'i' => 8,
+      'o' => 4,
+      'r' => 2,
+      'w' => 1,
+      _ => return Err(AsmError::Fence(text.to_string())),
+    };
+    bits |= bit;
+  }
+  if bits == 0 {
+    return Err(AsmError::Fence(text.to_string()));
+  }
+  Ok(bits)
+}
+
+/// Contents of a `"..."` literal with C escapes.
+pub fn string(text: &str) -> Result<Vec<u8>, AsmError> {
+  let text = text.trim();
+  let invalid = || AsmError::String(text.to_string());
+  let inner = (text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')))
+    .ok_or_else(invalid)?;
+  unescape(inner).ok_or_else(invalid)
+}
+
+fn unescape(text: &str) -> Option<Vec<u8>> {
+  let mut bytes = vec![];
+  let mut chars = text.chars().peekable();
+  while let Some(ch) = chars.next() {
+    if ch != '\\' {
+      let mut buf = [0; 4];
+      bytes.extend(ch.encode_utf8(&mut buf).as_bytes());
+      continue;
+    }
+    bytes.push(match chars.next()? {
+      'n' => b'\n',
+      't' => b'\t',
+      'r' => b'\r',
+      '0' => 0,
+      '\\' => b'\\',
+      '"' => b'"',
+      '\'' => b'\'',
+      'x' => {
+        let mut val = 0;
+        for _ in 0..2 {
+          let digit = chars.peek().and_then(|ch| ch.to_digit(16));
+          let Some(digit) = digit else { break };
+          chars.next();
+          val = val << 4 | digit as u8;
+        }
+        val
+      }
+      _ => return None,
+    });
+  }
+  Some(bytes)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Num(i64),
+  Name(String),
+  Op(&'static str),
+}
+
+// Longer operators first, so that `<<` is not taken for `<`
+const OPS: [&str; 13] =
+  ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, AsmError> {
+  let invalid = || AsmError::Expr(text.to_string());
+  let mut tokens = vec![];
+  let mut rest = text.trim_start();
+  while !rest.is_empty() {
+    let ch = rest.chars().next().unwrap();
+    let len = if ch.is_ascii_digit() {
+      let end = rest
+        .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+        .unwrap_or(rest.len());
+      tokens.push(Token::Num(number(&rest[..end]).ok_or_else(invalid)?));
+      end
+    } else if ch == '\'' {
+      let end = rest[1..].find('\'').ok_or_else(invalid)? + 2;
+      let bytes = unescape(&rest[1..end - 1]).ok_or_else(invalid)?;
+      let [byte] = bytes[..] else { return Err(invalid()) };
+      tokens.push(Token::Num(byte as i64));
+      end
+    } else if ch == '%' && rest[1..].starts_with(|ch: char| ch.is_alphabetic())
+    {
+      let end = rest[1..]
+        .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+        .map_or(rest.len(), |end| end + 1);
+      tokens.push(Token::Name(rest[..end].to_string()));
+      end
+    } else if ch.is_ascii_alphabetic() || "_.$".contains(ch) {
+      let end = rest
+        .find(|ch: char| !ch.is_ascii_alphanumeric() && !"_.$".contains(ch))
+        .unwrap_or(rest.len());
+      tokens.push(Token::Name(rest[..end].to_string()));
+      end
+    } else {
+      let op =
+        OPS.iter().find(|op| rest.starts_with(**op)).ok_or_else(invalid)?;
+      tokens.push(Token::Op(op));
+      op.len()
+    };
+    rest = rest[len..].trim_start();
+  }
+  Ok(tokens)
+}
+
+fn number(word: &str) -> Option<i64> {
+  let digits = word.replace('_', "");
+  let (digits, radix) = match digits.get(..2) {
+    Some("0x" | "0X") => (&digits[2..], 16),
+    Some("0b" | "0B") => (&digits[2..], 2),
+    Some("0o" | "0O") => (&digits[2..], 8),
+    _ => (&digits[..], 10),
+  };
+  // Full 64-bit patterns like `0xffffffffffffffff` wrap around
+  u64::from_str_radix(digits, radix).ok().map(|val| val as i64)
+}
+
+struct Parser<'a> {
+  tokens: Vec<Token>,
+  pos: usize,
+  env: &'a Env<'a>,
+  text: &'a str,
+}
+
+impl Parser<'_> {
+  fn invalid(&self) -> AsmError {
+    AsmError::Expr(self.text.to_string())
+  }
+
+  fn eat(&mut self, op: &str) -> bool {
+    let next = self.tokens.get(self.pos);
+    let found = matches!(next, Some(Token::Op(found)) if *found == op);
+    self.pos += found as usize;
+    found
+  }
+
+  /// Binary operators by precedence climbing, `|` binding the loosest.
+  fn binary(&mut self, level: usize) -> Result<i64, AsmError> {
+    const LEVELS: [&[&str]; 6] =
+      [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+    let Some(ops) = LEVELS.get(level) else { return self.unary() };
+    let mut lhs = self.binary(level + 1)?;
+    'climb: loop {
+      for &op in ops.iter() {
+        if self.eat(op) {
+          let rhs = self.binary(level + 1)?;
+          lhs = match op {
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "&" => lhs & rhs,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" => lhs.checked_div(rhs).ok_or_else(|| self.invalid())?,
+            _ => lhs.checked_rem(rhs).ok_or_else(|| self.invalid())?,
+          };
+          continue 'climb;
+        }
+      }
+      return Ok(lhs);
+    }
+  }
+
+  fn unary(&mut self) -> Result<i64, AsmError> {
+    if self.eat("-") {
+      return Ok(self.unary()?.wrapping_neg());
+    }
+    if self.eat("+") {
+      return self.unary();
+    }
+    if self.eat("~") {
+      return Ok(!self.unary()?);
+    }
+    if self.eat("(") {
+      let val = self.binary(0)?;
+      return if self.eat(")") { Ok(val) } else { Err(self.invalid()) };
+    }
+    let token =
+      self.tokens.get(self.pos).cloned().ok_or_else(|| self.invalid())?;
+    self.pos += 1;
+    match token {
+      Token::Num(num) => Ok(num),
+      Token::Name(name) if name.starts_with('%') => {
+        if !self.eat("(") {
+          return Err(self.invalid());
+        }
+        let val = self.binary(0)?;
+        if !self.eat(")") {
+          return Err(self.invalid());
+        }
+        match name.as_str() {
+          "%hi" => Ok((val.wrapping_add(0x800) >> 12) & 0xfffff),
+          "%lo" => Ok(val << 52 >> 52),
+          _ => Err(AsmError::Expr(name)),
+        }
+      }
+      Token::Name(name) if name == "." => {
+        self.env.pc.map(|pc| pc as i64).ok_or(AsmError::Constant(name))
+      }
+      Token::Name(name) => {
+        self.env.symbols.get(&name).copied().ok_or(AsmError::Undefined(name))
+      }
+      Token::Op(_) => Err(self.invalid()),
+    }
+  }
+}
+
+/// Value of an expression over numbers, characters, symbols, `.` and
+/// `%hi`/`%lo`, with C operators.
+pub fn eval(text: &str, env: &Env) -> Result<i64, AsmError> {
+  let tokens = tokenize(text)?;
+  if tokens.is_empty() {
+    return Err(AsmError::Expr(text.to_string()));
+  }
+  let mut parser = Parser { tokens, pos: 0, env, text };
+  let val = parser.binary(0)?;
+  if parser.pos < parser.tokens.len() {
+    return Err(parser.invalid());
+  }
+  Ok(val)
+}
@@ -0,0 +1,359 @@
+use {
+  super::{
+    expr::{Expr, ExprError},
+    lines::Lines,
+    machine::Machine,
+    symbols::Symbols,
+  },
+  crate::repr::session::Breakpoint,
+  egui::{
+    Button, Context, DragValue, Grid, RichText, ScrollArea, TextEdit, Window,
+  },
+  std::{collections::BTreeMap, mem},
+};
+
+#[derive(Clone)]
+struct Entry {
+  enabled: bool,
+  condition: String,
+  /// Parsed `condition`, `None` while it is empty.
+  expr: Result<Option<Expr>, ExprError>,
+  after: u64,
+  /// Times execution reached the address with the condition holding.
+  hits: u64,
+  /// Why the condition could not be evaluated at the last hit.
+  error: Option<ExprError>,
+}
+
+impl Entry {
+  fn new(condition: String, symbols: &Symbols) -> Self {
+    let mut entry = Self {
+      enabled: true,
+      condition,
+      expr: Ok(None),
+      after: 0,
+      hits: 0,
+      error: None,
+    };
+    entry.parse(symbols);
+    entry
+  }
+
+  fn parse(&mut self, symbols: &Symbols) {
+    self.expr = match self.condition.trim() {
+      "" => Ok(None),
+      text => Expr::parse(text, symbols).map(Some),
+    };
+    self.error = None;
+  }
+
+  fn holds(&self, machine: &Machine) -> Result<bool, ExprError> {
+    match &self.expr {
+      Ok(None) => Ok(true),
+      Ok(Some(expr)) => expr.eval(machine).map(|val| val != 0),
+      Err(err) => Err(err.clone()),
+    }
+  }
+}
+
+/// Addresses execution stops at, each one may be switched off, made
+/// conditional or told to let some hits pass.
+#[derive(Default, Clone)]
+pub struct Breakpoints {
+  by_addr: BTreeMap<u64, Entry>,
+  /// Edited since [`Self::take_changed`], for a running copy to catch up.
+  changed: bool,
+}
+
+impl Breakpoints {
+  pub fn new(breakpoints: Vec<Breakpoint>, symbols: &Symbols) -> Self {
+    let by_addr = breakpoints.into_iter().map(|bp| {
+      let entry = Entry::new(bp.condition, symbols);
+      (bp.addr, Entry { enabled: bp.enabled, after: bp.after, ..entry })
+    });
+    Self { by_addr: by_addr.collect(), changed: false }
+  }
+
+  pub fn repr(&self) -> Vec<Breakpoint> {
+    (self.by_addr.iter())
+      .map(|(&addr, entry)| Breakpoint {
+        addr,
+        enabled: entry.enabled,
+        condition: entry.condition.clone(),
+        after: entry.after,
+      })
+      .collect()
+  }
+
+  /// Parse the conditions again, symbols may have moved or disappeared.
+  pub fn relink(&mut self, symbols: &Symbols) {
+    self.by_addr.values_mut().for_each(|entry| entry.parse(symbols));
+    self.changed = true;
+  }
+
+  pub fn take_changed(&mut self) -> bool {
+    mem::take(&mut self.changed)
+  }
+
+  /// Enable the breakpoint at `addr`, a new one stops unconditionally.
+  pub fn insert(&mut self, addr: u64) {
+    let entry = Entry::new(String::new(), &Symbols::default());
+    self.by_addr.entry(addr).or_insert(entry).enabled = true;
+    self.changed = true;
+  }
+
+  pub fn remove(&mut self, addr: u64) {
+    self.changed |= self.by_addr.remove(&addr).is_some();
+  }
+
+  /// Set an enabled breakpoint at `addr` or delete the one there.
+  pub fn toggle(&mut self, addr: u64) {
+    if self.at(addr).is_some() {
+      self.remove(addr);
+    } else {
+      self.insert(addr);
+    }
+  }
+
+  /// `Some(enabled)` when there is a breakpoint at `addr`.
+  pub fn at(&self, addr: u64) -> Option<bool> {
+    self.by_addr.get(&addr).map(|entry| entry.enabled)
+  }
+
+  /// Count a hit at `pc` and tell whether execution stops there.
+  pub fn hit(&mut self, machine: &Machine) -> bool {
+    let entry = self.by_addr.get_mut(&machine.cpu.pc);
+    let Some(entry) = entry.filter(|entry| entry.enabled) else {
+      return false;
+    };
+    match entry.holds(machine) {
+      Ok(held) => {
+        entry.error = None;
+        entry.hits += held as u64;
+        held && entry.hits > entry.after
+      }
+      // A broken condition stops, so that it gets noticed
+      Err(err) => {
+        entry.error = Some(err);
+        true
+      }
+    }
+  }
+
+  /// Whether an enabled breakpoint at `pc` has its condition hold, skip
+  /// counts aside and without counting a hit.
+  pub fn stops(&self, machine: &Machine) -> bool {
+    let entry = self.by_addr.get(&machine.cpu.pc);
+    entry
+      .filter(|entry| entry.enabled)
+      .is_some_and(|entry| entry.holds(machine).unwrap_or(true))
+  }
+
+  /// Take the hits counted by a run on a copy of the breakpoints.
+  pub fn merge(&mut self, ran: Breakpoints) {
+    for (addr, ran) in ran.by_addr {
+      if let Some(entry) = self.by_addr.get_mut(&addr) {
+        entry.hits = ran.hits;
+        entry.error = ran.error;
+      }
+    }
+  }
+}
+
+/// Lists the breakpoints to switch them off, edit or delete them.
+#[derive(Default)]
+pub struct BreakpointList {
+  pub open: bool,
+}
+
+impl BreakpointList {
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    breakpoints: &mut Breakpoints,
+    symbols: &Symbols,
+    lines: &Lines,
+    running: bool,
+  ) {
+    let mut open = self.open;
+    Window::new("Breakpoints").open(&mut open).show(ctx, |ui| {
+      if breakpoints.by_addr.is_empty() {
+        ui.weak("Click the gutter of the instructions window to add one");
+        return;
+      }
+
+      let (mut remove, mut changed) = (None, false);
+      ScrollArea::vertical().show(ui, |ui| {
+        Grid::new("breakpoints").striped(true).show(ui, |ui| {
+          for label in ["", "address", "", "", "condition", "skip", "hits"] {
+            ui.label(label);
+          }
+          ui.end_row();
+
+          for (&addr, entry) in &mut breakpoints.by_addr {
+            changed |= ui.checkbox(&mut entry.enabled, "").changed();
+            ui.monospace(format!("{addr:#x}"));
+            ui.monospace(symbols.annotate(addr).unwrap_or_default());
+            match lines.lookup(addr) {
+              Some((file, line)) => {
+                let path = lines.path(file);
+                let name = path.rsplit('/').next().unwrap_or(path);
+                ui.monospace(format!("{name}:{line}")).on_hover_text(path)
+              }
+              None => ui.label(""),
+            };
+
+            let error = entry.expr.as_ref().err().or(entry.error.as_ref());
+            let mut edit = TextEdit::singleline(&mut entry.condition)
+              .hint_text("x10 == 0 && mem32[sp + 8] > 3")
+              .font(egui::TextStyle::Monospace)
+              .desired_width(200.0);
+            if error.is_some() {
+              edit = edit.text_color(ui.visuals().error_fg_color);
+            }
+            let response = ui.add(edit);
+            if let Some(err) = error {
+              response.clone().on_hover_text(err.to_string());
+            }
+            if response.changed() {
+              entry.parse(symbols);
+              changed = true;
+            }
+
+            changed |= (ui.add(DragValue::new(&mut entry.after)))
+              .on_hover_text("Hits passed over before stopping")
+              .changed();
+            ui.label(RichText::new(entry.hits.to_string()).monospace());
+            if ui.small_button("✖").on_hover_text("Delete").clicked() {
+              remove = Some(addr);
+            }
+            ui.end_row();
+          }
+        });
+      });
+      if let Some(addr) = remove {
+        breakpoints.remove(addr);
+      }
+      breakpoints.changed |= changed;
+
+      ui.separator();
+      ui.horizontal(|ui| {
+        let entries = || breakpoints.by_addr.values();
+        let all = entries().all(|entry| entry.enabled);
+        let (text, enable) =
+          if all { ("Disable all", false) } else { ("Enable all", true) };
+        if ui.button(text).clicked() {
+          (breakpoints.by_addr.values_mut())
+            .for_each(|entry| entry.enabled = enable);
+          breakpoints.changed = true;
+        }
+        // A run counts hits on its own copy, which would win over the reset
+        let reset = ui
+          .add_enabled(!running, Button::new("Reset hits"))
+          .on_disabled_hover_text("Hits are counted while running");
+        if reset.clicked() {
+          breakpoints.by_addr.values_mut().for_each(|entry| entry.hits = 0);
+        }
+        if ui.button("Delete all").clicked() {
+          breakpoints.by_addr.clear();
+          breakpoints.changed = true;
+        }
+      });
+    });
+    self.open = open;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LOOP: [u32; 2] = [
+    0x00150513, // addi a0, a0, 1
+    0xffdff06f, // j -4
+  ];
+  const JUMP: u64 = Machine::RAM + 4;
+
+  fn breakpoint(condition: &str, after: u64) -> Breakpoints {
+    let condition = condition.to_string();
+    let bp = Breakpoint { addr: JUMP, enabled: true, condition, after };
+    Breakpoints::new(vec![bp], &Symbols::default())
+  }
+
+  /// Values of `a0` the loop stops at within `steps`.
+  fn stops(breakpoints: &mut Breakpoints, steps: usize) -> Vec<u64> {
+    let mut machine = Machine::with_code(&LOOP);
+    let mut stops = vec![];
+    for _ in 0..steps {
+      machine.step().unwrap();
+      if breakpoints.hit(&machine) {
+        stops.push(machine.cpu.x(10));
+      }
+    }
+    stops
+  }
+
+  #[test]
+  fn toggle_and_repr() {
+    let mut breakpoints = Breakpoints::default();
+    breakpoints.toggle(JUMP);
+    assert_eq!(breakpoints.at(JUMP), Some(true));
+    breakpoints.toggle(JUMP);
+    assert_eq!(breakpoints.at(JUMP), None);
+
+    let mut breakpoints = breakpoint("a0 > 1", 2);
+    breakpoints.insert(Machine::RAM);
+    let repr = breakpoints.repr();
+    assert_eq!(repr.len(), 2);
+    assert_eq!((repr[1].condition.as_str(), repr[1].after), ("a0 > 1", 2));
+  }
+
+  #[test]
+  fn conditions_and_skips() {
+    assert_eq!(stops(&mut breakpoint("", 0), 8), [1, 2, 3, 4]);
+    assert_eq!(stops(&mut breakpoint("a0 % 2 == 0", 0), 8), [2, 4]);
+    let mut skipping = breakpoint("a0 >= 2", 1);
+    assert_eq!(stops(&mut skipping, 8), [3, 4]);
+    assert_eq!(skipping.by_addr[&JUMP].hits, 3);
+
+    let mut disabled = breakpoint("", 0);
+    disabled.by_addr.get_mut(&JUMP).unwrap().enabled = false;
+    assert!(stops(&mut disabled, 8).is_empty());
+  }
+
+  #[test]
+  fn broken_conditions_stop() {
+    let mut breakpoints = breakpoint("mem8[0]", 0);
+    assert_eq!(stops(&mut breakpoints, 2), [1]);
+    let entry = &breakpoints.by_addr[&JUMP];
+    assert_eq!(entry.error, Some(ExprError::Unmapped(0)));
+    assert_eq!(entry.hits, 0);
+
+    let mut unparsed = breakpoint("a0 +", 0);
+    assert_eq!(stops(&mut unparsed, 2), [1]);
+  }
+
+  #[test]
+  fn merge_takes_the_hits() {
+    let mut breakpoints = breakpoint("", 0);
+    let mut ran = breakpoints.clone();
+    stops(&mut ran, 6);
+    breakpoints.insert(Machine::RAM);
+    breakpoints.merge(ran);
+    assert_eq!(breakpoints.by_addr[&JUMP].hits, 3);
+    assert_eq!(breakpoints.by_addr[&Machine::RAM].hits, 0);
+  }
+
+  #[test]
+  fn edits_are_tracked() {
+    let mut breakpoints = Breakpoints::default();
+    assert!(!breakpoints.take_changed());
+    breakpoints.insert(JUMP);
+    assert!(breakpoints.take_changed());
+    assert!(!breakpoints.take_changed());
+    breakpoints.remove(Machine::RAM);
+    assert!(!breakpoints.take_changed());
+    breakpoints.toggle(JUMP);
+    assert!(breakpoints.take_changed());
+  }
+}
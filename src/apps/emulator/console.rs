@@ -0,0 +1,112 @@
+use {
+  super::machine::Uart,
+  egui::{
+    Context, Event, EventFilter, Frame, Key, RichText, ScrollArea, Sense,
+    Window,
+  },
+};
+
+/// Bytes of output kept, the older half is dropped past it.
+const SCROLLBACK: usize = 1 << 16;
+
+/// Serial terminal attached to the UART.
+pub struct Console {
+  out: Vec<u8>,
+  pub open: bool,
+}
+
+impl Default for Console {
+  fn default() -> Self {
+    Self { out: vec![], open: true }
+  }
+}
+
+impl Console {
+  fn push(&mut self, byte: u8) {
+    match byte {
+      b'\r' => {}
+      // Backspace
+      0x08 => {
+        self.out.pop();
+      }
+      byte => self.out.push(byte),
+    }
+    if self.out.len() > SCROLLBACK {
+      self.out.drain(..SCROLLBACK / 2);
+    }
+  }
+
+  pub fn ui(&mut self, ctx: &Context, uart: &mut Uart) {
+    // Drained even while hidden, so that no output is lost
+    while let Ok(byte) = uart.serial.tx.pop() {
+      self.push(byte);
+    }
+
+    let mut open = self.open;
+    Window::new("Console").open(&mut open).default_size([480.0, 320.0]).show(
+      ctx,
+      |ui| {
+        if ui.button("Clear").clicked() {
+          self.out.clear();
+        }
+        ui.separator();
+
+        let id = ui.id().with("console-input");
+        let focused = ui.memory(|mem| mem.has_focus(id));
+        let stroke = if focused {
+          ui.visuals().selection.stroke
+        } else {
+          ui.visuals().widgets.noninteractive.bg_stroke
+        };
+
+        let frame = Frame::canvas(ui.style()).stroke(stroke).show(ui, |ui| {
+          ScrollArea::vertical().stick_to_bottom(true).auto_shrink(false).show(
+            ui,
+            |ui| {
+              let text = String::from_utf8_lossy(&self.out);
+              ui.label(RichText::new(text).monospace());
+            },
+          );
+        });
+
+        let response = ui.interact(frame.response.rect, id, Sense::click());
+        if response.clicked() {
+          response.request_focus();
+        }
+        if !focused {
+          response.on_hover_text("Click to type into the receive FIFO");
+          return;
+        }
+
+        ui.memory_mut(|mem| {
+          mem.set_focus_lock_filter(
+            id,
+            EventFilter {
+              tab: true,
+              horizontal_arrows: true,
+              vertical_arrows: true,
+              escape: false,
+            },
+          )
+        });
+        for event in ui.input(|i| i.events.clone()) {
+          let bytes: &[u8] = match &event {
+            Event::Text(text) => text.as_bytes(),
+            Event::Key { key, pressed: true, .. } => match key {
+              // Terminals send a carriage return for enter
+              Key::Enter => b"\r",
+              Key::Backspace => b"\x7f",
+              Key::Tab => b"\t",
+              _ => continue,
+            },
+            _ => continue,
+          };
+          for &byte in bytes {
+            let _ = uart.serial.rx.push(byte);
+          }
+        }
+      },
+    );
+    self.open = open;
+  }
+}
@@ -0,0 +1,111 @@
+use {
+  super::assembler::{self, Diagnostic, Program},
+  crate::{repr::session::RegionKind, widgets::HexEdit},
+  egui::{Button, Color32, Context, ScrollArea, TextEdit, TextStyle, Window},
+};
+
+const EXAMPLE: &str = "\
+# Prints a greeting to the console
+.equ UART, 0x10000000
+
+.text
+_start:
+    li   t0, UART
+    la   a0, hello
+loop:
+    lbu  t1, 0(a0)
+    beqz t1, done
+    sb   t1, 0(t0)
+    addi a0, a0, 1
+    j    loop
+done:
+    j    done
+
+.data
+hello:
+    .string \"Hello, world!\\n\"
+";
+
+/// Writes RV64GC assembly and loads it into memory.
+pub struct AsmEditor {
+  source: String,
+  base: u64,
+  base_edit: HexEdit,
+  /// Outcome of assembling the current source.
+  result: Result<Program, Vec<Diagnostic>>,
+  pub open: bool,
+}
+
+impl Default for AsmEditor {
+  fn default() -> Self {
+    let base = RegionKind::Ram.default_base();
+    Self {
+      source: EXAMPLE.to_string(),
+      base,
+      base_edit: HexEdit::default(),
+      result: assembler::assemble(EXAMPLE, base),
+      open: false,
+    }
+  }
+}
+
+impl AsmEditor {
+  /// Returns the program to load once asked to.
+  pub fn ui(&mut self, ctx: &Context, running: bool) -> Option<Program> {
+    let mut load = None;
+    let mut open = self.open;
+    Window::new("Assembly").open(&mut open).default_size([480.0, 480.0]).show(
+      ctx,
+      |ui| {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+          ui.label("Base");
+          let old = self.base;
+          self.base_edit.show(ui, &mut self.base);
+          changed |= self.base != old;
+
+          let ready = !running && self.result.is_ok();
+          let button = ui.add_enabled(ready, Button::new("Load"));
+          if let Ok(program) = &self.result
+            && button.on_hover_text("Also moves pc to the entry").clicked()
+          {
+            load = Some(program.clone());
+          }
+        });
+
+        match &self.result {
+          Ok(Program { bytes, entry, .. }) => {
+            ui.weak(format!("{} bytes, entry at {entry:#x}", bytes.len()));
+          }
+          Err(errors) => {
+            ScrollArea::vertical()
+              .id_salt("diagnostics")
+              .max_height(80.0)
+              .show(ui, |ui| {
+                for diag in errors {
+                  ui.colored_label(Color32::LIGHT_RED, diag.to_string());
+                }
+              });
+          }
+        }
+        ui.separator();
+
+        ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+          let edit = TextEdit::multiline(&mut self.source)
+            .font(TextStyle::Monospace)
+            .code_editor()
+            .desired_width(f32::INFINITY)
+            .desired_rows(24);
+          changed |= ui.add(edit).changed();
+        });
+
+        // Cheap enough to redo on every edit
+        if changed {
+          self.result = assembler::assemble(&self.source, self.base);
+        }
+      },
+    );
+    self.open = open;
+    load
+  }
+}
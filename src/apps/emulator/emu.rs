@@ -1,44 +1,76 @@
 use {
-  super::asm::Asm,
+  super::{
+    asm::{self, Asm},
+    assembler::Program,
+    breakpoints::{BreakpointList, Breakpoints},
+    console::Console,
+    editor::AsmEditor,
+    gdb::Gdb,
+    history::Timeline,
+    irq::Interrupts,
+    lines::{self, Lines},
+    machine::{Bus, CSRS, ElfError, Machine, Priv},
+    map::MemoryMap,
+    runner::{Halt, Runner},
+    source::Source,
+    symbols::Symbols,
+    trace::TraceView,
+    walk::PageWalk,
+    watch::WatchList,
+  },
   egui_toast::{Toast, ToastKind},
-  std::{fs, time::Duration},
+  std::{fs, ops::Range, path::Path, time::Duration},
   tokio::time::Instant,
 };
 
 use crate::{
+  Arx,
   client::Result,
   panels::MemoryEditor,
-  repr::session::{Bus, CpuRepr, SessionRepr},
+  repr::session::{DebugRepr, LineTable, SessionRepr, Symbol},
   tx,
   widgets::HexEdit,
-  Arx,
 };
 
 use {
   crate::login::Account,
   egui::{
-    Align2, Button, Color32, Context, Direction, Key, KeyboardShortcut,
-    Modifiers, RichText, WidgetText, Window,
+    Align2, Button, Color32, ComboBox, Context, Direction, DragValue, Grid,
+    Key, KeyboardShortcut, Modifiers, RichText, ScrollArea, WidgetText, Window,
   },
   egui_extras::{Size, StripBuilder},
   egui_file_dialog::FileDialog,
   egui_toast::Toasts,
 };
 
-#[derive(Default, Clone)]
-struct State {
-  pc: u64,
-  memory: Vec<u8>,
-}
-
 #[derive(Default)]
 pub struct Panel {
   xregs: Xregs,
+  fregs: Fregs,
+  csrs: Csrs,
   dram: Memory,
   asm: Asm,
+  walk: PageWalk,
+  console: Console,
+  irq: Interrupts,
+  map: MemoryMap,
+  source: Source,
+  gdb: Gdb,
+  breakpoints: BreakpointList,
+  watch: WatchList,
+  timeline: Timeline,
+  trace: TraceView,
+  editor: AsmEditor,
 
   exit: bool,
-  state: State,
+  machine: Machine,
+  runner: Runner,
+  symbols: Symbols,
+  lines: Lines,
+  /// Entry of the loaded executable.
+  entry: Option<u64>,
+  /// Last address clicked in the instructions window.
+  cursor: Option<u64>,
   dialog: FileDialog,
 
   name: String,
@@ -74,31 +106,38 @@ impl EmulatorPanel {
   }
 
   pub fn ui(&mut self, ctx: &Context) -> bool {
+    // The machine here is stale while a copy of it runs, saved once stopped
+    let due = self.instant.elapsed() >= Duration::from_secs(1)
+      && !self.panel.runner.is_running();
     if let Some(mut arx) = self.synx.ready() {
       if let Ok(Err(err)) = arx.try_recv() {
         self
           .toasts
           .add(Toast::new().kind(ToastKind::Error).text(err.to_string()));
       }
-    } else if self.instant.elapsed() >= Duration::from_secs(1) {
+    } else if due {
       self.instant = Instant::now();
       self.sync_repr();
     }
 
+    let exit = self.panel.ui(ctx, &mut self.toasts);
+
     self.toasts.show(ctx);
 
-    self.panel.ui(ctx)
+    exit
   }
 
   pub fn sync_repr(&mut self) {
-    let State { pc, memory } = self.panel.state.clone();
     let repr = SessionRepr {
       name: self.panel.name.clone(),
-      cpu: CpuRepr {
-        pc,
-        bus: Bus { dram: memory },
-        fregs: vec![],
-        xregs: self.panel.xregs.regs.iter().map(|(x, _)| *x).collect(),
+      cpu: self.panel.machine.repr(),
+      debug: DebugRepr {
+        symbols: self.panel.symbols.repr(),
+        lines: self.panel.lines.repr(),
+        breakpoints: self.panel.runner.breakpoints.repr(),
+        watchpoints: self.panel.machine.watch.list.clone(),
+        entry: self.panel.entry,
+        code: self.panel.asm.code.clone(),
       },
       ..self.repr.clone()
     };
@@ -112,57 +151,338 @@ impl EmulatorPanel {
 }
 
 impl Panel {
-  pub fn store_repr(&mut self, SessionRepr { name, cpu, .. }: SessionRepr) {
-    self.state.memory = cpu.bus.dram;
-    self.state.pc = cpu.pc;
-    for i in 0..cpu.xregs.len().min(32) {
-      self.xregs.regs[i].0 = cpu.xregs[i];
-    }
-
-    self.asm.decode(&self.state.memory);
+  pub fn store_repr(
+    &mut self,
+    SessionRepr { name, cpu, debug, .. }: SessionRepr,
+  ) {
+    self.machine = Machine::new(cpu);
+    self.runner.history.clear();
+    self.runner.trace.clear();
+    self.set_symbols(debug.symbols);
+    self.set_lines(debug.lines);
+    self.runner.breakpoints =
+      Breakpoints::new(debug.breakpoints, &self.symbols);
+    self.machine.watch.list = debug.watchpoints;
+    self.entry = debug.entry;
+    self.asm.code = debug.code;
+
+    self.dram.changed = true;
     self.name = name;
   }
 
-  pub fn ui(&mut self, ctx: &Context) -> bool {
+  fn set_symbols(&mut self, symbols: Vec<Symbol>) {
+    self.symbols = Symbols::new(symbols);
+    let names = self.symbols.iter();
+    (self.dram.editor)
+      .set_symbols(names.map(|sym| (sym.name.clone(), sym.addr as usize)));
+    self.runner.breakpoints.relink(&self.symbols);
+    self.asm.reload();
+  }
+
+  fn set_lines(&mut self, table: LineTable) {
+    self.lines = Lines::new(table);
+    self.source.clear();
+    self.asm.reload();
+  }
+
+  pub fn step(&mut self, toasts: &mut Toasts) {
+    if let Err(err) = self.runner.step(&mut self.machine) {
+      toasts.add(Toast::new().kind(ToastKind::Warning).text(err.to_string()));
+    }
+    if let Some(hit) = self.machine.watch.hit.take() {
+      let text = self.watch.hit(hit, &self.machine);
+      toasts.add(Toast::new().kind(ToastKind::Info).text(text));
+    }
+  }
+
+  pub fn ui(&mut self, ctx: &Context, toasts: &mut Toasts) -> bool {
+    if let Some((machine, halt)) = self.runner.poll() {
+      self.machine = machine;
+      self.gdb.stopped(&halt);
+      match halt {
+        Halt::Exception(err) => {
+          let text = err.to_string();
+          toasts.add(Toast::new().kind(ToastKind::Warning).text(text));
+        }
+        Halt::Watch(hit) => {
+          let text = self.watch.hit(hit, &self.machine);
+          toasts.add(Toast::new().kind(ToastKind::Info).text(text));
+        }
+        Halt::Paused | Halt::Reached => {}
+      }
+    }
+    self.gdb.serve(&mut self.machine, &mut self.runner);
+    let running = self.runner.is_running();
+
     egui::TopBottomPanel::top("emulator-menu").show(ctx, |ui| {
       egui::menu::bar(ui, |ui| {
-        self.file_menu_button(ui);
+        self.file_menu_button(ui, toasts);
+        ui.separator();
+
+        self.run_controls(ui, toasts);
+        ui.separator();
 
         ui.text_edit_singleline(&mut self.name);
+
+        if let Some((port, attached)) = self.gdb.status() {
+          ui.separator();
+          let state = if attached { "attached" } else { "waiting" };
+          ui.label(format!("GDB on localhost:{port}, {state}"))
+            .on_hover_text(format!("target remote localhost:{port}"));
+        }
       });
     });
 
-    self.dram.ui(ctx, &mut self.state);
-    if let Some(pc) = self.asm.ui(ctx) {
+    if self.dram.ui(ctx, &mut self.machine, running) {
+      self.runner.edited();
+    }
+    // The running copy of the machine would not see it, so it waits
+    let request = if running { None } else { self.dram.editor.take_watch() };
+    if let Some((range, kind)) = request {
+      let size = (range.end - range.start) as u64;
+      self.machine.watch.add(range.start as u64, size, kind);
+      self.watch.open = true;
+    }
+    if let Some(pc) = self.asm.ui(
+      ctx,
+      &self.symbols,
+      &self.lines,
+      &mut self.runner.breakpoints,
+      self.machine.cpu.pc,
+    ) {
+      self.cursor = Some(pc as u64);
       self.dram.editor.frame_data.set_highlight_address(pc);
     }
+    if self.asm.take_marked() {
+      self.dram.changed = true;
+    }
+    let patch = if running { None } else { self.asm.take_patch() };
+    if let Some((addr, bytes)) = patch {
+      if self.machine.bus.write(addr, &bytes).is_some() {
+        self.runner.edited();
+        self.dram.changed = true;
+      } else {
+        let text =
+          format!("cannot write {:#x} bytes at {addr:#x}", bytes.len());
+        toasts.add(Toast::new().kind(ToastKind::Error).text(text));
+      }
+    }
+    self.walk.ui(ctx, &self.machine);
+    self.console.ui(ctx, &mut self.machine.bus.uart);
+    if self.map.ui(ctx, &mut self.machine, running) {
+      self.runner.edited();
+      self.dram.changed = true;
+    }
+    self.irq.ui(ctx, &self.machine);
+    self.source.ui(ctx, &self.lines, self.machine.cpu.pc);
+    self.breakpoints.ui(
+      ctx,
+      &mut self.runner.breakpoints,
+      &self.symbols,
+      &self.lines,
+      running,
+    );
+    // Edits of the gutter or the list reach a run going on
+    self.runner.sync();
+    (self.watch).ui(ctx, &mut self.machine.watch, &self.symbols, running);
+    let history = &mut self.runner.history;
+    if self.timeline.ui(ctx, history, &mut self.machine, running) {
+      self.dram.changed = true;
+    }
+    self.trace.ui(ctx, &mut self.runner.trace, running, toasts);
+    if let Some(program) = self.editor.ui(ctx, running) {
+      self.load_program(program, toasts);
+    }
+    let cpu = self.machine.cpu.clone();
     Window::new("Registers")
       .collapsible(false)
       .fixed_size([370.0, 400.0])
       .show(ctx, |ui| {
-        self.xregs.ui(ui);
+        ui.add_enabled_ui(!running, |ui| {
+          self.xregs.ui(ui, &mut self.machine.cpu.xregs);
+        });
       });
+    Window::new("Float registers")
+      .collapsible(false)
+      .default_size([480.0, 400.0])
+      .show(ctx, |ui| {
+        ui.add_enabled_ui(!running, |ui| {
+          let cpu = &mut self.machine.cpu;
+          self.fregs.ui(ui, &mut cpu.fregs, &mut cpu.fcsr);
+        });
+      });
+    Window::new("CSRs").collapsible(false).default_size([360.0, 400.0]).show(
+      ctx,
+      |ui| {
+        ui.add_enabled_ui(!running, |ui| {
+          self.csrs.ui(ui, &mut self.machine);
+        });
+      },
+    );
+    if self.machine.cpu != cpu {
+      self.runner.edited();
+    }
 
     self.dialog.update(ctx);
 
     if let Some(path) = self.dialog.take_selected() {
-      match fs::read(path) {
-        Ok(bytes) => {
-          self.asm.decode(&bytes);
-          self.state.memory = bytes;
+      match fs::read(&path) {
+        Ok(bytes) => self.load(&path, &bytes, toasts),
+        Err(err) => {
+          let text = format!("{}: {err}", path.display());
+          toasts.add(Toast::new().kind(ToastKind::Error).text(text));
         }
-        Err(err) => {}
       }
+      self.dram.changed = true;
     }
 
+    // Stores may have touched the code, or `pc` jumped where decoding did
+    // not go
+    self.dram.track(&self.machine.bus);
+    if self.asm.unreached(self.machine.cpu.pc) {
+      self.dram.changed = true;
+    }
     self.dram.if_changed(|| {
-      self.asm.decode(&self.state.memory);
+      let pc = self.machine.cpu.pc;
+      if let Some(code) = self.machine.bus.code(pc) {
+        let (symbols, lines) = (&self.symbols, &self.lines);
+        let seeds = asm::seeds(pc, self.entry, symbols);
+        self.asm.decode(code.base, &code.data, &seeds, symbols, lines);
+      }
     });
 
     self.exit
   }
 
-  fn file_menu_button(&mut self, ui: &mut egui::Ui) {
+  /// Load a file into the machine along with whatever debug info it has.
+  fn load(&mut self, path: &Path, bytes: &[u8], toasts: &mut Toasts) {
+    let elf = match self.machine.load_image(bytes) {
+      Ok(elf) => elf,
+      Err(err) => {
+        let text = format!("{}: {err}", path.display());
+        toasts.add(Toast::new().kind(ToastKind::Error).text(text));
+        return;
+      }
+    };
+    let (mut symbols, mut table) = (vec![], LineTable::default());
+    let entry = elf.as_ref().map(|elf| elf.entry);
+    if let Some(elf) = elf {
+      // The program runs without debug info, so only warn about it
+      let mut warn = |err: ElfError| {
+        let text = format!("{}: {err}", path.display());
+        toasts.add(Toast::new().kind(ToastKind::Warning).text(text));
+      };
+      symbols = elf.symbols().unwrap_or_else(|err| {
+        warn(err);
+        vec![]
+      });
+      table = elf.lines().unwrap_or_else(|err| {
+        warn(err);
+        LineTable::default()
+      });
+    }
+    let dir = path.parent().unwrap_or(Path::new(""));
+    self.entry = entry;
+    self.asm.code.clear();
+    self.runner.history.clear();
+    self.runner.trace.clear();
+    self.set_symbols(symbols);
+    self.set_lines(lines::resolve(table, dir));
+  }
+
+  /// Place an assembled program in memory and point pc at it.
+  fn load_program(&mut self, program: Program, toasts: &mut Toasts) {
+    let Program { base, bytes, labels, entry } = program;
+    if self.machine.bus.write(base, &bytes).is_none() {
+      let text = format!(
+        "{:#x} bytes at {base:#x} do not fit in a memory region",
+        bytes.len()
+      );
+      toasts.add(Toast::new().kind(ToastKind::Error).text(text));
+      return;
+    }
+    self.machine.cpu.pc = entry;
+    self.entry = Some(entry);
+    // The program shadows whatever debug info covered its range
+    let range = base..base + bytes.len() as u64;
+    let mut symbols = self.symbols.repr();
+    symbols.retain(|sym| !range.contains(&sym.addr));
+    symbols.extend(labels);
+    let mut table = self.lines.repr();
+    let len = table.rows.len();
+    table.rows.retain(|&(addr, ..)| !range.contains(&addr));
+    if table.rows.len() != len {
+      let idx = table.rows.partition_point(|&(addr, ..)| addr < base);
+      table.rows.insert(idx, (base, 0, 0));
+    }
+
+    self.runner.history.clear();
+    self.runner.trace.clear();
+    self.set_symbols(symbols);
+    self.set_lines(table);
+    self.dram.changed = true;
+  }
+
+  fn run_controls(&mut self, ui: &mut egui::Ui, toasts: &mut Toasts) {
+    let running = self.runner.is_running();
+
+    ui.add_enabled_ui(!running, |ui| {
+      button(ui, "▶ Run", (Modifiers::NONE, Key::F5), |_| {
+        self.runner.run(self.machine.clone(), None);
+      });
+    });
+    ui.add_enabled_ui(running, |ui| {
+      button(ui, "⏸ Pause", (Modifiers::NONE, Key::F6), |_| {
+        self.runner.pause();
+      });
+    });
+    ui.add_enabled_ui(!running, |ui| {
+      button(ui, "Step", (Modifiers::NONE, Key::F7), |_| {
+        self.step(toasts);
+      });
+      button(ui, "Step back", (Modifiers::SHIFT, Key::F7), |_| {
+        if self.runner.history.back(&mut self.machine) {
+          self.dram.changed = true;
+        } else {
+          toasts.add(Toast::new().kind(ToastKind::Info).text(NO_HISTORY));
+        }
+      });
+      button(ui, "Reverse continue", (Modifiers::SHIFT, Key::F5), |_| {
+        let breakpoints = &self.runner.breakpoints;
+        let history = &mut self.runner.history;
+        if !history.reverse(&mut self.machine, |m| breakpoints.stops(m)) {
+          toasts.add(Toast::new().kind(ToastKind::Info).text(NO_HISTORY));
+        }
+        self.dram.changed = true;
+      });
+      button(ui, "Step over", (Modifiers::NONE, Key::F8), |_| {
+        match self.machine.call_return() {
+          Some(ret) => self.runner.run(self.machine.clone(), Some(ret)),
+          None => self.step(toasts),
+        }
+      });
+      button(ui, "Run to cursor", (Modifiers::NONE, Key::F4), |_| {
+        if let Some(cursor) = self.cursor {
+          self.runner.run(self.machine.clone(), Some(cursor));
+        }
+      });
+    });
+
+    ui.add(
+      DragValue::new(&mut self.runner.ips)
+        .range(1..=u32::MAX)
+        .speed(1000)
+        .suffix(" ips"),
+    )
+    .on_hover_text("Instructions per second while running");
+
+    if running {
+      ui.spinner();
+      ui.ctx().request_repaint();
+    }
+  }
+
+  fn file_menu_button(&mut self, ui: &mut egui::Ui, toasts: &mut Toasts) {
     let open_shortcut = KeyboardShortcut::new(Modifiers::CTRL, Key::O);
 
     if ui.input_mut(|i| i.consume_shortcut(&open_shortcut)) {
@@ -182,26 +502,6 @@ impl Panel {
         ui.separator();
       }
 
-      fn button(
-        ui: &mut egui::Ui,
-        name: impl Into<WidgetText>,
-        short: impl Into<Option<(Modifiers, Key)>>,
-        clicked: impl FnOnce(&mut egui::Ui),
-      ) {
-        let mut button = Button::new(name);
-        if let Some((md, key)) = short.into() {
-          let short = KeyboardShortcut::new(md, key);
-          button = button.shortcut_text(ui.ctx().format_shortcut(&short));
-
-          if ui.input_mut(|i| i.consume_shortcut(&short)) {
-            return clicked(ui);
-          }
-        }
-        if ui.add(button).clicked() {
-          return clicked(ui);
-        }
-      }
-
       button(ui, "Open file", (Modifiers::CTRL, Key::O), |_| {
         self.dialog.select_file();
       });
@@ -210,6 +510,61 @@ impl Panel {
         self.asm.open = !self.asm.open;
       });
 
+      button(ui, "Toggle assembly editor", (Modifiers::ALT, Key::E), |_| {
+        self.editor.open = !self.editor.open;
+      });
+
+      button(ui, "Toggle page walk", (Modifiers::ALT, Key::P), |_| {
+        self.walk.open = !self.walk.open;
+      });
+
+      button(ui, "Toggle source", (Modifiers::ALT, Key::S), |_| {
+        self.source.open = !self.source.open;
+      });
+
+      button(ui, "Toggle breakpoints", (Modifiers::ALT, Key::B), |_| {
+        self.breakpoints.open = !self.breakpoints.open;
+      });
+
+      button(ui, "Toggle watchpoints", (Modifiers::ALT, Key::W), |_| {
+        self.watch.open = !self.watch.open;
+      });
+
+      button(ui, "Toggle timeline", (Modifiers::ALT, Key::T), |_| {
+        self.timeline.open = !self.timeline.open;
+      });
+
+      button(ui, "Toggle trace", (Modifiers::ALT, Key::R), |_| {
+        self.trace.open = !self.trace.open;
+      });
+
+      button(ui, "Toggle console", (Modifiers::ALT, Key::C), |_| {
+        self.console.open = !self.console.open;
+      });
+
+      button(ui, "Toggle interrupts", (Modifiers::ALT, Key::I), |_| {
+        self.irq.open = !self.irq.open;
+      });
+
+      button(ui, "Toggle memory map", (Modifiers::ALT, Key::M), |_| {
+        self.map.open = !self.map.open;
+      });
+
+      ui.horizontal(|ui| {
+        button(ui, "Toggle GDB server", (Modifiers::ALT, Key::G), |ui| {
+          if self.gdb.status().is_some() {
+            self.gdb.stop();
+          } else if let Err(err) = self.gdb.start(ui.ctx()) {
+            let text = format!("GDB server: {err}");
+            toasts.add(Toast::new().kind(ToastKind::Error).text(text));
+          }
+        });
+        ui.add_enabled(
+          self.gdb.status().is_none(),
+          DragValue::new(&mut self.gdb.port).range(1..=u16::MAX).prefix(":"),
+        );
+      });
+
       button(ui, "Leave session", (Modifiers::ALT, Key::X), |_| {
         self.exit = true;
       });
@@ -221,23 +576,52 @@ impl Panel {
   }
 }
 
+const NO_HISTORY: &str = "Reached the start of the recorded history";
+
+fn button(
+  ui: &mut egui::Ui,
+  name: impl Into<WidgetText>,
+  short: impl Into<Option<(Modifiers, Key)>>,
+  clicked: impl FnOnce(&mut egui::Ui),
+) {
+  let mut button = Button::new(name);
+  if let Some((md, key)) = short.into() {
+    let short = KeyboardShortcut::new(md, key);
+    button = button.shortcut_text(ui.ctx().format_shortcut(&short));
+
+    // Disabled controls must not steal their shortcut
+    if ui.is_enabled() && ui.input_mut(|i| i.consume_shortcut(&short)) {
+      return clicked(ui);
+    }
+  }
+  if ui.add(button).clicked() {
+    clicked(ui);
+  }
+}
+
 pub struct Memory {
   editor: MemoryEditor,
+  /// Memory regions the editor ranges were built from.
+  ranges: Vec<(String, Range<usize>)>,
   changed: bool,
+  /// `Bus::writes` as last tracked.
+  writes: u64,
 }
 
 impl Default for Memory {
   fn default() -> Self {
-    let editor = MemoryEditor::new()
-      .with_address_range("All", 0..0x100000) // 1MB dram
-      .with_address_range("Boot", 0xFF00..0xFF80)
-      .with_window_title("Memory");
-
-    Self { editor, changed: false }
+    let editor = MemoryEditor::new().with_window_title("Memory");
+    Self { editor, ranges: vec![], changed: false, writes: 0 }
   }
 }
 
 impl Memory {
+  /// Note the writes to memory since the last call.
+  pub fn track(&mut self, bus: &Bus) {
+    self.changed |= bus.writes != self.writes;
+    self.writes = bus.writes;
+  }
+
   pub fn if_changed(&mut self, f: impl FnOnce()) {
     if self.changed {
       self.changed = false;
@@ -245,38 +629,83 @@ impl Memory {
     }
   }
 
-  pub fn ui(&mut self, ctx: &Context, state: &mut State) {
+  /// Follow the memory map, the editor cannot forget a range by itself.
+  fn remap(&mut self, bus: &Bus) {
+    let ranges: Vec<_> = (bus.regions.iter())
+      .filter(|region| region.kind.is_memory())
+      .map(|region| {
+        (region.name.clone(), region.base as usize..region.end() as usize)
+      })
+      .collect();
+    if ranges == self.ranges {
+      return;
+    }
+
+    let mut options = self.editor.options.clone();
+    if !ranges.iter().any(|(name, _)| *name == options.selected_address_range) {
+      options.selected_address_range.clear();
+    }
+    let mut editor = (ranges.iter()).fold(
+      MemoryEditor::new().with_options(options).with_window_title("Memory"),
+      |editor, (name, range)| editor.with_address_range(name, range.clone()),
+    );
+    editor.frame_data = self.editor.frame_data.clone();
+    self.editor = editor;
+    self.ranges = ranges;
+  }
+
+  /// Returns whether memory was written.
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    machine: &mut Machine,
+    running: bool,
+  ) -> bool {
+    self.remap(&machine.bus);
+    if self.ranges.is_empty() {
+      return false;
+    }
+    let read =
+      |bus: &mut Bus, addr| bus.peek(addr as u64, 1).map(|byte| byte as u8);
+    // The running copy of the machine would not see the edits
+    if running {
+      self.editor.window_ui_read_only(ctx, &mut machine.bus, read);
+      return false;
+    }
+    let mut written = false;
     self.editor.window_ui(
       ctx,
-      &mut state.memory,
-      |mem, addr| mem.get(addr).copied(),
-      |mem, addr, val| {
-        self.changed = true;
-        if addr < mem.len() {
-          mem[addr] = val
-        }
+      &mut machine.bus,
+      read,
+      |bus, addr, val| {
+        written = true;
+        // The page tables may have changed under the cached translations
+        machine.tlb.flush();
+        bus.poke(addr as u64, 1, val as u64);
       },
       |pc| {
-        state.pc = pc as u64;
+        machine.cpu.pc = pc as u64;
       },
     );
+    self.changed |= written;
+    written
   }
 }
 
 #[derive(Default)]
 pub struct Xregs {
-  regs: [(u64, HexEdit); 32],
+  edits: [HexEdit; 32],
 }
 
 impl Xregs {
-  pub fn ui(&mut self, ui: &mut egui::Ui) {
+  pub fn ui(&mut self, ui: &mut egui::Ui, regs: &mut [u64; 32]) {
     StripBuilder::new(ui).sizes(Size::remainder(), 16).vertical(|mut strip| {
-      let (chunks, _) = self.regs.as_chunks_mut::<16>();
+      let (chunks, _) = self.edits.as_chunks_mut::<16>();
       for i in 0..16 {
         strip.strip(|builder| {
           builder.sizes(Size::remainder(), 2).horizontal(|mut strip| {
             for (xi, chunk) in chunks.iter_mut().enumerate() {
-              let (x, edit) = &mut chunk[i];
+              let (x, edit) = (&mut regs[xi * 16 + i], &mut chunk[i]);
 
               let idx = xi * 16 + i;
               strip.cell(|ui| {
@@ -299,3 +728,112 @@ impl Xregs {
     });
   }
 }
+
+#[derive(Default)]
+pub struct Fregs {
+  edits: [HexEdit; 32],
+}
+
+impl Fregs {
+  const ROUNDING: [&str; 5] = ["rne", "rtz", "rdn", "rup", "rmm"];
+  const FLAGS: [&str; 5] = ["NX", "UF", "OF", "DZ", "NV"];
+
+  pub fn ui(
+    &mut self,
+    ui: &mut egui::Ui,
+    regs: &mut [u64; 32],
+    fcsr: &mut u32,
+  ) {
+    ui.horizontal(|ui| {
+      let mut frm = (*fcsr >> 5 & 0b111) as usize;
+      ComboBox::from_label("frm")
+        .selected_text(*Self::ROUNDING.get(frm).unwrap_or(&"invalid"))
+        .show_ui(ui, |ui| {
+          for (mode, name) in Self::ROUNDING.iter().enumerate() {
+            ui.selectable_value(&mut frm, mode, *name);
+          }
+        });
+      *fcsr = *fcsr & !0xe0 | (frm as u32) << 5;
+
+      ui.separator();
+      // Accrued flags, highest bit first like the spec draws them
+      for (bit, name) in Self::FLAGS.iter().enumerate().rev() {
+        let mut set = *fcsr & 1 << bit != 0;
+        if ui.toggle_value(&mut set, *name).changed() {
+          *fcsr ^= 1 << bit;
+        }
+      }
+    });
+    ui.separator();
+
+    ScrollArea::vertical().show(ui, |ui| {
+      Grid::new("fregs").striped(true).show(ui, |ui| {
+        for (idx, (reg, edit)) in
+          regs.iter_mut().zip(&mut self.edits).enumerate()
+        {
+          ui.label(
+            RichText::new(format!("f{idx:02}"))
+              .color(Color32::from_rgb(0, 140, 140)),
+          );
+          edit.show(ui, reg);
+          ui.monospace(format!("{:e}", f64::from_bits(*reg)));
+          // Singles live in the low half of a NaN-boxed register
+          if *reg >> 32 == 0xffff_ffff {
+            ui.monospace(format!("{:e}", f32::from_bits(*reg as u32)));
+          } else {
+            ui.weak("-");
+          }
+          ui.end_row();
+        }
+      });
+    });
+  }
+}
+
+#[derive(Default)]
+pub struct Csrs {
+  edits: Vec<HexEdit>,
+}
+
+impl Csrs {
+  pub fn ui(&mut self, ui: &mut egui::Ui, machine: &mut Machine) {
+    self.edits.resize_with(CSRS.len(), HexEdit::default);
+
+    ui.horizontal(|ui| {
+      ComboBox::from_label("privilege")
+        .selected_text(format!("{:?}", machine.cpu.mode))
+        .show_ui(ui, |ui| {
+          for mode in [Priv::Machine, Priv::Supervisor, Priv::User] {
+            ui.selectable_value(
+              &mut machine.cpu.mode,
+              mode,
+              format!("{mode:?}"),
+            );
+          }
+        });
+    });
+    ui.separator();
+
+    ScrollArea::vertical().show(ui, |ui| {
+      Grid::new("csrs").striped(true).show(ui, |ui| {
+        for (&(addr, name), edit) in CSRS.iter().zip(&mut self.edits) {
+          let Some(old) = machine.csr_read(addr) else { continue };
+
+          ui.label(RichText::new(name).color(Color32::from_rgb(0, 140, 140)))
+            .on_hover_text(format!("{addr:#05x}"));
+          // The top two address bits mark read-only CSRs
+          if addr >> 10 == 0b11 {
+            ui.monospace(format!("0x{old:016x}"));
+          } else {
+            let mut val = old;
+            edit.show(ui, &mut val);
+            if val != old {
+              machine.csr_write(addr, val);
+            }
+          }
+          ui.end_row();
+        }
+      });
+    });
+  }
+}
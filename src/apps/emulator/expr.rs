@@ -0,0 +1,514 @@
+use super::{
+  machine::{CSRS, FREGS, Machine, XREGS},
+  symbols::Symbols,
+};
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ExprError {
+  #[error("expression is empty")]
+  Empty,
+  #[error("unexpected `{0}`")]
+  Unexpected(String),
+  #[error("expression ends too early")]
+  End,
+  #[error("invalid number `{0}`")]
+  Number(String),
+  #[error("unknown name `{0}`")]
+  Unknown(String),
+  #[error("division by zero")]
+  DivZero,
+  #[error("nothing mapped at {0:#x}")]
+  Unmapped(u64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reg {
+  X(usize),
+  F(usize),
+  Pc,
+  Csr(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unary {
+  Neg,
+  Not,
+  BitNot,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Binary {
+  Mul,
+  Div,
+  Rem,
+  Add,
+  Sub,
+  Shl,
+  Shr,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Eq,
+  Ne,
+  And,
+  Xor,
+  Or,
+  LogicAnd,
+  LogicOr,
+}
+
+impl Binary {
+  /// Binding power, C precedence from `||` up to `*`.
+  fn power(self) -> u8 {
+    match self {
+      Self::LogicOr => 1,
+      Self::LogicAnd => 2,
+      Self::Or => 3,
+      Self::Xor => 4,
+      Self::And => 5,
+      Self::Eq | Self::Ne => 6,
+      Self::Lt | Self::Le | Self::Gt | Self::Ge => 7,
+      Self::Shl | Self::Shr => 8,
+      Self::Add | Self::Sub => 9,
+      Self::Mul | Self::Div | Self::Rem => 10,
+    }
+  }
+
+  fn is_comparison(self) -> bool {
+    matches!(self, Self::Lt | Self::Le | Self::Gt | Self::Ge)
+  }
+
+  fn apply(self, lhs: u64, rhs: u64) -> Result<u64, ExprError> {
+    Ok(match self {
+      Self::Mul => lhs.wrapping_mul(rhs),
+      Self::Div => lhs.checked_div(rhs).ok_or(ExprError::DivZero)?,
+      Self::Rem => lhs.checked_rem(rhs).ok_or(ExprError::DivZero)?,
+      Self::Add => lhs.wrapping_add(rhs),
+      Self::Sub => lhs.wrapping_sub(rhs),
+      Self::Shl => lhs.checked_shl(rhs as u32).unwrap_or(0),
+      Self::Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+      Self::Lt => (lhs < rhs) as u64,
+      Self::Le => (lhs <= rhs) as u64,
+      Self::Gt => (lhs > rhs) as u64,
+      Self::Ge => (lhs >= rhs) as u64,
+      Self::Eq => (lhs == rhs) as u64,
+      Self::Ne => (lhs != rhs) as u64,
+      Self::And => lhs & rhs,
+      Self::Xor => lhs ^ rhs,
+      Self::Or => lhs | rhs,
+      Self::LogicAnd | Self::LogicOr => unreachable!("short-circuited"),
+    })
+  }
+}
+
+/// Parsed expression over the registers and memory of a [`Machine`].
+///
+/// Values are 64-bit integers that wrap around, comparisons and logic
+/// operators give 0 or 1. Symbols are resolved when parsing.
+///
+/// Like C, `<`, `<=`, `>` and `>=` compare signed only when both sides are:
+/// numbers and casts to `i8` up to `i64` are signed, while registers, memory
+/// and casts to `u8` up to `u64` are not. So unsigned is the default and
+/// `(i32)mem32[sp] < 0` is how a negative `int` is caught.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Num(u64),
+  Reg(Reg),
+  /// `mem8[..]` up to `mem64[..]`, the width in bytes.
+  Mem(usize, Box<Expr>),
+  /// `(i32)` and the like, the width in bytes and whether it is signed.
+  Cast(usize, bool, Box<Expr>),
+  Unary(Unary, Box<Expr>),
+  Binary(Binary, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+  Num(u64),
+  Name(String),
+  Op(&'static str),
+}
+
+// Longer operators first, so that `<=` is not taken for `<`
+const OPS: [&str; 24] = [
+  "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<",
+  ">", "&", "^", "|", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ExprError> {
+  let mut tokens = vec![];
+  let mut chars = text.char_indices().peekable();
+  while let Some(&(start, ch)) = chars.peek() {
+    if ch.is_whitespace() {
+      chars.next();
+    } else if ch.is_ascii_alphanumeric() || matches!(ch, '_' | '.' | '$') {
+      let mut end = start;
+      while let Some(&(idx, ch)) = chars.peek() {
+        if !(ch.is_ascii_alphanumeric() || matches!(ch, '_' | '.' | '$')) {
+          break;
+        }
+        end = idx + ch.len_utf8();
+        chars.next();
+      }
+      let word = &text[start..end];
+      tokens.push(if ch.is_ascii_digit() {
+        Token::Num(number(word)?)
+      } else {
+        Token::Name(word.to_string())
+      });
+    } else {
+      let rest = &text[start..];
+      let op = OPS
+        .iter()
+        .find(|op| rest.starts_with(**op))
+        .ok_or_else(|| ExprError::Unexpected(ch.to_string()))?;
+      for _ in 0..op.len() {
+        chars.next();
+      }
+      tokens.push(Token::Op(op));
+    }
+  }
+  Ok(tokens)
+}
+
+fn number(word: &str) -> Result<u64, ExprError> {
+  let digits = word.replace('_', "");
+  let (digits, radix) = match digits.get(..2) {
+    Some("0x" | "0X") => (&digits[2..], 16),
+    Some("0b" | "0B") => (&digits[2..], 2),
+    _ => (&digits[..], 10),
+  };
+  u64::from_str_radix(digits, radix)
+    .map_err(|_| ExprError::Number(word.to_string()))
+}
+
+fn register(name: &str) -> Option<Reg> {
+  let index = |prefix: &str| {
+    let idx: usize = name.strip_prefix(prefix)?.parse().ok()?;
+    (idx < 32).then_some(idx)
+  };
+  if name == "pc" {
+    Some(Reg::Pc)
+  } else if let Some(idx) = index("x") {
+    Some(Reg::X(idx))
+  } else if let Some(idx) = index("f") {
+    Some(Reg::F(idx))
+  } else if let Some(idx) = XREGS.iter().position(|&abi| abi == name) {
+    Some(Reg::X(idx))
+  } else if name == "s0" {
+    Some(Reg::X(8))
+  } else if let Some(idx) = FREGS.iter().position(|&abi| abi == name) {
+    Some(Reg::F(idx))
+  } else {
+    CSRS.iter().find(|&&(_, csr)| csr == name).map(|&(addr, _)| Reg::Csr(addr))
+  }
+}
+
+struct Parser<'a> {
+  tokens: std::vec::IntoIter<Token>,
+  peeked: Option<Token>,
+  symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+  fn peek(&mut self) -> Option<&Token> {
+    if self.peeked.is_none() {
+      self.peeked = self.tokens.next();
+    }
+    self.peeked.as_ref()
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    self.peek();
+    self.peeked.take()
+  }
+
+  fn expect(&mut self, op: &str) -> Result<(), ExprError> {
+    match self.next() {
+      Some(Token::Op(found)) if found == op => Ok(()),
+      Some(token) => Err(unexpected(token)),
+      None => Err(ExprError::End),
+    }
+  }
+
+  fn binary(&mut self) -> Option<Binary> {
+    let Some(Token::Op(op)) = self.peek() else { return None };
+    Some(match *op {
+      "*" => Binary::Mul,
+      "/" => Binary::Div,
+      "%" => Binary::Rem,
+      "+" => Binary::Add,
+      "-" => Binary::Sub,
+      "<<" => Binary::Shl,
+      ">>" => Binary::Shr,
+      "<" => Binary::Lt,
+      "<=" => Binary::Le,
+      ">" => Binary::Gt,
+      ">=" => Binary::Ge,
+      "==" => Binary::Eq,
+      "!=" => Binary::Ne,
+      "&" => Binary::And,
+      "^" => Binary::Xor,
+      "|" => Binary::Or,
+      "&&" => Binary::LogicAnd,
+      "||" => Binary::LogicOr,
+      _ => return None,
+    })
+  }
+
+  /// Operators binding tighter than `power`, by precedence climbing.
+  fn expr(&mut self, power: u8) -> Result<Expr, ExprError> {
+    let mut lhs = self.unary()?;
+    while let Some(op) = self.binary().filter(|op| op.power() > power) {
+      self.next();
+      let rhs = self.expr(op.power())?;
+      lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+  }
+
+  fn unary(&mut self) -> Result<Expr, ExprError> {
+    let token = self.next().ok_or(ExprError::End)?;
+    let op = match token {
+      Token::Num(num) => return Ok(Expr::Num(num)),
+      Token::Name(name) => return self.name(name),
+      Token::Op("(") => {
+        if let Some((width, signed)) = self.cast() {
+          self.next();
+          self.expect(")")?;
+          return Ok(Expr::Cast(width, signed, Box::new(self.unary()?)));
+        }
+        let expr = self.expr(0)?;
+        self.expect(")")?;
+        return Ok(expr);
+      }
+      Token::Op("-") => Unary::Neg,
+      Token::Op("!") => Unary::Not,
+      Token::Op("~") => Unary::BitNot,
+      token => return Err(unexpected(token)),
+    };
+    Ok(Expr::Unary(op, Box::new(self.unary()?)))
+  }
+
+  /// Type of a cast after its `(`, not taken yet.
+  fn cast(&mut self) -> Option<(usize, bool)> {
+    let Some(Token::Name(name)) = self.peek() else { return None };
+    let width = match name.as_str() {
+      "i8" | "u8" => 1,
+      "i16" | "u16" => 2,
+      "i32" | "u32" => 4,
+      "i64" | "u64" => 8,
+      _ => return None,
+    };
+    Some((width, name.starts_with('i')))
+  }
+
+  fn name(&mut self, name: String) -> Result<Expr, ExprError> {
+    let width = match name.as_str() {
+      "mem8" => Some(1),
+      "mem16" => Some(2),
+      "mem32" => Some(4),
+      "mem64" => Some(8),
+      _ => None,
+    };
+    if let Some(width) = width {
+      self.expect("[")?;
+      let addr = self.expr(0)?;
+      self.expect("]")?;
+      return Ok(Expr::Mem(width, Box::new(addr)));
+    }
+    if let Some(reg) = register(&name) {
+      return Ok(Expr::Reg(reg));
+    }
+    let symbol = self.symbols.iter().find(|symbol| symbol.name == name);
+    symbol.map(|symbol| Expr::Num(symbol.addr)).ok_or(ExprError::Unknown(name))
+  }
+}
+
+fn unexpected(token: Token) -> ExprError {
+  ExprError::Unexpected(match token {
+    Token::Num(num) => num.to_string(),
+    Token::Name(name) => name,
+    Token::Op(op) => op.to_string(),
+  })
+}
+
+impl Expr {
+  /// Parse `text` like `x10 == 0 && mem32[sp + 8] > 3`, with registers by
+  /// number or ABI name, CSRs by name and the addresses of `symbols`.
+  pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, ExprError> {
+    let tokens = tokenize(text)?;
+    if tokens.is_empty() {
+      return Err(ExprError::Empty);
+    }
+    let mut parser =
+      Parser { tokens: tokens.into_iter(), peeked: None, symbols };
+    let expr = parser.expr(0)?;
+    match parser.next() {
+      Some(token) => Err(unexpected(token)),
+      None => Ok(expr),
+    }
+  }
+
+  /// Whether the value is signed, for comparisons.
+  fn is_signed(&self) -> bool {
+    match self {
+      Expr::Num(_) => true,
+      Expr::Reg(_) | Expr::Mem(..) => false,
+      &Expr::Cast(_, signed, _) => signed,
+      Expr::Unary(Unary::Not, _) => true,
+      Expr::Unary(_, expr) => expr.is_signed(),
+      Expr::Binary(op, lhs, rhs) => match op {
+        Binary::Shl | Binary::Shr => lhs.is_signed(),
+        Binary::Mul
+        | Binary::Div
+        | Binary::Rem
+        | Binary::Add
+        | Binary::Sub
+        | Binary::And
+        | Binary::Xor
+        | Binary::Or => lhs.is_signed() && rhs.is_signed(),
+        // Truth values
+        _ => true,
+      },
+    }
+  }
+
+  /// Value of the expression, memory is read at virtual addresses like the
+  /// debugger sees it.
+  pub fn eval(&self, machine: &Machine) -> Result<u64, ExprError> {
+    Ok(match self {
+      &Expr::Num(num) => num,
+      &Expr::Reg(reg) => match reg {
+        Reg::X(idx) => machine.cpu.xregs[idx],
+        Reg::F(idx) => machine.cpu.fregs[idx],
+        Reg::Pc => machine.cpu.pc,
+        Reg::Csr(addr) => machine.csr_read(addr).unwrap_or_default(),
+      },
+      Expr::Mem(width, addr) => {
+        let addr = addr.eval(machine)?;
+        (machine.debug_paddr(addr))
+          .and_then(|paddr| machine.bus.peek(paddr, *width))
+          .ok_or(ExprError::Unmapped(addr))?
+      }
+      &Expr::Cast(width, signed, ref expr) => {
+        let shift = 64 - 8 * width as u32;
+        let val = expr.eval(machine)? << shift;
+        if signed { ((val as i64) >> shift) as u64 } else { val >> shift }
+      }
+      Expr::Unary(op, expr) => {
+        let val = expr.eval(machine)?;
+        match op {
+          Unary::Neg => val.wrapping_neg(),
+          Unary::Not => (val == 0) as u64,
+          Unary::BitNot => !val,
+        }
+      }
+      Expr::Binary(op, lhs, rhs) => {
+        let signed = op.is_comparison() && lhs.is_signed() && rhs.is_signed();
+        let (lhs, rhs) = (lhs.eval(machine)?, rhs);
+        match op {
+          Binary::LogicAnd => (lhs != 0 && rhs.eval(machine)? != 0) as u64,
+          Binary::LogicOr => (lhs != 0 || rhs.eval(machine)? != 0) as u64,
+          // Flipping the sign bits orders signed values as unsigned ones
+          op if signed => {
+            const SIGN: u64 = 1 << 63;
+            op.apply(lhs ^ SIGN, rhs.eval(machine)? ^ SIGN)?
+          }
+          op => op.apply(lhs, rhs.eval(machine)?)?,
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::repr::session::Symbol};
+
+  fn eval(text: &str, machine: &Machine) -> Result<u64, ExprError> {
+    let symbols = Symbols::new(vec![Symbol {
+      name: "main".into(),
+      addr: Machine::RAM + 0x40,
+      size: 0,
+      code: true,
+    }]);
+    Expr::parse(text, &symbols)?.eval(machine)
+  }
+
+  #[test]
+  fn precedence() {
+    let machine = Machine::default();
+    let eval = |text| eval(text, &machine).unwrap();
+    assert_eq!(eval("1 + 2 * 3"), 7);
+    assert_eq!(eval("(1 + 2) * 3"), 9);
+    assert_eq!(eval("10 - 4 - 3"), 3);
+    assert_eq!(eval("1 << 2 + 1"), 8);
+    assert_eq!(eval("6 & 3 == 3"), 0);
+    assert_eq!(eval("1 || 0 && 0"), 1);
+    assert_eq!(eval("1 | 6 ^ 3 & 5"), 7);
+    assert_eq!(eval("-1"), u64::MAX);
+    assert_eq!(eval("!5 + ~0"), u64::MAX);
+    assert_eq!(eval("0x10 + 0b11 + 1_000"), 1019);
+    assert_eq!(eval("main + 4"), Machine::RAM + 0x44);
+  }
+
+  #[test]
+  fn short_circuit() {
+    let machine = Machine::default();
+    assert_eq!(eval("0 && 1 / 0", &machine), Ok(0));
+    assert_eq!(eval("1 || mem8[0]", &machine), Ok(1));
+    assert_eq!(eval("1 / 0", &machine), Err(ExprError::DivZero));
+    assert_eq!(eval("1 % (2 - 2)", &machine), Err(ExprError::DivZero));
+  }
+
+  #[test]
+  fn signedness() {
+    let mut machine = Machine::default();
+    machine.cpu.xregs[10] = -1_i64 as u64;
+    let eval = |text| eval(text, &machine).unwrap();
+    assert_eq!(eval("-1 < 0"), 1);
+    assert_eq!(eval("a0 < 0"), 0);
+    assert_eq!(eval("(i64)a0 < 0"), 1);
+    assert_eq!(eval("(i32)a0 < 1 + 1"), 1);
+    assert_eq!(eval("(u32)a0 >= 0"), 1);
+    assert_eq!(eval("(u32)a0"), 0xffff_ffff);
+    assert_eq!(eval("(i8)0x80"), 0xffff_ffff_ffff_ff80);
+    assert_eq!(eval("(u8)0x1ff"), 0xff);
+    assert_eq!(eval("(i16)a0 == -1"), 1);
+  }
+
+  #[test]
+  fn registers_and_memory() {
+    let mut machine = Machine::with_code(&[0xdead_beef, 0x8000_0000]);
+    machine.cpu.xregs[2] = Machine::RAM;
+    machine.cpu.xregs[8] = 3;
+    machine.cpu.fregs[1] = 7;
+    machine.cpu.csrs.mscratch = 9;
+    let eval = |text| eval(text, &machine);
+    assert_eq!(eval("x8 + s0 + fp"), Ok(9));
+    assert_eq!(eval("f1 + ft1 + mscratch"), Ok(23));
+    assert_eq!(eval("pc == sp"), Ok(1));
+    assert_eq!(eval("mem32[sp]"), Ok(0xdead_beef));
+    assert_eq!(eval("mem16[sp + 2]"), Ok(0xdead));
+    assert_eq!(eval("mem64[sp]"), Ok(0x8000_0000_dead_beef));
+    assert_eq!(eval("(i32)mem32[sp + 4] < 0"), Ok(1));
+    assert_eq!(eval("mem8[8]"), Err(ExprError::Unmapped(8)));
+  }
+
+  #[test]
+  fn parse_errors() {
+    let parse = |text| Expr::parse(text, &Symbols::default()).err();
+    let unexpected = |text: &str| Some(ExprError::Unexpected(text.into()));
+    assert_eq!(parse(" "), Some(ExprError::Empty));
+    assert_eq!(parse("1 +"), Some(ExprError::End));
+    assert_eq!(parse("(1"), Some(ExprError::End));
+    assert_eq!(parse("1 2"), unexpected("2"));
+    assert_eq!(parse("1 @ 2"), unexpected("@"));
+    assert_eq!(parse("mem32 1"), unexpected("1"));
+    assert_eq!(parse("(i32 a0"), unexpected("a0"));
+    assert_eq!(parse("0xzz"), Some(ExprError::Number("0xzz".into())));
+    assert_eq!(parse("x32"), Some(ExprError::Unknown("x32".into())));
+  }
+}
@@ -0,0 +1,681 @@
+use {
+  super::{
+    machine::{CSRS, Exception, FCSR, FFLAGS, FREGS, FRM, Machine, XREGS},
+    runner::{Halt, Runner},
+  },
+  egui::Context,
+  std::{
+    collections::VecDeque,
+    fmt::Write,
+    io,
+    net::TcpListener as StdListener,
+    sync::{
+      Arc,
+      atomic::{AtomicBool, Ordering},
+    },
+  },
+  tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+  },
+};
+
+// Register numbers GDB assigns to RISC-V
+const PC: usize = 32;
+const FREG: usize = 33;
+const CSR: usize = 65;
+
+// Signals of stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+/// Description of the registers, so that GDB knows about the CSRs.
+fn target_xml() -> String {
+  let mut xml = String::from(
+    "<?xml version=\"1.0\"?>\
+     <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+     <target version=\"1.0\">\
+     <architecture>riscv:rv64</architecture>\
+     <feature name=\"org.gnu.gdb.riscv.cpu\">",
+  );
+  for (idx, name) in XREGS.iter().enumerate() {
+    let kind = match idx {
+      1 => "code_ptr",
+      2 | 8 => "data_ptr",
+      _ => "int",
+    };
+    let _ =
+      write!(xml, "<reg name=\"{name}\" bitsize=\"64\" type=\"{kind}\"/>");
+  }
+  xml += "<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/></feature>";
+
+  xml += "<feature name=\"org.gnu.gdb.riscv.fpu\">";
+  for (idx, name) in FREGS.iter().enumerate() {
+    let _ = write!(
+      xml,
+      "<reg name=\"{name}\" bitsize=\"64\" type=\"ieee_double\" \
+       regnum=\"{}\"/>",
+      FREG + idx
+    );
+  }
+  for (addr, name) in [(FFLAGS, "fflags"), (FRM, "frm"), (FCSR, "fcsr")] {
+    let _ = write!(
+      xml,
+      "<reg name=\"{name}\" bitsize=\"32\" type=\"int\" regnum=\"{}\"/>",
+      CSR + addr as usize
+    );
+  }
+  xml += "</feature><feature name=\"org.gnu.gdb.riscv.csr\">";
+  for &(addr, name) in CSRS.iter().filter(|&&(addr, _)| !is_float(addr)) {
+    let _ = write!(
+      xml,
+      "<reg name=\"{name}\" bitsize=\"64\" type=\"int\" regnum=\"{}\"/>",
+      CSR + addr as usize
+    );
+  }
+  xml + "</feature></target>"
+}
+
+fn is_float(csr: u16) -> bool {
+  matches!(csr, FFLAGS | FRM | FCSR)
+}
+
+fn signal(halt: &Halt) -> u8 {
+  use Exception::*;
+
+  match halt {
+    Halt::Paused => SIGINT,
+    Halt::Reached | Halt::Watch(_) => SIGTRAP,
+    Halt::Exception(exc) => match exc {
+      IllegalInstruction(_) => SIGILL,
+      InstructionAddressMisaligned(_)
+      | LoadAddressMisaligned(_)
+      | StoreAddressMisaligned(_) => SIGBUS,
+      InstructionAccessFault(_)
+      | LoadAccessFault(_)
+      | StoreAccessFault(_)
+      | InstructionPageFault(_)
+      | LoadPageFault(_)
+      | StorePageFault(_) => SIGSEGV,
+      Breakpoint(_) | EnvironmentCall => SIGTRAP,
+    },
+  }
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().fold(String::new(), |mut hex, byte| {
+    let _ = write!(hex, "{byte:02x}");
+    hex
+  })
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+  (0..hex.len())
+    .step_by(2)
+    .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+    .collect()
+}
+
+/// Little-endian register value of at most eight bytes.
+fn unhex_reg(hex: &str) -> Option<u64> {
+  let bytes = unhex(hex).filter(|bytes| bytes.len() <= 8)?;
+  Some(bytes.iter().rev().fold(0, |acc, &byte| acc << 8 | byte as u64))
+}
+
+/// `addr,len` of memory and breakpoint packets.
+fn addr_len(args: &str) -> Option<(u64, u64)> {
+  let (addr, len) = args.split_once(',')?;
+  Some((
+    u64::from_str_radix(addr, 16).ok()?,
+    u64::from_str_radix(len, 16).ok()?,
+  ))
+}
+
+/// Bytes to the debugger, framed and checksummed.
+fn frame(data: &str) -> Vec<u8> {
+  let mut packet = vec![b'$'];
+  for byte in data.bytes() {
+    if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+      packet.extend([b'}', byte ^ 0x20]);
+    } else {
+      packet.push(byte);
+    }
+  }
+  let sum = packet[1..].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+  packet.extend(format!("#{sum:02x}").bytes());
+  packet
+}
+
+/// Data of a whole `$...#xx` packet, unless its checksum is wrong.
+fn unframe(packet: &[u8]) -> Option<Vec<u8>> {
+  let (body, sum) = packet.split_at(packet.len().checked_sub(3)?);
+  let (body, sum) = (body.get(1..)?, std::str::from_utf8(&sum[1..]).ok()?);
+  let sum = u8::from_str_radix(sum, 16);
+  if sum != Ok(body.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))) {
+    return None;
+  }
+  let mut data = vec![];
+  let mut bytes = body.iter();
+  while let Some(&byte) = bytes.next() {
+    match byte {
+      b'}' => data.extend(bytes.next().map(|byte| byte ^ 0x20)),
+      byte => data.push(byte),
+    }
+  }
+  Some(data)
+}
+
+/// What the connection task hands to the UI thread.
+enum Packet {
+  Command(String),
+  Attached(bool),
+}
+
+/// Shared between the UI and the connection task.
+struct Link {
+  packets: UnboundedSender<Packet>,
+  /// Set by `^C` while the machine runs.
+  interrupt: Arc<AtomicBool>,
+  ctx: Context,
+}
+
+impl Link {
+  fn send(&self, packet: Packet) {
+    let _ = self.packets.send(packet);
+    // The UI serves packets only while it draws
+    self.ctx.request_repaint();
+  }
+}
+
+/// One debugger at a time, the next one waits for it to detach.
+async fn listen(
+  listener: TcpListener,
+  link: Link,
+  mut replies: UnboundedReceiver<String>,
+) {
+  while let Ok((stream, _)) = listener.accept().await {
+    // Left over from the previous debugger
+    while replies.try_recv().is_ok() {}
+    link.send(Packet::Attached(true));
+    let _ = serve(stream, &link, &mut replies).await;
+    link.send(Packet::Attached(false));
+  }
+}
+
+async fn serve(
+  mut stream: TcpStream,
+  link: &Link,
+  replies: &mut UnboundedReceiver<String>,
+) -> io::Result<()> {
+  let _ = stream.set_nodelay(true);
+  let (mut buf, mut read) = (vec![], [0; 4096]);
+  loop {
+    tokio::select! {
+      len = stream.read(&mut read) => {
+        let len = len?;
+        if len == 0 {
+          return Ok(());
+        }
+        buf.extend_from_slice(&read[..len]);
+      }
+      Some(reply) = replies.recv() => {
+        stream.write_all(&frame(&reply)).await?;
+        continue;
+      }
+    }
+
+    loop {
+      match buf.first() {
+        // Acknowledgements of our replies, resent ones are not kept
+        Some(b'+' | b'-') => {
+          buf.remove(0);
+        }
+        Some(0x03) => {
+          buf.remove(0);
+          link.interrupt.store(true, Ordering::Relaxed);
+          link.ctx.request_repaint();
+        }
+        Some(b'$') => {
+          let Some(end) = buf.iter().position(|&byte| byte == b'#') else {
+            break;
+          };
+          if buf.len() < end + 3 {
+            break;
+          }
+          let packet: Vec<_> = buf.drain(..end + 3).collect();
+          // The debugger resends what arrived garbled
+          let Some(data) = unframe(&packet) else {
+            stream.write_all(b"-").await?;
+            continue;
+          };
+          stream.write_all(b"+").await?;
+          let data = String::from_utf8_lossy(&data).into_owned();
+          // A kill gets no reply, the debugger waits for the connection to
+          // close
+          let kill = data == "k";
+          link.send(Packet::Command(data));
+          if kill {
+            return Ok(());
+          }
+        }
+        Some(_) => {
+          buf.remove(0);
+        }
+        None => break,
+      }
+    }
+  }
+}
+
+struct Server {
+  port: u16,
+  packets: UnboundedReceiver<Packet>,
+  replies: UnboundedSender<String>,
+  interrupt: Arc<AtomicBool>,
+  task: JoinHandle<()>,
+  /// Commands received while the machine was busy.
+  pending: VecDeque<String>,
+  attached: bool,
+  /// A `c` waits for the machine to stop.
+  resumed: bool,
+}
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    self.task.abort();
+  }
+}
+
+/// GDB remote serial protocol stub on localhost, served by the UI thread
+/// between frames.
+pub struct Gdb {
+  pub port: u16,
+  server: Option<Server>,
+}
+
+impl Default for Gdb {
+  fn default() -> Self {
+    Self { port: 1234, server: None }
+  }
+}
+
+impl Gdb {
+  pub fn start(&mut self, ctx: &Context) -> io::Result<()> {
+    let listener = StdListener::bind(("127.0.0.1", self.port))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    let (packets, packets_rx) = mpsc::unbounded_channel();
+    let (replies, replies_rx) = mpsc::unbounded_channel();
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let link = Link { packets, interrupt: interrupt.clone(), ctx: ctx.clone() };
+    let task = tokio::spawn(listen(listener, link, replies_rx));
+
+    self.server = Some(Server {
+      port: self.port,
+      packets: packets_rx,
+      replies,
+      interrupt,
+      task,
+      pending: VecDeque::new(),
+      attached: false,
+      resumed: false,
+    });
+    Ok(())
+  }
+
+  pub fn stop(&mut self) {
+    self.server = None;
+  }
+
+  /// Port listened on, and whether a debugger is attached.
+  pub fn status(&self) -> Option<(u16, bool)> {
+    self.server.as_ref().map(|server| (server.port, server.attached))
+  }
+
+  /// Tell a waiting debugger that the machine stopped.
+  pub fn stopped(&mut self, halt: &Halt) {
+    if let Some(server) = self.server.as_mut().filter(|s| s.resumed) {
+      server.resumed = false;
+      let _ = server.replies.send(format!("S{:02x}", signal(halt)));
+    }
+  }
+
+  /// Answer pending packets.
+  pub fn serve(&mut self, machine: &mut Machine, runner: &mut Runner) {
+    let Some(server) = self.server.as_mut() else { return };
+    if server.interrupt.swap(false, Ordering::Relaxed) {
+      if runner.is_running() {
+        runner.pause();
+      } else if server.resumed {
+        server.resumed = false;
+        let _ = server.replies.send(format!("S{SIGINT:02x}"));
+      }
+    }
+
+    while let Ok(packet) = server.packets.try_recv() {
+      match packet {
+        Packet::Command(packet) => server.pending.push_back(packet),
+        Packet::Attached(attached) => {
+          server.attached = attached;
+          server.resumed = false;
+          server.pending.clear();
+        }
+      }
+    }
+
+    // Commands wait for the machine, only `^C` reaches a running one
+    while !runner.is_running() && !server.resumed {
+      let Some(packet) = server.pending.pop_front() else { break };
+      if let Some(reply) = server.command(&packet, machine, runner) {
+        let _ = server.replies.send(reply);
+      }
+    }
+  }
+}
+
+impl Server {
+  /// Reply to `packet`, none while the machine runs on a `c`.
+  fn command(
+    &mut self,
+    packet: &str,
+    machine: &mut Machine,
+    runner: &mut Runner,
+  ) -> Option<String> {
+    let at = packet.char_indices().nth(1).map_or(packet.len(), |(at, _)| at);
+    let (cmd, args) = packet.split_at(at);
+    let ok = |done: Option<()>| done.map_or("E01", |_| "OK").to_string();
+
+    Some(match cmd {
+      "?" => format!("S{SIGTRAP:02x}"),
+      "g" => {
+        let regs = machine.cpu.xregs.iter().chain([&machine.cpu.pc]);
+        regs.map(|reg| hex(&reg.to_le_bytes())).collect()
+      }
+      "G" => ok((|| {
+        let bytes = unhex(args)?;
+        runner.edited();
+        let mut regs = bytes
+          .chunks_exact(8)
+          .map(|reg| u64::from_le_bytes(reg.try_into().unwrap()));
+        for idx in 0..32 {
+          machine.cpu.set_x(idx, regs.next()?);
+        }
+        machine.cpu.pc = regs.next()?;
+        Some(())
+      })()),
+      "p" => usize::from_str_radix(args, 16)
+        .ok()
+        .and_then(|idx| Self::read_reg(machine, idx))
+        .unwrap_or_else(|| "E01".into()),
+      "P" => ok((|| {
+        let (idx, val) = args.split_once('=')?;
+        let idx = usize::from_str_radix(idx, 16).ok()?;
+        runner.edited();
+        Self::write_reg(machine, idx, unhex_reg(val)?)
+      })()),
+      "m" => (|| {
+        let (addr, len) = addr_len(args)?;
+        let bytes = (0..len)
+          .map(|idx| {
+            let paddr = machine.debug_paddr(addr.wrapping_add(idx))?;
+            machine.bus.peek(paddr, 1).map(|byte| byte as u8)
+          })
+          .collect::<Option<Vec<_>>>()?;
+        Some(hex(&bytes))
+      })()
+      .unwrap_or_else(|| "E01".into()),
+      "M" => ok((|| {
+        let (at, data) = args.split_once(':')?;
+        let (addr, _) = addr_len(at)?;
+        runner.edited();
+        for (idx, byte) in unhex(data)?.into_iter().enumerate() {
+          let paddr = machine.debug_paddr(addr.wrapping_add(idx as u64))?;
+          machine.bus.poke(paddr, 1, byte as u64)?;
+        }
+        // Page tables may be among the written bytes
+        machine.tlb.flush();
+        Some(())
+      })()),
+      "c" => {
+        if let Ok(addr) = u64::from_str_radix(args, 16) {
+          machine.cpu.pc = addr;
+          runner.edited();
+        }
+        runner.run(machine.clone(), None);
+        self.resumed = true;
+        return None;
+      }
+      "s" => {
+        if let Ok(addr) = u64::from_str_radix(args, 16) {
+          machine.cpu.pc = addr;
+          runner.edited();
+        }
+        let halt = match runner.step(machine) {
+          Ok(()) => Halt::Reached,
+          Err(exc) => Halt::Exception(exc),
+        };
+        // A single step stops anyway, whatever it touched
+        machine.watch.hit = None;
+        format!("S{:02x}", signal(&halt))
+      }
+      "b" => {
+        let history = &mut runner.history;
+        let moved = match args {
+          "s" => history.back(machine),
+          "c" => {
+            let breakpoints = &runner.breakpoints;
+            history.reverse(machine, |machine| breakpoints.stops(machine))
+          }
+          _ => return Some(String::new()),
+        };
+        if moved {
+          format!("S{SIGTRAP:02x}")
+        } else {
+          format!("T{SIGTRAP:02x}replaylog:begin;")
+        }
+      }
+      // Conditions and commands after `;` are left for GDB to evaluate
+      "Z" | "z" => match args
+        .split(';')
+        .next()
+        .and_then(|args| args.strip_prefix("0,"))
+        .and_then(addr_len)
+      {
+        Some((addr, _)) => {
+          if cmd == "Z" {
+            runner.breakpoints.insert(addr);
+          } else {
+            runner.breakpoints.remove(addr);
+          }
+          "OK".into()
+        }
+        // Only software breakpoints, GDB falls back to them
+        None => String::new(),
+      },
+      "H" | "T" => "OK".into(),
+      "D" => {
+        self.attached = false;
+        "OK".into()
+      }
+      "k" => {
+        self.attached = false;
+        return None;
+      }
+      "q" => self.query(args),
+      _ => String::new(),
+    })
+  }
+
+  fn query(&self, query: &str) -> String {
+    if query.starts_with("Supported") {
+      return "PacketSize=4000;qXfer:features:read+;ReverseStep+;ReverseContinue+"
+        .into();
+    }
+    if let Some(at) = query.strip_prefix("Xfer:features:read:target.xml:") {
+      let Some((offset, len)) = addr_len(at) else { return "E01".into() };
+      let xml = target_xml();
+      let start = (offset as usize).min(xml.len());
+      let end = start.saturating_add(len as usize).min(xml.len());
+      let more = if end < xml.len() { 'm' } else { 'l' };
+      return format!("{more}{}", &xml[start..end]);
+    }
+    match query {
+      "Attached" => "1".into(),
+      "C" => "QC1".into(),
+      "fThreadInfo" => "m1".into(),
+      "sThreadInfo" => "l".into(),
+      _ => String::new(),
+    }
+  }
+
+  fn read_reg(machine: &Machine, idx: usize) -> Option<String> {
+    let cpu = &machine.cpu;
+    let val = match idx {
+      0..PC => cpu.xregs[idx],
+      PC => cpu.pc,
+      FREG..CSR => cpu.fregs[idx - FREG],
+      _ => {
+        let addr = u16::try_from(idx - CSR).ok()?;
+        let val = machine.csr_read(addr)?;
+        if is_float(addr) {
+          return Some(hex(&(val as u32).to_le_bytes()));
+        }
+        val
+      }
+    };
+    Some(hex(&val.to_le_bytes()))
+  }
+
+  fn write_reg(machine: &mut Machine, idx: usize, val: u64) -> Option<()> {
+    let cpu = &mut machine.cpu;
+    match idx {
+      0..PC => cpu.set_x(idx, val),
+      PC => cpu.pc = val,
+      FREG..CSR => cpu.fregs[idx - FREG] = val,
+      _ => machine.csr_write(u16::try_from(idx - CSR).ok()?, val)?,
+    }
+    Some(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn server() -> Server {
+    let (_, packets) = mpsc::unbounded_channel();
+    let (replies, _) = mpsc::unbounded_channel();
+    Server {
+      port: 0,
+      packets,
+      replies,
+      interrupt: Arc::default(),
+      task: tokio::spawn(async {}),
+      pending: VecDeque::new(),
+      attached: true,
+      resumed: false,
+    }
+  }
+
+  #[test]
+  fn framing() {
+    assert_eq!(frame("OK"), b"$OK#9a");
+    assert_eq!(frame("a$b"), b"$a}\x04b#44");
+    assert_eq!(unframe(&frame("a$b")).as_deref(), Some(&b"a$b"[..]));
+    assert_eq!(unframe(b"$OK#9b"), None);
+    assert_eq!(unframe(b"$OK#zz"), None);
+    assert_eq!(hex(&[0x00, 0xab]), "00ab");
+    assert_eq!(unhex("00ab"), Some(vec![0x00, 0xab]));
+    assert_eq!(unhex("0g"), None);
+    assert_eq!(unhex_reg("3412000000000000"), Some(0x1234));
+    assert_eq!(unhex_reg(&"00".repeat(9)), None);
+  }
+
+  #[test]
+  fn addresses() {
+    assert_eq!(addr_len("80000000,4"), Some((0x8000_0000, 4)));
+    assert_eq!(addr_len("80000000"), None);
+    assert_eq!(addr_len("zz,4"), None);
+  }
+
+  #[test]
+  fn signals() {
+    let exc = |exc| signal(&Halt::Exception(exc));
+    assert_eq!(signal(&Halt::Paused), SIGINT);
+    assert_eq!(signal(&Halt::Reached), SIGTRAP);
+    assert_eq!(exc(Exception::IllegalInstruction(0)), SIGILL);
+    assert_eq!(exc(Exception::LoadAddressMisaligned(1)), SIGBUS);
+    assert_eq!(exc(Exception::StorePageFault(0)), SIGSEGV);
+  }
+
+  #[tokio::test]
+  async fn registers_and_memory() {
+    let (mut server, mut runner) = (server(), Runner::default());
+    let mut machine = Machine::with_code(&[0x00500513]); // li a0, 5
+    let mut command =
+      |packet| server.command(packet, &mut machine, &mut runner);
+
+    assert_eq!(command("P0a=2a00000000000000").as_deref(), Some("OK"));
+    assert_eq!(command("pa").as_deref(), Some("2a00000000000000"));
+    assert_eq!(command("p20").as_deref(), Some("0000008000000000"));
+    assert_eq!(command("p0").as_deref(), Some("0000000000000000"));
+    assert_eq!(command("pfffff").as_deref(), Some("E01"));
+
+    assert_eq!(command("m80000000,4").as_deref(), Some("13055000"));
+    assert_eq!(command("M80000004,2:0100").as_deref(), Some("OK"));
+    assert_eq!(command("m80000004,2").as_deref(), Some("0100"));
+    assert_eq!(command("m0,1").as_deref(), Some("E01"));
+
+    assert_eq!(command("s").as_deref(), Some("S05"));
+    assert_eq!(command("pa").as_deref(), Some("0500000000000000"));
+    // The recorded step does not lead to what the debugger writes
+    assert_eq!(command("M80000008,1:00").as_deref(), Some("OK"));
+
+    // Unknown commands, whatever their first character
+    assert_eq!(command("ж").as_deref(), Some(""));
+    assert_eq!(command("pж").as_deref(), Some("E01"));
+    assert!(runner.history.is_empty());
+  }
+
+  #[tokio::test]
+  async fn breakpoints() {
+    let (mut server, mut runner) = (server(), Runner::default());
+    let mut machine = Machine::default();
+    let mut command = |packet, runner: &mut Runner| {
+      server.command(packet, &mut machine, runner)
+    };
+
+    // The condition after `;` is GDB's to evaluate
+    let reply = command("Z0,80000010,4;X3,220a00", &mut runner);
+    assert_eq!(reply.as_deref(), Some("OK"));
+    assert_eq!(runner.breakpoints.at(0x8000_0010), Some(true));
+    assert_eq!(command("z0,80000010,4", &mut runner).as_deref(), Some("OK"));
+    assert_eq!(runner.breakpoints.at(0x8000_0010), None);
+    // Hardware breakpoints and watchpoints are not supported
+    assert_eq!(command("Z1,80000010,4", &mut runner).as_deref(), Some(""));
+  }
+
+  #[tokio::test]
+  async fn queries_and_kill() {
+    let (mut server, mut runner) = (server(), Runner::default());
+    let mut machine = Machine::default();
+
+    let supported = server.query("Supported:multiprocess+");
+    assert!(supported.contains("qXfer:features:read+"));
+    let xml = target_xml();
+    let head = server.query("Xfer:features:read:target.xml:0,10");
+    assert_eq!(head, format!("m{}", &xml[..0x10]));
+    let tail = server
+      .query(&format!("Xfer:features:read:target.xml:10,{:x}", xml.len()));
+    assert_eq!(tail, format!("l{}", &xml[0x10..]));
+
+    assert_eq!(server.command("k", &mut machine, &mut runner), None);
+    assert!(!server.attached);
+  }
+}
@@ -0,0 +1,160 @@
+use {
+  super::machine::{FREGS, XREGS},
+  egui::{Color32, Style, TextFormat, TextStyle, Visuals, text::LayoutJob},
+};
+
+/// What an instruction does, by its mnemonic.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Class {
+  Jump,
+  Load,
+  /// Stores and atomics, which write memory.
+  Store,
+  /// CSR access, fences and changes of privilege.
+  System,
+  Other,
+}
+
+impl Class {
+  fn of(mnemonic: &str) -> Self {
+    let name = mnemonic.to_ascii_lowercase();
+    let name = name.strip_prefix("c.").unwrap_or(&name);
+    match name {
+      "jal" | "jalr" | "j" | "jr" | "ret" | "call" | "tail" | "beqz"
+      | "bnez" | "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => Self::Jump,
+      "lb" | "lh" | "lw" | "ld" | "lbu" | "lhu" | "lwu" | "flw" | "fld"
+      | "lwsp" | "ldsp" | "flwsp" | "fldsp" => Self::Load,
+      "sb" | "sh" | "sw" | "sd" | "fsw" | "fsd" | "swsp" | "sdsp" | "fswsp"
+      | "fsdsp" => Self::Store,
+      "ecall" | "ebreak" | "mret" | "sret" | "wfi" | "sfence.vma" | "fence"
+      | "fence.i" => Self::System,
+      _ if name.starts_with("lr.") => Self::Load,
+      _ if name.starts_with("sc.") || name.starts_with("amo") => Self::Store,
+      _ if name.starts_with("csr") => Self::System,
+      _ => Self::Other,
+    }
+  }
+}
+
+/// Colors of the tokens, following the dark or light visuals.
+struct Theme {
+  text: Color32,
+  weak: Color32,
+  strong: Color32,
+  jump: Color32,
+  load: Color32,
+  store: Color32,
+  system: Color32,
+  register: Color32,
+  immediate: Color32,
+  symbol: Color32,
+}
+
+impl Theme {
+  fn from_visuals(visuals: &Visuals) -> Self {
+    let pick = |dark, light| if visuals.dark_mode { dark } else { light };
+    Self {
+      text: visuals.text_color(),
+      weak: visuals.weak_text_color(),
+      strong: visuals.strong_text_color(),
+      jump: visuals.warn_fg_color,
+      load: pick(
+        Color32::from_rgb(110, 180, 250),
+        Color32::from_rgb(20, 90, 170),
+      ),
+      store: pick(
+        Color32::from_rgb(200, 140, 240),
+        Color32::from_rgb(130, 50, 170),
+      ),
+      system: visuals.error_fg_color,
+      register: pick(
+        Color32::from_rgb(150, 210, 160),
+        Color32::from_rgb(30, 120, 50),
+      ),
+      immediate: pick(
+        Color32::from_rgb(230, 190, 120),
+        Color32::from_rgb(150, 100, 20),
+      ),
+      symbol: visuals.hyperlink_color,
+    }
+  }
+
+  fn mnemonic(&self, mnemonic: &str) -> Color32 {
+    match Class::of(mnemonic) {
+      Class::Jump => self.jump,
+      Class::Load => self.load,
+      Class::Store => self.store,
+      Class::System => self.system,
+      Class::Other => self.strong,
+    }
+  }
+}
+
+fn is_register(word: &str) -> bool {
+  let numbered = |prefix| {
+    let idx = word.strip_prefix(prefix).map(str::parse::<u32>);
+    matches!(idx, Some(Ok(idx)) if idx < 32)
+  };
+  XREGS.contains(&word)
+    || FREGS.contains(&word)
+    || word == "s0"
+    || numbered('x')
+    || numbered('f')
+}
+
+/// Highlight disassembly, one instruction or directive a line, as
+/// `mnemonic operands <symbol+offset>`.
+pub fn highlight(style: &Style, text: &str) -> LayoutJob {
+  let theme = Theme::from_visuals(&style.visuals);
+  let font = TextStyle::Monospace.resolve(style);
+  let mut job = LayoutJob::default();
+  let mut append = |text: &str, color| {
+    job.append(text, 0.0, TextFormat::simple(font.clone(), color));
+  };
+
+  for (idx, line) in text.split('\n').enumerate() {
+    if idx > 0 {
+      append("\n", theme.text);
+    }
+    if line == "unknown instruction" {
+      append(line, theme.weak);
+      continue;
+    }
+
+    let (mnemonic, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    // Data shows as directives
+    let color = if mnemonic.starts_with('.') {
+      theme.weak
+    } else {
+      theme.mnemonic(mnemonic)
+    };
+    append(mnemonic, color);
+    if !rest.is_empty() {
+      append(" ", theme.text);
+    }
+
+    while let Some(ch) = rest.chars().next() {
+      let len = match ch {
+        // `<symbol+offset>` as annotated, up to its end
+        '<' => rest.find('>').map_or(rest.len(), |end| end + 1),
+        '-' | '0'..='9' => rest[1..]
+          .find(|ch: char| !ch.is_ascii_alphanumeric())
+          .map_or(rest.len(), |end| end + 1),
+        _ if ch.is_ascii_alphabetic() || ch == '_' => rest
+          .find(|ch: char| !(ch.is_ascii_alphanumeric() || "_.".contains(ch)))
+          .unwrap_or(rest.len()),
+        _ => ch.len_utf8(),
+      };
+      let (token, tail) = rest.split_at(len);
+      let color = match ch {
+        '<' => theme.symbol,
+        '-' | '0'..='9' => theme.immediate,
+        _ if is_register(token) => theme.register,
+        _ => theme.text,
+      };
+      append(token, color);
+      rest = tail;
+    }
+  }
+  job
+}
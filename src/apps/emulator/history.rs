@@ -0,0 +1,457 @@
+use {
+  super::machine::{Clint, Cpu, Exception, Machine, Plic, Store, Tlb, Uart},
+  egui::{CollapsingHeader, Context, DragValue, Slider, Window},
+  std::{collections::VecDeque, mem},
+};
+
+const MIB: usize = 1 << 20;
+
+/// Everything of a machine but its memory, which the undo log rebuilds.
+#[derive(Clone)]
+struct Checkpoint {
+  /// Steps recorded before it was taken.
+  step: u64,
+  cpu: Cpu,
+  tlb: Tlb,
+  uart: Uart,
+  clint: Clint,
+  plic: Plic,
+}
+
+impl Checkpoint {
+  fn new(step: u64, machine: &Machine) -> Self {
+    let Machine { cpu, tlb, bus, .. } = machine;
+    Self {
+      step,
+      cpu: cpu.clone(),
+      tlb: tlb.clone(),
+      uart: bus.uart.clone(),
+      clint: bus.clint.clone(),
+      plic: bus.plic.clone(),
+    }
+  }
+
+  /// Bytes it takes, the cached translations included.
+  fn size(&self) -> usize {
+    size_of::<Self>() + self.tlb.heap_size()
+  }
+
+  fn restore(&self, machine: &mut Machine) {
+    machine.cpu = self.cpu.clone();
+    machine.tlb = self.tlb.clone();
+    // The serial line stays whatever the caller attached
+    let serial = mem::take(&mut machine.bus.uart.serial);
+    machine.bus.uart = self.uart.clone();
+    machine.bus.uart.serial = serial;
+    machine.bus.clint = self.clint.clone();
+    machine.bus.plic = self.plic.clone();
+  }
+}
+
+/// Recorded past of a machine, to step back through it.
+///
+/// A checkpoint is taken every `interval` steps and each step logs what its
+/// stores overwrote. Going back undoes the stores down to a checkpoint,
+/// restores it and replays forward to the wanted step, so seeking costs at
+/// most `interval` steps. The steps ahead stay recorded until execution
+/// resumes from the past.
+///
+/// Console input cannot be replayed: a step that takes some is followed by a
+/// checkpoint, so that no replay runs past it.
+pub struct History {
+  /// Bytes the history may take, the oldest steps are dropped beyond.
+  pub budget: usize,
+  pub interval: u64,
+  checkpoints: VecDeque<Checkpoint>,
+  /// Bytes the checkpoints take, kept as they come and go.
+  checkpoint_bytes: usize,
+  /// Stores along with the step that made them, oldest first.
+  stores: VecDeque<(u64, Store)>,
+  /// Step the machine is at and the last one recorded.
+  at: u64,
+  end: u64,
+  /// `Uart::received` after the last recorded step.
+  received: u64,
+}
+
+impl Default for History {
+  fn default() -> Self {
+    Self {
+      budget: 64 * MIB,
+      interval: 10_000,
+      checkpoints: VecDeque::new(),
+      checkpoint_bytes: 0,
+      stores: VecDeque::new(),
+      at: 0,
+      end: 0,
+      received: 0,
+    }
+  }
+}
+
+impl History {
+  /// Earliest step still recorded.
+  pub fn start(&self) -> u64 {
+    self.checkpoints.front().map_or(self.at, |cp| cp.step)
+  }
+
+  pub fn at(&self) -> u64 {
+    self.at
+  }
+
+  pub fn end(&self) -> u64 {
+    self.end
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.checkpoints.is_empty()
+  }
+
+  /// Rough size of the history in bytes.
+  pub fn size(&self) -> usize {
+    self.checkpoint_bytes + self.stores.len() * size_of::<(u64, Store)>()
+  }
+
+  /// Forget everything, for a machine the history does not lead to.
+  pub fn clear(&mut self) {
+    *self =
+      Self { budget: self.budget, interval: self.interval, ..Self::default() };
+  }
+
+  /// Step `machine` and record it, dropping the steps ahead if it was in the
+  /// past.
+  pub fn step(&mut self, machine: &mut Machine) -> Result<(), Exception> {
+    if self.at < self.end {
+      self.truncate();
+    }
+    let due = self.checkpoints.back().is_none_or(|cp| {
+      self.at - cp.step >= self.interval
+        || machine.bus.uart.received != self.received
+    });
+    if due {
+      let checkpoint = Checkpoint::new(self.at, machine);
+      self.checkpoint_bytes += checkpoint.size();
+      self.checkpoints.push_back(checkpoint);
+      self.received = machine.bus.uart.received;
+    }
+
+    machine.journal.enabled = true;
+    let result = machine.step();
+    machine.journal.enabled = false;
+    let (at, stores) = (self.at, machine.journal.stores.drain(..));
+    if result.is_ok() {
+      self.stores.extend(stores.map(|store| (at, store)));
+      self.at += 1;
+      self.end = self.at;
+      self.trim();
+    }
+    result
+  }
+
+  /// Drop the steps ahead of the current one.
+  fn truncate(&mut self) {
+    let at = self.at;
+    let keep = self.stores.partition_point(|&(step, _)| step < at);
+    self.stores.truncate(keep);
+    let keep = self.checkpoints.partition_point(|cp| cp.step <= at);
+    for checkpoint in self.checkpoints.drain(keep..) {
+      self.checkpoint_bytes -= checkpoint.size();
+    }
+    self.end = at;
+  }
+
+  /// Drop the oldest steps until the history fits its budget again.
+  pub fn trim(&mut self) {
+    while self.size() > self.budget && self.checkpoints.len() > 1 {
+      if let Some(checkpoint) = self.checkpoints.pop_front() {
+        self.checkpoint_bytes -= checkpoint.size();
+      }
+      let start = self.start();
+      while self.stores.front().is_some_and(|&(step, _)| step < start) {
+        self.stores.pop_front();
+      }
+    }
+  }
+
+  /// Bring `machine` to `step`, clamped to the recorded ones.
+  pub fn seek(&mut self, machine: &mut Machine, step: u64) {
+    self.replaying(machine, |history, machine| history.goto(machine, step));
+  }
+
+  /// Undo the last step, `false` at the start of the history.
+  pub fn back(&mut self, machine: &mut Machine) -> bool {
+    let back = self.at > self.start();
+    if back {
+      self.seek(machine, self.at - 1);
+    }
+    back
+  }
+
+  /// Go back to the latest step `stop` holds at, or that a watchpoint
+  /// stopped on, else to the start of the history.
+  pub fn reverse(
+    &mut self,
+    machine: &mut Machine,
+    mut stop: impl FnMut(&Machine) -> bool,
+  ) -> bool {
+    self.replaying(machine, |history, machine| {
+      let start = history.start();
+      let mut upper = history.at;
+      // One checkpoint span at a time, the latest first
+      while upper > start {
+        let idx = history.checkpoints.partition_point(|cp| cp.step < upper);
+        let low = history.checkpoints[idx - 1].step;
+        // A step earlier, to see a watchpoint the step into `low` touched
+        history.goto(machine, low.saturating_sub(1).max(start));
+
+        let (mut found, mut watched) = (None, false);
+        while history.at < upper {
+          if history.at >= low && (watched || stop(machine)) {
+            found = Some(history.at);
+          }
+          let Some(hit) = history.replay(machine) else { break };
+          watched = hit;
+        }
+        if let Some(step) = found {
+          history.goto(machine, step);
+          return true;
+        }
+        upper = low;
+      }
+      history.goto(machine, start);
+      false
+    })
+  }
+
+  /// Run `f` with the serial line detached, so that replays neither print
+  /// again nor eat input typed meanwhile.
+  fn replaying<R>(
+    &mut self,
+    machine: &mut Machine,
+    f: impl FnOnce(&mut Self, &mut Machine) -> R,
+  ) -> R {
+    let serial = mem::take(&mut machine.bus.uart.serial);
+    let ret = f(self, machine);
+    machine.bus.uart.serial = serial;
+    machine.watch.hit = None;
+    self.received = machine.bus.uart.received;
+    ret
+  }
+
+  fn goto(&mut self, machine: &mut Machine, step: u64) {
+    let step = step.clamp(self.start(), self.end);
+    if step < self.at {
+      let idx = self.checkpoints.partition_point(|cp| cp.step <= step) - 1;
+      let checkpoint = &self.checkpoints[idx];
+      let from = self.stores.partition_point(|&(s, _)| s < checkpoint.step);
+      let to = self.stores.partition_point(|&(s, _)| s < self.at);
+      for (_, store) in self.stores.range(from..to).rev() {
+        machine.bus.poke(store.addr, store.size, store.old);
+      }
+      checkpoint.restore(machine);
+      self.at = checkpoint.step;
+    }
+    while self.at < step {
+      if self.replay(machine).is_none() {
+        break;
+      }
+    }
+  }
+
+  /// Redo one recorded step, telling whether it touched a watchpoint.
+  fn replay(&mut self, machine: &mut Machine) -> Option<bool> {
+    // Recorded steps went through, anything else means the past changed
+    machine.step().ok()?;
+    self.at += 1;
+    Some(machine.watch.hit.take().is_some())
+  }
+}
+
+/// Scrubs through the recorded steps and sets how much is recorded.
+#[derive(Default)]
+pub struct Timeline {
+  pub open: bool,
+}
+
+impl Timeline {
+  /// Returns whether the machine moved.
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    history: &mut History,
+    machine: &mut Machine,
+    running: bool,
+  ) -> bool {
+    let mut moved = false;
+    let mut open = self.open;
+    Window::new("Timeline").open(&mut open).show(ctx, |ui| {
+      if running {
+        ui.weak("Recording…");
+      } else if history.is_empty() {
+        ui.weak("Run or step to record a history");
+      } else {
+        let (start, end) = (history.start(), history.end());
+        let mut at = history.at();
+        let slider = Slider::new(&mut at, start..=end).text("step");
+        if ui.add(slider).changed() {
+          history.seek(machine, at);
+          moved = true;
+        }
+        ui.label(match end - history.at() {
+          0 => format!("{} steps recorded", end - start),
+          ahead => format!("{ahead} steps back, continuing drops them"),
+        });
+      }
+
+      ui.separator();
+      CollapsingHeader::new("Settings").show(ui, |ui| {
+        // The running copy of the history would not see the edits
+        ui.add_enabled_ui(!running, |ui| {
+          let mut budget = history.budget / MIB;
+          ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut budget).range(1..=16384).suffix(" MiB"));
+            ui.label("memory budget");
+          });
+          if budget != history.budget / MIB {
+            history.budget = budget * MIB;
+            history.trim();
+          }
+          ui.horizontal(|ui| {
+            ui.add(
+              DragValue::new(&mut history.interval).range(100..=1_000_000),
+            );
+            ui.label("steps between checkpoints");
+          })
+          .response
+          .on_hover_text("Fewer make seeking faster but take more memory");
+          ui.weak(format!(
+            "{:.1} MiB in use",
+            history.size() as f64 / MIB as f64
+          ));
+        });
+      });
+    });
+    self.open = open;
+    moved
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::repr::session::WatchKind};
+
+  const DATA: u64 = Machine::RAM + 0x100;
+
+  /// Registers, pc and the words the loop stores to.
+  type State = ([u64; 32], u64, Vec<u64>);
+
+  fn state(machine: &Machine) -> State {
+    let words = (0..8).map(|idx| machine.bus.peek(DATA + 8 * idx, 8).unwrap());
+    (machine.cpu.xregs, machine.cpu.pc, words.collect())
+  }
+
+  /// Record `steps` of a loop storing 1, 2, 3.. to consecutive words, with
+  /// a checkpoint every 5 steps. Returns the state after every step.
+  fn record(steps: usize) -> (Machine, History, Vec<State>) {
+    let mut machine = Machine::with_code(&[
+      0x00150513, // addi a0, a0, 1
+      0x00a5b023, // sd a0, 0(a1)
+      0x00858593, // addi a1, a1, 8
+      0xff5ff06f, // j -12
+    ]);
+    machine.cpu.xregs[11] = DATA;
+    let mut history = History { interval: 5, ..History::default() };
+    let mut states = vec![state(&machine)];
+    for _ in 0..steps {
+      history.step(&mut machine).unwrap();
+      states.push(state(&machine));
+    }
+    (machine, history, states)
+  }
+
+  #[test]
+  fn back_undoes_stores() {
+    let (mut machine, mut history, states) = record(12);
+    for step in (0..12).rev() {
+      assert!(history.back(&mut machine));
+      assert_eq!(history.at(), step);
+      assert_eq!(state(&machine), states[step as usize], "step {step}");
+    }
+    assert!(!history.back(&mut machine));
+    assert_eq!(history.end(), 12);
+  }
+
+  #[test]
+  fn seeks_across_checkpoints() {
+    let (mut machine, mut history, states) = record(23);
+    for step in [3, 17, 5, 10, 0, 23, 22] {
+      history.seek(&mut machine, step);
+      assert_eq!(state(&machine), states[step as usize], "step {step}");
+    }
+    history.seek(&mut machine, 100);
+    assert_eq!(history.at(), 23);
+  }
+
+  #[test]
+  fn stepping_from_the_past_drops_the_future() {
+    let (mut machine, mut history, states) = record(12);
+    history.seek(&mut machine, 6);
+    history.step(&mut machine).unwrap();
+    assert_eq!((history.at(), history.end()), (7, 7));
+    assert_eq!(state(&machine), states[7]);
+
+    history.seek(&mut machine, 100);
+    assert_eq!(history.at(), 7);
+    history.seek(&mut machine, 2);
+    assert_eq!(state(&machine), states[2]);
+  }
+
+  #[test]
+  fn trim_keeps_the_budget() {
+    let (mut machine, mut history, states) = record(40);
+    history.budget = history.size() / 2;
+    history.trim();
+    assert!(history.size() <= history.budget);
+    let start = history.start();
+    assert!(start > 0 && start % 5 == 0);
+
+    history.seek(&mut machine, 0);
+    assert_eq!(history.at(), start);
+    assert_eq!(state(&machine), states[start as usize]);
+    assert!(!history.back(&mut machine));
+  }
+
+  #[test]
+  fn reverse_to_a_breakpoint() {
+    let (mut machine, mut history, states) = record(30);
+    // The `sd` storing 3, at step 9
+    let stop = |machine: &Machine| {
+      machine.cpu.pc == Machine::RAM + 4 && machine.cpu.xregs[10] == 3
+    };
+    assert!(history.reverse(&mut machine, stop));
+    assert_eq!(history.at(), 9);
+    assert_eq!(state(&machine), states[9]);
+
+    // Nothing earlier stops, so it ends at the start
+    assert!(!history.reverse(&mut machine, |machine| {
+      machine.cpu.pc == Machine::RAM + 4 && machine.cpu.xregs[10] == 3 && false
+    }));
+    assert_eq!(history.at(), 0);
+  }
+
+  #[test]
+  fn reverse_to_a_watchpoint() {
+    let (mut machine, mut history, states) = record(30);
+    // Stored to by step 9, whose next step 10 is also a checkpoint
+    machine.watch.add(DATA + 16, 8, WatchKind::Write);
+    assert!(history.reverse(&mut machine, |_| false));
+    assert_eq!(history.at(), 10);
+    assert_eq!(state(&machine), states[10]);
+    assert_eq!(machine.watch.hit, None);
+
+    // From there, the store of step 1
+    machine.watch.add(DATA, 8, WatchKind::Write);
+    assert!(history.reverse(&mut machine, |_| false));
+    assert_eq!(history.at(), 2);
+  }
+}
@@ -0,0 +1,98 @@
+use {
+  super::machine::{INTERRUPTS, Machine, SOURCES, UART_IRQ},
+  egui::{Context, Grid, Ui, Window},
+};
+
+fn flag(ui: &mut Ui, set: bool) {
+  if set {
+    ui.monospace("●");
+  } else {
+    ui.weak("○");
+  }
+}
+
+/// Pending and enabled interrupts of the hart, the CLINT and the PLIC.
+#[derive(Default)]
+pub struct Interrupts {
+  pub open: bool,
+}
+
+impl Interrupts {
+  pub fn ui(&mut self, ctx: &Context, machine: &Machine) {
+    let mut open = self.open;
+    Window::new("Interrupts").open(&mut open).show(ctx, |ui| {
+      let csrs = &machine.cpu.csrs;
+      let pending = machine.pending();
+
+      ui.strong("Hart");
+      Grid::new("interrupts-hart").striped(true).show(ui, |ui| {
+        ui.label("");
+        ui.label("pending");
+        ui.label("enabled");
+        ui.label("delegated");
+        ui.end_row();
+        for (code, name) in INTERRUPTS {
+          ui.monospace(name);
+          flag(ui, pending >> code & 1 != 0);
+          flag(ui, csrs.mie >> code & 1 != 0);
+          flag(ui, csrs.mideleg >> code & 1 != 0);
+          ui.end_row();
+        }
+      });
+      ui.separator();
+
+      let clint = &machine.bus.clint;
+      ui.strong("CLINT");
+      Grid::new("interrupts-clint").show(ui, |ui| {
+        ui.label("mtime");
+        ui.monospace(format!("0x{:016x}", clint.mtime));
+        ui.end_row();
+        ui.label("mtimecmp");
+        ui.monospace(format!("0x{:016x}", clint.mtimecmp));
+        ui.end_row();
+        ui.label("msip");
+        flag(ui, clint.msip);
+        ui.end_row();
+      });
+      ui.separator();
+
+      let plic = &machine.bus.plic;
+      ui.strong("PLIC");
+      ui.label(format!(
+        "Threshold: M {}, S {}",
+        plic.threshold[0], plic.threshold[1]
+      ));
+      Grid::new("interrupts-plic").striped(true).show(ui, |ui| {
+        for label in ["source", "priority", "level", "pending", "claimed"] {
+          ui.label(label);
+        }
+        ui.label("M");
+        ui.label("S");
+        ui.end_row();
+
+        for source in 1..SOURCES {
+          let bit = |mask: u32| mask >> source & 1 != 0;
+          let active = plic.priority[source] != 0
+            || bit(plic.level | plic.enable[0] | plic.enable[1]);
+          // Idle sources would only bury the interesting ones
+          if !active && source != UART_IRQ {
+            continue;
+          }
+          if source == UART_IRQ {
+            ui.monospace(format!("{source} uart"));
+          } else {
+            ui.monospace(source.to_string());
+          }
+          ui.monospace(plic.priority[source].to_string());
+          flag(ui, bit(plic.level));
+          flag(ui, bit(plic.pending));
+          flag(ui, bit(plic.claimed));
+          flag(ui, bit(plic.enable[0]));
+          flag(ui, bit(plic.enable[1]));
+          ui.end_row();
+        }
+      });
+    });
+    self.open = open;
+  }
+}
@@ -0,0 +1,79 @@
+use {
+  crate::repr::session::LineTable,
+  std::{fs, path::Path},
+};
+
+/// Where the source of `path` lies next to an executable in `dir`.
+fn find(dir: &Path, path: &str) -> Option<String> {
+  let path = Path::new(path);
+  let name = path.file_name().map(|name| dir.join(name));
+  // Compilers record paths of the build machine, often absolute ones
+  [Some(path.to_path_buf()), Some(dir.join(path)), name]
+    .into_iter()
+    .flatten()
+    .find(|path| path.is_file())
+    .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Point the files of `table` at their sources on disk, where found.
+pub fn resolve(mut table: LineTable, dir: &Path) -> LineTable {
+  for file in &mut table.files {
+    if let Some(path) = find(dir, file) {
+      *file = path;
+    }
+  }
+  table
+}
+
+/// Source lines of the loaded executable along with the text of its files.
+#[derive(Default)]
+pub struct Lines {
+  table: LineTable,
+  /// Lines of every file, empty when it could not be read.
+  sources: Vec<Vec<String>>,
+}
+
+impl Lines {
+  pub fn new(table: LineTable) -> Self {
+    let sources = (table.files.iter())
+      .map(|path| match fs::read(path) {
+        Ok(text) => {
+          String::from_utf8_lossy(&text).lines().map(String::from).collect()
+        }
+        Err(_) => vec![],
+      })
+      .collect();
+    Self { table, sources }
+  }
+
+  pub fn repr(&self) -> LineTable {
+    self.table.clone()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.table.rows.is_empty()
+  }
+
+  pub fn path(&self, file: u32) -> &str {
+    self.table.files.get(file as usize).map_or("", String::as_str)
+  }
+
+  /// Lines of `file`, empty when its source was not found.
+  pub fn source(&self, file: u32) -> &[String] {
+    self.sources.get(file as usize).map_or(&[], Vec::as_slice)
+  }
+
+  /// Text of a line, counted from one.
+  pub fn text(&self, file: u32, line: u32) -> Option<&str> {
+    let idx = (line as usize).checked_sub(1)?;
+    self.source(file).get(idx).map(String::as_str)
+  }
+
+  /// File and line the code at `addr` was compiled from.
+  pub fn lookup(&self, addr: u64) -> Option<(u32, u32)> {
+    let rows = &self.table.rows;
+    let idx = rows.partition_point(|&(at, ..)| at <= addr).checked_sub(1)?;
+    let (_, file, line) = rows[idx];
+    (line != 0).then_some((file, line))
+  }
+}
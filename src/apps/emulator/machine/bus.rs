@@ -0,0 +1,405 @@
+use {
+  super::{
+    Clint, Plic, Uart,
+    clint::CLINT_SIZE,
+    plic::{PLIC_SIZE, UART_IRQ},
+    uart::UART_SIZE,
+  },
+  crate::repr::session::{self, Region, RegionKind},
+  std::mem,
+};
+
+/// Largest memory region, the memory editor cannot scroll much further.
+pub const MAX_MEMORY: u64 = 1 << 28;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+  #[error("a region has no name")]
+  Unnamed,
+  #[error("two regions are named `{0}`")]
+  Duplicate(String),
+  #[error("region `{0}` is empty")]
+  Empty(String),
+  #[error("region `{0}` is larger than {MAX_MEMORY:#x} bytes")]
+  TooLarge(String),
+  #[error("region `{0}` wraps past the end of the address space")]
+  Overflow(String),
+  #[error("regions `{0}` and `{1}` overlap")]
+  Overlap(String, String),
+  #[error("only one {0} can be mapped")]
+  Device(&'static str),
+}
+
+impl RegionKind {
+  pub const ALL: [RegionKind; 5] =
+    [Self::Ram, Self::Rom, Self::Uart, Self::Clint, Self::Plic];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Ram => "RAM",
+      Self::Rom => "ROM",
+      Self::Uart => "UART",
+      Self::Clint => "CLINT",
+      Self::Plic => "PLIC",
+    }
+  }
+
+  pub fn is_memory(self) -> bool {
+    matches!(self, Self::Ram | Self::Rom)
+  }
+
+  /// Register window of a device, memory is sized freely.
+  pub fn device_size(self) -> Option<u64> {
+    match self {
+      Self::Ram | Self::Rom => None,
+      Self::Uart => Some(UART_SIZE),
+      Self::Clint => Some(CLINT_SIZE),
+      Self::Plic => Some(PLIC_SIZE),
+    }
+  }
+
+  /// Where the QEMU `virt` board maps it.
+  pub fn default_base(self) -> u64 {
+    match self {
+      Self::Ram => 0x8000_0000,
+      Self::Rom => 0x1000,
+      Self::Uart => 0x1000_0000,
+      Self::Clint => 0x0200_0000,
+      Self::Plic => 0x0c00_0000,
+    }
+  }
+}
+
+impl Region {
+  pub fn new(kind: RegionKind, name: impl Into<String>, base: u64) -> Self {
+    let size = kind.device_size().unwrap_or(0x10_0000);
+    Self { name: name.into(), kind, base, size, data: vec![] }
+  }
+
+  pub fn end(&self) -> u64 {
+    self.base.saturating_add(self.size)
+  }
+
+  pub fn contains(&self, addr: u64) -> bool {
+    (self.base..self.end()).contains(&addr)
+  }
+}
+
+/// Layout of the QEMU `virt` board with `ram` bytes of RAM.
+pub fn virt(ram: u64) -> Vec<Region> {
+  let mut map = vec![
+    Region::new(RegionKind::Clint, "clint", RegionKind::Clint.default_base()),
+    Region::new(RegionKind::Plic, "plic", RegionKind::Plic.default_base()),
+    Region::new(RegionKind::Uart, "uart", RegionKind::Uart.default_base()),
+    Region::new(RegionKind::Ram, "ram", RegionKind::Ram.default_base()),
+  ];
+  map[3].size = ram;
+  map[3].data = vec![0; ram as usize];
+  map
+}
+
+/// Check that `map` can be decoded: named, sized and without overlaps.
+pub fn validate(map: &[Region]) -> Result<(), MapError> {
+  for (idx, region) in map.iter().enumerate() {
+    let name = || region.name.clone();
+    if region.name.is_empty() {
+      return Err(MapError::Unnamed);
+    } else if region.size == 0 {
+      return Err(MapError::Empty(name()));
+    } else if region.kind.is_memory() && region.size > MAX_MEMORY {
+      return Err(MapError::TooLarge(name()));
+    } else if region.base.checked_add(region.size).is_none() {
+      return Err(MapError::Overflow(name()));
+    }
+
+    for other in &map[..idx] {
+      if other.name == region.name {
+        return Err(MapError::Duplicate(name()));
+      } else if other.base < region.end() && region.base < other.end() {
+        return Err(MapError::Overlap(other.name.clone(), name()));
+      } else if !region.kind.is_memory() && other.kind == region.kind {
+        return Err(MapError::Device(region.kind.name()));
+      }
+    }
+  }
+  Ok(())
+}
+
+#[derive(Clone)]
+pub struct Bus {
+  /// Memory and device regions, sorted by address.
+  pub regions: Vec<Region>,
+  pub uart: Uart,
+  pub clint: Clint,
+  pub plic: Plic,
+  /// Writes to memory so far, to notice them without comparing it.
+  pub writes: u64,
+}
+
+impl Default for Bus {
+  fn default() -> Self {
+    Self {
+      regions: virt(0x10_0000),
+      uart: Uart::default(),
+      clint: Clint::default(),
+      plic: Plic::default(),
+      writes: 0,
+    }
+  }
+}
+
+/// Bytes `offset..offset + size` of a little-endian register.
+pub(super) fn read_part(reg: u64, offset: u64, size: usize) -> u64 {
+  let val = reg >> ((offset & 7) * 8);
+  if size >= 8 { val } else { val & ((1 << (size * 8)) - 1) }
+}
+
+/// Replace bytes `offset..offset + size` of a little-endian register.
+pub(super) fn write_part(reg: &mut u64, offset: u64, size: usize, val: u64) {
+  let shift = (offset & 7) * 8;
+  let mask = if size >= 8 { u64::MAX } else { (1 << (size * 8)) - 1 };
+  *reg = *reg & !(mask << shift) | (val & mask) << shift;
+}
+
+impl Bus {
+  pub fn new(session::Bus { mut regions, dram }: session::Bus) -> Self {
+    if regions.is_empty() {
+      // Older sessions ran from a flat memory at 0 next to the devices
+      regions = virt(0).into_iter().filter(|r| !r.kind.is_memory()).collect();
+      let mut ram = Region::new(RegionKind::Ram, "dram", 0);
+      ram.size = dram.len() as u64;
+      ram.data = dram;
+      regions.push(ram);
+    } else if let Some(region) = (regions.iter_mut())
+      .find(|region| region.kind.is_memory() && region.base == 0)
+      && region.data.is_empty()
+    {
+      region.data = dram;
+    }
+    // Sizes are trusted only once checked, a broken map starts over
+    if validate(&regions).is_err() {
+      regions = virt(0x10_0000);
+    }
+    for region in &mut regions {
+      if region.kind.is_memory() {
+        region.data.resize(region.size as usize, 0);
+      }
+    }
+    regions.sort_by_key(|region| region.base);
+    Self { regions, ..Self::default() }
+  }
+
+  pub fn repr(&self) -> session::Bus {
+    let mut regions = self.regions.clone();
+    // Readers from before the memory map expect the flat memory at 0
+    let dram = (regions.iter_mut())
+      .find(|region| region.kind.is_memory() && region.base == 0)
+      .map(|region| mem::take(&mut region.data))
+      .unwrap_or_default();
+    session::Bus { regions, dram }
+  }
+
+  /// Replace the layout. Memory regions keep their contents, found by name
+  /// or else, for a renamed one, by base and kind.
+  pub fn remap(&mut self, mut map: Vec<Region>) -> Result<(), MapError> {
+    validate(&map)?;
+    let mut old: Vec<_> = mem::take(&mut self.regions)
+      .into_iter()
+      .filter(|region| region.kind.is_memory())
+      .collect();
+    let matches: [fn(&Region, &Region) -> bool; 2] = [
+      |old, new| old.name == new.name,
+      |old, new| (old.base, old.kind) == (new.base, new.kind),
+    ];
+    let mut kept = vec![None; map.len()];
+    for same in matches {
+      for (region, kept) in map.iter().zip(&mut kept) {
+        if kept.is_none()
+          && region.kind.is_memory()
+          && let Some(idx) = old.iter().position(|old| same(old, region))
+        {
+          *kept = Some(old.swap_remove(idx).data);
+        }
+      }
+    }
+    for (region, kept) in map.iter_mut().zip(kept) {
+      region.data = kept.unwrap_or_default();
+      if region.kind.is_memory() {
+        region.data.resize(region.size as usize, 0);
+      }
+    }
+    map.sort_by_key(|region| region.base);
+    self.regions = map;
+    Ok(())
+  }
+
+  pub fn region(&self, addr: u64) -> Option<&Region> {
+    self.regions.iter().find(|region| region.contains(addr))
+  }
+
+  /// Memory region holding `pc`, or else the first one.
+  pub fn code(&self, pc: u64) -> Option<&Region> {
+    let mut memory = self.regions.iter().filter(|r| r.kind.is_memory());
+    memory.clone().find(|region| region.contains(pc)).or_else(|| memory.next())
+  }
+
+  /// Advance the timer and sample the interrupt lines, once per step.
+  pub fn tick(&mut self) {
+    self.clint.mtime = self.clint.mtime.wrapping_add(1);
+    self.plic.set_level(UART_IRQ, self.uart.interrupt());
+  }
+
+  /// Little-endian read of `size` bytes, `None` if nothing is mapped there.
+  pub fn load(&mut self, addr: u64, size: usize) -> Option<u64> {
+    let region = self.region(addr)?;
+    let offset = addr - region.base;
+    Some(match region.kind {
+      RegionKind::Ram | RegionKind::Rom => return self.peek(addr, size),
+      RegionKind::Uart => self.uart.read(offset) as u64,
+      RegionKind::Clint => self.clint.read(offset, size),
+      RegionKind::Plic => self.plic.read(offset) as u64,
+    })
+  }
+
+  /// Read memory only, without the side effects of device registers.
+  pub fn peek(&self, addr: u64, size: usize) -> Option<u64> {
+    let bytes = self.memory(addr, size)?;
+    Some(bytes.iter().rev().fold(0, |acc, &byte| acc << 8 | byte as u64))
+  }
+
+  /// Little-endian write of the low `size` bytes of `val`.
+  pub fn store(&mut self, addr: u64, size: usize, val: u64) -> Option<()> {
+    let region = self.region(addr)?;
+    let offset = addr - region.base;
+    match region.kind {
+      RegionKind::Rom => return None,
+      RegionKind::Ram => return self.poke(addr, size, val),
+      RegionKind::Uart => self.uart.write(offset, val as u8),
+      RegionKind::Clint => self.clint.write(offset, size, val),
+      RegionKind::Plic => self.plic.write(offset, val as u32),
+    }
+    Some(())
+  }
+
+  /// Write memory like a debugger does, read-only regions included.
+  pub fn poke(&mut self, addr: u64, size: usize, val: u64) -> Option<()> {
+    let bytes = self.memory_mut(addr, size)?;
+    bytes.copy_from_slice(&val.to_le_bytes()[..size]);
+    Some(())
+  }
+
+  /// Whether `size` bytes at `addr` lie within a single memory region.
+  pub fn fits(&self, addr: u64, size: u64) -> bool {
+    usize::try_from(size).is_ok_and(|size| self.memory(addr, size).is_some())
+  }
+
+  /// Copy `bytes` to memory like [`Self::poke`] does.
+  pub fn write(&mut self, addr: u64, bytes: &[u8]) -> Option<()> {
+    if !bytes.is_empty() {
+      self.memory_mut(addr, bytes.len())?.copy_from_slice(bytes);
+    }
+    Some(())
+  }
+
+  fn memory(&self, addr: u64, size: usize) -> Option<&[u8]> {
+    let region = self.region(addr).filter(|r| r.kind.is_memory())?;
+    let offset = (addr - region.base) as usize;
+    region.data.get(offset..offset.checked_add(size)?)
+  }
+
+  fn memory_mut(&mut self, addr: u64, size: usize) -> Option<&mut [u8]> {
+    self.writes = self.writes.wrapping_add(1);
+    let region = self
+      .regions
+      .iter_mut()
+      .find(|region| region.contains(addr) && region.kind.is_memory())?;
+    let offset = (addr - region.base) as usize;
+    region.data.get_mut(offset..offset.checked_add(size)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ram(name: &str, base: u64, size: u64) -> Region {
+    Region { size, ..Region::new(RegionKind::Ram, name, base) }
+  }
+
+  #[test]
+  fn validates() {
+    assert_eq!(validate(&virt(0x10_0000)), Ok(()));
+    assert_eq!(validate(&[ram("", 0, 1)]), Err(MapError::Unnamed));
+    assert_eq!(
+      validate(&[ram("a", 0, 0x100), ram("b", 0xff, 0x100)]),
+      Err(MapError::Overlap("a".into(), "b".into()))
+    );
+    assert_eq!(
+      validate(&[ram("a", 0, 0x100), ram("a", 0x100, 0x100)]),
+      Err(MapError::Duplicate("a".into()))
+    );
+    let uart = |name, base| Region::new(RegionKind::Uart, name, base);
+    assert_eq!(
+      validate(&[uart("a", 0), uart("b", 0x100)]),
+      Err(MapError::Device("UART"))
+    );
+    assert_eq!(
+      validate(&[ram("a", 0, MAX_MEMORY + 1)]),
+      Err(MapError::TooLarge("a".into()))
+    );
+    assert_eq!(
+      validate(&[ram("a", u64::MAX, 2)]),
+      Err(MapError::Overflow("a".into()))
+    );
+  }
+
+  #[test]
+  fn remap_keeps_contents() {
+    let mut bus = Bus::default();
+    bus.poke(0x8000_0000, 8, 0x1122_3344_5566_7788).unwrap();
+
+    // Moved, then renamed in place, then grown
+    bus.remap(vec![ram("ram", 0x4000_0000, 0x10_0000)]).unwrap();
+    assert_eq!(bus.peek(0x4000_0000, 8), Some(0x1122_3344_5566_7788));
+    bus.remap(vec![ram("main", 0x4000_0000, 0x10_0000)]).unwrap();
+    assert_eq!(bus.peek(0x4000_0000, 8), Some(0x1122_3344_5566_7788));
+    bus.remap(vec![ram("main", 0x4000_0000, 0x20_0000)]).unwrap();
+    assert_eq!(bus.peek(0x4000_0000, 8), Some(0x1122_3344_5566_7788));
+    assert_eq!(bus.peek(0x401f_fff8, 8), Some(0));
+
+    // Moved and renamed at once is a new region
+    bus.remap(vec![ram("other", 0, 0x1000)]).unwrap();
+    assert_eq!(bus.peek(0, 8), Some(0));
+    let overlap = vec![ram("a", 0, 0x100), ram("b", 0x80, 0x100)];
+    assert!(bus.remap(overlap).is_err());
+    assert_eq!(bus.regions[0].name, "other");
+  }
+
+  #[test]
+  fn sessions() {
+    // Older sessions had a flat memory at 0
+    let legacy = session::Bus { regions: vec![], dram: vec![1, 2, 3, 4] };
+    let bus = Bus::new(legacy);
+    assert_eq!(bus.code(0).map(|region| region.size), Some(4));
+    assert_eq!(bus.peek(0, 4), Some(0x0403_0201));
+    assert_eq!(bus.region(0x1000_0000).unwrap().kind, RegionKind::Uart);
+
+    // Without memory, or with a broken map, they start over from `virt`
+    let bus = Bus::new(session::Bus { regions: vec![], dram: vec![] });
+    assert_eq!(bus.code(0).unwrap().base, 0x8000_0000);
+    let huge = ram("ram", 0, MAX_MEMORY + 1);
+    let bus = Bus::new(session::Bus { regions: vec![huge], dram: vec![] });
+    assert_eq!(bus.code(0).unwrap().size, 0x10_0000);
+
+    // The memory at 0 goes to `dram` for older readers, and back
+    let mut bus = Bus::default();
+    bus.remap(vec![ram("low", 0, 0x100), ram("high", 0x1000, 0x100)]).unwrap();
+    bus.poke(0, 1, 0xaa).unwrap();
+    bus.poke(0x1000, 1, 0xbb).unwrap();
+    let repr = bus.repr();
+    assert_eq!(repr.dram.len(), 0x100);
+    assert!(repr.regions[0].data.is_empty());
+    let bus = Bus::new(repr);
+    assert_eq!((bus.peek(0, 1), bus.peek(0x1000, 1)), (Some(0xaa), Some(0xbb)));
+  }
+}
@@ -0,0 +1,114 @@
+use super::bus::{read_part, write_part};
+
+/// Bytes of the register window.
+pub const CLINT_SIZE: u64 = 0x10000;
+
+const MSIP: u64 = 0x0;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+/// Core-local interruptor of the only hart.
+///
+/// `mtime` ticks once per retired step, so runs stay reproducible no matter
+/// how fast the host is.
+#[derive(Clone)]
+pub struct Clint {
+  pub msip: bool,
+  pub mtimecmp: u64,
+  pub mtime: u64,
+}
+
+impl Default for Clint {
+  fn default() -> Self {
+    // No timer interrupt until software programs a deadline
+    Self { msip: false, mtimecmp: u64::MAX, mtime: 0 }
+  }
+}
+
+impl Clint {
+  pub fn timer(&self) -> bool {
+    self.mtime >= self.mtimecmp
+  }
+
+  pub fn read(&self, offset: u64, size: usize) -> u64 {
+    match offset & !7 {
+      MSIP if offset < 4 => read_part(self.msip as u64, offset, size),
+      MTIMECMP => read_part(self.mtimecmp, offset, size),
+      MTIME => read_part(self.mtime, offset, size),
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, offset: u64, size: usize, val: u64) {
+    match offset & !7 {
+      MSIP if offset < 4 => {
+        let mut msip = self.msip as u64;
+        write_part(&mut msip, offset, size, val);
+        self.msip = msip & 1 != 0;
+      }
+      MTIMECMP => write_part(&mut self.mtimecmp, offset, size, val),
+      MTIME => write_part(&mut self.mtime, offset, size, val),
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::{
+      super::{Machine, csr::status::MIE},
+      *,
+    },
+    crate::repr::session::RegionKind,
+  };
+
+  const MTIP: u64 = 1 << 7;
+  const NOP: u32 = 0x00000013;
+
+  #[test]
+  fn registers() {
+    let mut clint = Clint::default();
+    assert!(!clint.timer());
+    clint.write(MTIMECMP, 4, 0x1234_5678);
+    clint.write(MTIMECMP + 4, 4, 0);
+    clint.write(MTIME + 4, 4, 0x1234_5678);
+    assert_eq!(clint.read(MTIME, 8), 0x1234_5678_0000_0000);
+    assert_eq!(clint.read(MTIMECMP + 2, 2), 0x1234);
+    assert!(clint.timer());
+
+    clint.write(MSIP, 4, 3);
+    assert!(clint.msip);
+    assert_eq!(clint.read(MSIP, 4), 1);
+    // Past `msip`, nothing is there
+    clint.write(MSIP + 4, 4, 0);
+    assert_eq!((clint.msip, clint.read(MSIP + 4, 4)), (true, 0));
+  }
+
+  #[test]
+  fn fires_at_mtimecmp() {
+    let mut machine = Machine::with_code(&[NOP; 8]);
+    let clint = RegionKind::Clint.default_base();
+    machine.bus.store(clint + MTIMECMP, 8, 3).unwrap();
+    machine.cpu.csrs.mie = MTIP;
+    machine.cpu.csrs.mstatus |= MIE;
+    machine.cpu.csrs.mtvec = Machine::RAM + 0x100;
+
+    for _ in 0..3 {
+      machine.step().unwrap();
+    }
+    assert_eq!(machine.bus.load(clint + MTIME, 8), Some(3));
+    assert_eq!(machine.cpu.pc, Machine::RAM + 12);
+    machine.step().unwrap();
+    let csrs = &machine.cpu.csrs;
+    assert_eq!(machine.cpu.pc, Machine::RAM + 0x100);
+    assert_eq!((csrs.mcause, csrs.mepc), (1 << 63 | 7, Machine::RAM + 12));
+    assert_eq!(csrs.mip & MTIP, MTIP);
+
+    // Pushing the deadline back drops it
+    machine.bus.store(clint + MTIMECMP, 8, u64::MAX).unwrap();
+    machine.cpu.csrs.mstatus |= MIE;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs.mip & MTIP, 0);
+  }
+}
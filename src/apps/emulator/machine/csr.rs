@@ -0,0 +1,443 @@
+use {
+  super::{Exception, Machine, Mode, Priv},
+  raki::ZicsrOpcode,
+};
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const SATP: u16 = 0x180;
+
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+
+/// Every implemented CSR, in the order the CSR window lists them.
+pub const CSRS: &[(u16, &str)] = &[
+  (MSTATUS, "mstatus"),
+  (MISA, "misa"),
+  (MEDELEG, "medeleg"),
+  (MIDELEG, "mideleg"),
+  (MIE, "mie"),
+  (MIP, "mip"),
+  (MTVEC, "mtvec"),
+  (MCOUNTEREN, "mcounteren"),
+  (MSCRATCH, "mscratch"),
+  (MEPC, "mepc"),
+  (MCAUSE, "mcause"),
+  (MTVAL, "mtval"),
+  (MCYCLE, "mcycle"),
+  (MINSTRET, "minstret"),
+  (MVENDORID, "mvendorid"),
+  (MARCHID, "marchid"),
+  (MIMPID, "mimpid"),
+  (MHARTID, "mhartid"),
+  (SSTATUS, "sstatus"),
+  (SIE, "sie"),
+  (SIP, "sip"),
+  (STVEC, "stvec"),
+  (SCOUNTEREN, "scounteren"),
+  (SSCRATCH, "sscratch"),
+  (SEPC, "sepc"),
+  (SCAUSE, "scause"),
+  (STVAL, "stval"),
+  (SATP, "satp"),
+  (FFLAGS, "fflags"),
+  (FRM, "frm"),
+  (FCSR, "fcsr"),
+  (CYCLE, "cycle"),
+  (TIME, "time"),
+  (INSTRET, "instret"),
+];
+
+/// CSRs that hold state of their own, the rest are views or constants.
+pub const PERSISTED: &[u16] = &[
+  FCSR, MSTATUS, MEDELEG, MIDELEG, MIE, MIP, MTVEC, MCOUNTEREN, MSCRATCH, MEPC,
+  MCAUSE, MTVAL, MCYCLE, MINSTRET, STVEC, SCOUNTEREN, SSCRATCH, SEPC, SCAUSE,
+  STVAL, SATP,
+];
+
+/// Fields of `mstatus`, `sstatus` is a restricted view of it.
+pub mod status {
+  pub const SIE: u64 = 1 << 1;
+  pub const MIE: u64 = 1 << 3;
+  pub const SPIE: u64 = 1 << 5;
+  pub const MPIE: u64 = 1 << 7;
+  pub const SPP: u64 = 1 << 8;
+  pub const MPP: u64 = 0b11 << 11;
+  pub const FS: u64 = 0b11 << 13;
+  pub const MPRV: u64 = 1 << 17;
+  pub const SUM: u64 = 1 << 18;
+  pub const MXR: u64 = 1 << 19;
+  pub const TVM: u64 = 1 << 20;
+  pub const TW: u64 = 1 << 21;
+  pub const TSR: u64 = 1 << 22;
+  pub const UXL: u64 = 0b11 << 32;
+  pub const SD: u64 = 1 << 63;
+
+  pub(super) const MSTATUS_WRITE: u64 = SIE
+    | MIE
+    | SPIE
+    | MPIE
+    | SPP
+    | MPP
+    | FS
+    | MPRV
+    | SUM
+    | MXR
+    | TVM
+    | TW
+    | TSR;
+  pub(super) const SSTATUS_WRITE: u64 = SIE | SPIE | SPP | FS | SUM | MXR;
+  pub(super) const SSTATUS_READ: u64 = SSTATUS_WRITE | UXL | SD;
+}
+
+use status::{FS, MPP, MSTATUS_WRITE, SD, SSTATUS_READ, SSTATUS_WRITE, TVM};
+
+/// Supervisor software, timer and external interrupts.
+const S_INTERRUPTS: u64 = 0x222;
+/// Every exception but an environment call from machine mode.
+const MEDELEG_WRITE: u64 = 0xb3ff;
+
+/// RV64IMAFDC with supervisor and user modes.
+const MISA_VAL: u64 = 2 << 62
+  | 1 << 0
+  | 1 << 2
+  | 1 << 3
+  | 1 << 5
+  | 1 << 8
+  | 1 << 12
+  | 1 << 18
+  | 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Csrs {
+  pub mstatus: u64,
+  pub medeleg: u64,
+  pub mideleg: u64,
+  pub mie: u64,
+  pub mip: u64,
+  pub mtvec: u64,
+  pub mcounteren: u64,
+  pub mscratch: u64,
+  pub mepc: u64,
+  pub mcause: u64,
+  pub mtval: u64,
+  pub mcycle: u64,
+  pub minstret: u64,
+  pub stvec: u64,
+  pub scounteren: u64,
+  pub sscratch: u64,
+  pub sepc: u64,
+  pub scause: u64,
+  pub stval: u64,
+  pub satp: u64,
+}
+
+impl Default for Csrs {
+  fn default() -> Self {
+    Self {
+      // 64-bit lower modes, floating point starts enabled for bare programs
+      mstatus: 2 << 32 | 2 << 34 | 1 << 13,
+      medeleg: 0,
+      mideleg: 0,
+      mie: 0,
+      mip: 0,
+      mtvec: 0,
+      mcounteren: 0,
+      mscratch: 0,
+      mepc: 0,
+      mcause: 0,
+      mtval: 0,
+      mcycle: 0,
+      minstret: 0,
+      stvec: 0,
+      scounteren: 0,
+      sscratch: 0,
+      sepc: 0,
+      scause: 0,
+      stval: 0,
+      satp: 0,
+    }
+  }
+}
+
+/// Trap vectors only support the direct and vectored modes.
+fn tvec(val: u64) -> u64 {
+  if val & 0b11 >= 2 { val & !0b11 } else { val }
+}
+
+impl Machine {
+  pub(super) fn exec_csr(
+    &mut self,
+    op: &ZicsrOpcode,
+    raw: u32,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    use ZicsrOpcode::*;
+
+    let illegal = Exception::IllegalInstruction(raw as u64);
+    let addr = (raw >> 20) as u16;
+    let rd = (raw >> 7 & 0x1f) as usize;
+    let rs1 = (raw >> 15 & 0x1f) as usize;
+
+    let src = match op {
+      CSRRW | CSRRS | CSRRC => self.cpu.x(rs1),
+      _ => rs1 as u64,
+    };
+
+    let old = self.csr_read(addr).ok_or(illegal)?;
+    // The PLIC's SEIP shows in `mip` but is not written back by set and clear
+    let base = if addr == MIP { self.cpu.csrs.mip } else { old };
+    // Set and clear never write when their source register is `x0`
+    let new = match op {
+      CSRRW | CSRRWI => Some(src),
+      _ if rs1 == 0 => None,
+      CSRRS | CSRRSI => Some(base | src),
+      _ => Some(base & !src),
+    };
+    if !self.csr_allowed(addr, new.is_some()) {
+      return Err(illegal);
+    }
+    if let Some(new) = new {
+      self.csr_write(addr, new).ok_or(illegal)?;
+      if matches!(addr, FFLAGS | FRM | FCSR) {
+        self.cpu.csrs.mstatus |= FS;
+      }
+    }
+
+    self.cpu.set_x(rd, old);
+    Ok(self.cpu.pc.wrapping_add(len))
+  }
+
+  fn csr_allowed(&self, addr: u16, write: bool) -> bool {
+    let (mode, csrs) = (self.cpu.mode, &self.cpu.csrs);
+
+    // The address encodes the lowest privilege and whether it is read-only
+    if (addr >> 8 & 0b11) as u8 > mode as u8 || write && addr >> 10 == 0b11 {
+      return false;
+    }
+    match addr {
+      FFLAGS | FRM | FCSR => csrs.mstatus & FS != 0,
+      CYCLE | TIME | INSTRET => {
+        let bit = 1 << (addr & 0x1f);
+        (mode == Priv::Machine || csrs.mcounteren & bit != 0)
+          && (mode != Priv::User || csrs.scounteren & bit != 0)
+      }
+      SATP => mode != Priv::Supervisor || csrs.mstatus & TVM == 0,
+      _ => true,
+    }
+  }
+
+  pub fn csr_read(&self, addr: u16) -> Option<u64> {
+    let (csrs, fcsr) = (&self.cpu.csrs, self.cpu.fcsr as u64);
+    let mstatus =
+      if csrs.mstatus & FS == FS { csrs.mstatus | SD } else { csrs.mstatus };
+
+    Some(match addr {
+      FFLAGS => fcsr & 0x1f,
+      FRM => fcsr >> 5 & 0b111,
+      FCSR => fcsr & 0xff,
+
+      CYCLE | MCYCLE => csrs.mcycle,
+      TIME => self.bus.clint.mtime,
+      INSTRET | MINSTRET => csrs.minstret,
+
+      SSTATUS => mstatus & SSTATUS_READ,
+      SIE => csrs.mie & csrs.mideleg,
+      SIP => self.pending() & csrs.mideleg,
+      STVEC => csrs.stvec,
+      SCOUNTEREN => csrs.scounteren,
+      SSCRATCH => csrs.sscratch,
+      SEPC => csrs.sepc,
+      SCAUSE => csrs.scause,
+      STVAL => csrs.stval,
+      SATP => csrs.satp,
+
+      MVENDORID | MARCHID | MIMPID | MHARTID => 0,
+      MSTATUS => mstatus,
+      MISA => MISA_VAL,
+      MEDELEG => csrs.medeleg,
+      MIDELEG => csrs.mideleg,
+      MIE => csrs.mie,
+      MIP => self.pending(),
+      MTVEC => csrs.mtvec,
+      MCOUNTEREN => csrs.mcounteren,
+      MSCRATCH => csrs.mscratch,
+      MEPC => csrs.mepc,
+      MCAUSE => csrs.mcause,
+      MTVAL => csrs.mtval,
+      _ => return None,
+    })
+  }
+
+  /// Write with the WARL rules of `addr`, `None` if it is not writable.
+  pub fn csr_write(&mut self, addr: u16, val: u64) -> Option<()> {
+    let (csrs, fcsr) = (&mut self.cpu.csrs, self.cpu.fcsr);
+    let masked = |old: u64, mask: u64| old & !mask | val & mask;
+
+    match addr {
+      FFLAGS => self.cpu.fcsr = fcsr & !0x1f | val as u32 & 0x1f,
+      FRM => self.cpu.fcsr = fcsr & !0xe0 | (val as u32 & 0b111) << 5,
+      FCSR => self.cpu.fcsr = val as u32 & 0xff,
+
+      MCYCLE => csrs.mcycle = val,
+      MINSTRET => csrs.minstret = val,
+
+      SSTATUS => csrs.mstatus = masked(csrs.mstatus, SSTATUS_WRITE),
+      SIE => csrs.mie = masked(csrs.mie, csrs.mideleg),
+      // Only the software interrupt is raised by software
+      SIP => csrs.mip = masked(csrs.mip, csrs.mideleg & 1 << 1),
+      STVEC => csrs.stvec = tvec(val),
+      SCOUNTEREN => csrs.scounteren = val & 0b111,
+      SSCRATCH => csrs.sscratch = val,
+      SEPC => csrs.sepc = val & !1,
+      SCAUSE => csrs.scause = val,
+      STVAL => csrs.stval = val,
+      // Unsupported translation modes leave it untouched
+      SATP => {
+        if Mode::new(val).is_some() {
+          csrs.satp = val;
+        }
+      }
+
+      MSTATUS => {
+        let mstatus = masked(csrs.mstatus, MSTATUS_WRITE);
+        // `MPP` never holds the reserved privilege level
+        csrs.mstatus =
+          if mstatus & MPP == 0b10 << 11 { mstatus & !MPP } else { mstatus };
+      }
+      MISA => {}
+      MEDELEG => csrs.medeleg = val & MEDELEG_WRITE,
+      MIDELEG => csrs.mideleg = val & S_INTERRUPTS,
+      MIE => csrs.mie = val & 0xaaa,
+      MIP => csrs.mip = masked(csrs.mip, S_INTERRUPTS),
+      MTVEC => csrs.mtvec = tvec(val),
+      MCOUNTEREN => csrs.mcounteren = val & 0b111,
+      MSCRATCH => csrs.mscratch = val,
+      MEPC => csrs.mepc = val & !1,
+      MCAUSE => csrs.mcause = val,
+      MTVAL => csrs.mtval = val,
+      _ => return None,
+    }
+    // Cached translations belong to the previous address space
+    if addr == SATP {
+      self.tlb.flush();
+    }
+    Some(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn read_modify_write() {
+    let mut machine = Machine::with_code(&[
+      0x34059573, // csrrw a0, mscratch, a1
+      0x3406a673, // csrrs a2, mscratch, a3
+      0x3407b773, // csrrc a4, mscratch, a5
+      0x301027f3, // csrr a5, misa
+    ]);
+    (machine.cpu.xregs[11], machine.cpu.xregs[13]) = (5, 0b1010);
+    machine.cpu.xregs[15] = 0b0011;
+    for _ in 0..4 {
+      machine.step().unwrap();
+    }
+
+    let x = |idx| machine.cpu.x(idx);
+    assert_eq!((x(10), x(12), x(14)), (0, 5, 0b1111));
+    assert_eq!(machine.cpu.csrs.mscratch, 0b1100);
+    assert_eq!(x(15), MISA_VAL);
+  }
+
+  #[test]
+  fn warl_fields() {
+    let mut machine = Machine::default();
+    machine.csr_write(MTVEC, 0x8000_0002).unwrap();
+    assert_eq!(machine.csr_read(MTVEC), Some(0x8000_0000));
+    machine.csr_write(MTVEC, 0x8000_0001).unwrap();
+    assert_eq!(machine.csr_read(MTVEC), Some(0x8000_0001));
+
+    machine.csr_write(MSTATUS, 0b10 << 11).unwrap();
+    assert_eq!(machine.cpu.csrs.mstatus & MPP, 0);
+    machine.csr_write(MISA, 0).unwrap();
+    assert_eq!(machine.csr_read(MISA), Some(MISA_VAL));
+    machine.csr_write(MEDELEG, u64::MAX).unwrap();
+    assert_eq!(machine.cpu.csrs.medeleg >> 11 & 1, 0);
+
+    machine.csr_write(SSTATUS, status::SIE | status::MIE).unwrap();
+    assert_eq!(machine.cpu.csrs.mstatus & status::MIE, 0);
+    assert_eq!(machine.cpu.csrs.mstatus & status::SIE, status::SIE);
+    assert_eq!(machine.csr_read(0x7ff), None);
+    assert_eq!(machine.csr_write(0x7ff, 0), None);
+  }
+
+  #[test]
+  fn pending_reads_the_plic() {
+    let seip = 1 << 9;
+    let mut machine = Machine::with_code(&[
+      0x34416073, // csrrsi zero, mip, 2
+    ]);
+    machine.bus.plic.priority[3] = 1;
+    machine.bus.plic.enable[1] = 1 << 3;
+    machine.bus.plic.set_level(3, true);
+    assert_eq!(machine.csr_read(MIP), Some(seip));
+    assert_eq!(machine.csr_read(SIP), Some(0));
+    machine.cpu.csrs.mideleg = seip;
+    assert_eq!(machine.csr_read(SIP), Some(seip));
+
+    // Set and clear leave it out of what they write
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs.mip, 1 << 1);
+    assert_eq!(machine.csr_read(MIP), Some(seip | 1 << 1));
+  }
+
+  #[test]
+  fn privilege_checks() {
+    let raw = 0x34202573; // csrr a0, mcause
+    let mut machine = Machine::with_code(&[raw]);
+    machine.cpu.mode = Priv::Supervisor;
+    assert_eq!(machine.step(), Err(Exception::IllegalInstruction(raw as u64)));
+
+    // Writes to the read-only `cycle` are illegal, reads need `mcounteren`
+    let raw = 0xc0001573; // csrrw a0, cycle, x0
+    let mut machine = Machine::with_code(&[raw, 0xc0002573]);
+    assert_eq!(machine.step(), Err(Exception::IllegalInstruction(raw as u64)));
+    machine.cpu.pc += 4;
+    machine.cpu.mode = Priv::Supervisor;
+    assert!(machine.step().is_err());
+    machine.cpu.csrs.mcounteren = 1;
+    machine.step().unwrap();
+  }
+}
@@ -0,0 +1,481 @@
+use {
+  super::elf::{Elf, ElfError, cstr},
+  crate::repr::session::LineTable,
+  std::collections::HashMap,
+};
+
+// Standard opcodes of the line number program
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK2: u64 = 0x03;
+const DW_FORM_BLOCK4: u64 = 0x04;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_BLOCK1: u64 = 0x0a;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+/// Sequential reads through a DWARF section.
+struct Cursor<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], ElfError> {
+    let end = self.pos.checked_add(len).ok_or(ElfError::Truncated)?;
+    let bytes = self.bytes.get(self.pos..end).ok_or(ElfError::Truncated)?;
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  fn uint(&mut self, size: usize) -> Result<u64, ElfError> {
+    let bytes = self.take(size)?;
+    Ok(bytes.iter().rev().fold(0, |acc, &byte| acc << 8 | byte as u64))
+  }
+
+  fn u8(&mut self) -> Result<u8, ElfError> {
+    self.uint(1).map(|val| val as u8)
+  }
+
+  fn uleb(&mut self) -> Result<u64, ElfError> {
+    let (mut val, mut shift) = (0, 0);
+    loop {
+      let byte = self.u8()?;
+      if shift < 64 {
+        val |= ((byte & 0x7f) as u64) << shift;
+      }
+      shift += 7;
+      if byte & 0x80 == 0 {
+        return Ok(val);
+      }
+    }
+  }
+
+  fn sleb(&mut self) -> Result<i64, ElfError> {
+    let (mut val, mut shift) = (0i64, 0);
+    loop {
+      let byte = self.u8()?;
+      if shift < 64 {
+        val |= ((byte & 0x7f) as i64) << shift;
+      }
+      shift += 7;
+      if byte & 0x80 == 0 {
+        if shift < 64 && byte & 0x40 != 0 {
+          val |= -1 << shift;
+        }
+        return Ok(val);
+      }
+    }
+  }
+
+  fn cstr(&mut self) -> Result<&'a [u8], ElfError> {
+    let tail = self.bytes.get(self.pos..).ok_or(ElfError::Truncated)?;
+    let len = tail.iter().position(|&byte| byte == 0);
+    let str = self.take(len.ok_or(ElfError::Truncated)?)?;
+    self.pos += 1;
+    Ok(str)
+  }
+}
+
+/// String tables DWARF 5 headers point into.
+struct Strings<'a> {
+  str: &'a [u8],
+  line_str: &'a [u8],
+}
+
+/// Path of a file entry, or nothing for other content.
+fn form<'a>(
+  unit: &mut Cursor<'a>,
+  strings: &Strings<'a>,
+  form: u64,
+  offset: usize,
+) -> Result<Option<&'a [u8]>, ElfError> {
+  let skip = match form {
+    DW_FORM_STRING => return unit.cstr().map(Some),
+    DW_FORM_STRP | DW_FORM_LINE_STRP => {
+      let at = unit.uint(offset)? as usize;
+      let table =
+        if form == DW_FORM_STRP { strings.str } else { strings.line_str };
+      return Ok(Some(cstr(table, at)));
+    }
+    DW_FORM_UDATA => return unit.uleb().map(|_| None),
+    DW_FORM_DATA1 => 1,
+    DW_FORM_DATA2 => 2,
+    DW_FORM_DATA4 => 4,
+    DW_FORM_DATA8 => 8,
+    DW_FORM_DATA16 => 16,
+    DW_FORM_BLOCK1 => unit.uint(1)? as usize,
+    DW_FORM_BLOCK2 => unit.uint(2)? as usize,
+    DW_FORM_BLOCK4 => unit.uint(4)? as usize,
+    DW_FORM_BLOCK => unit.uleb()? as usize,
+    _ => return Err(ElfError::DebugLine("unknown attribute form")),
+  };
+  unit.take(skip).map(|_| None)
+}
+
+/// Value of a numeric form, for directory indices.
+fn data(unit: &mut Cursor, form: u64) -> Result<u64, ElfError> {
+  match form {
+    DW_FORM_UDATA => unit.uleb(),
+    DW_FORM_DATA1 => unit.uint(1),
+    DW_FORM_DATA2 => unit.uint(2),
+    _ => Err(ElfError::DebugLine("unknown directory index form")),
+  }
+}
+
+/// Directory or file entries of a DWARF 5 header, as `(path, directory)`.
+fn entries<'a>(
+  unit: &mut Cursor<'a>,
+  strings: &Strings<'a>,
+  offset: usize,
+) -> Result<Vec<(&'a [u8], u64)>, ElfError> {
+  let count = unit.u8()?;
+  let formats = (0..count)
+    .map(|_| Ok((unit.uleb()?, unit.uleb()?)))
+    .collect::<Result<Vec<_>, ElfError>>()?;
+
+  let mut entries = vec![];
+  for _ in 0..unit.uleb()? {
+    let (mut path, mut dir) = (&[][..], 0);
+    for &(content, kind) in &formats {
+      match content {
+        DW_LNCT_DIRECTORY_INDEX => dir = data(unit, kind)?,
+        DW_LNCT_PATH => {
+          path = form(unit, strings, kind, offset)?.unwrap_or_default();
+        }
+        _ => {
+          form(unit, strings, kind, offset)?;
+        }
+      }
+    }
+    entries.push((path, dir));
+  }
+  Ok(entries)
+}
+
+fn join(dir: &[u8], name: &[u8]) -> String {
+  let name = String::from_utf8_lossy(name);
+  if dir.is_empty() || name.starts_with('/') {
+    return name.into_owned();
+  }
+  format!("{}/{name}", String::from_utf8_lossy(dir).trim_end_matches('/'))
+}
+
+/// Rows of every line number program, merged into one table.
+#[derive(Default)]
+struct Builder {
+  table: LineTable,
+  paths: HashMap<String, u32>,
+}
+
+impl Builder {
+  fn file(&mut self, path: String) -> u32 {
+    let files = &mut self.table.files;
+    *self.paths.entry(path).or_insert_with_key(|path| {
+      files.push(path.clone());
+      files.len() as u32 - 1
+    })
+  }
+
+  /// One line number program, with offsets of `offset` bytes.
+  fn unit<'a>(
+    &mut self,
+    unit: &mut Cursor<'a>,
+    strings: &Strings<'a>,
+    offset: usize,
+  ) -> Result<(), ElfError> {
+    let version = unit.uint(2)?;
+    if !(2..=5).contains(&version) {
+      return Err(ElfError::DebugLine("unknown version"));
+    }
+    if version >= 5 {
+      // Address and segment selector sizes
+      unit.take(2)?;
+    }
+    let header = unit.uint(offset)? as usize;
+    let program = unit.pos.saturating_add(header);
+
+    let min_inst = unit.u8()? as u64;
+    if version >= 4 {
+      // Maximum operations per instruction, always one outside VLIW
+      unit.u8()?;
+    }
+    // `is_stmt` only matters to debuggers placing breakpoints
+    unit.u8()?;
+    let line_base = unit.u8()? as i8 as i64;
+    let line_range = unit.u8()?;
+    let opcode_base = unit.u8()?;
+    if line_range == 0 || opcode_base == 0 {
+      return Err(ElfError::DebugLine("empty line range"));
+    }
+    let lengths = unit.take(opcode_base as usize - 1)?;
+
+    // Files of this unit, numbered from one before DWARF 5
+    let first = if version >= 5 { 0 } else { 1 };
+    let mut files = vec![];
+    if version >= 5 {
+      let dirs = entries(unit, strings, offset)?;
+      for (name, dir) in entries(unit, strings, offset)? {
+        let dir = dirs.get(dir as usize).map_or(&[][..], |&(dir, _)| dir);
+        files.push(self.file(join(dir, name)));
+      }
+    } else {
+      let mut dirs = vec![&[][..]];
+      loop {
+        match unit.cstr()? {
+          [] => break,
+          dir => dirs.push(dir),
+        }
+      }
+      loop {
+        let name = unit.cstr()?;
+        if name.is_empty() {
+          break;
+        }
+        let dir = unit.uleb()? as usize;
+        unit.uleb()?;
+        unit.uleb()?;
+        let dir = dirs.get(dir).copied().unwrap_or_default();
+        files.push(self.file(join(dir, name)));
+      }
+    }
+    unit.pos = program;
+
+    let row = |addr, file: u64, line: i64| {
+      let file = files.get(file.wrapping_sub(first) as usize)?;
+      Some((addr, *file, line.max(1) as u32))
+    };
+    let rows = &mut self.table.rows;
+    let range = line_range as u64;
+    let (mut addr, mut file, mut line) = (0u64, 1, 1i64);
+
+    while unit.pos < unit.bytes.len() {
+      match unit.u8()? {
+        op if op >= opcode_base => {
+          let adjusted = (op - opcode_base) as u64;
+          addr = addr.wrapping_add(adjusted / range * min_inst);
+          line = line.wrapping_add(line_base + (adjusted % range) as i64);
+          rows.extend(row(addr, file, line));
+        }
+        0 => {
+          let len = unit.uleb()? as usize;
+          let end = unit.pos.saturating_add(len);
+          match unit.u8()? {
+            DW_LNE_END_SEQUENCE => {
+              // Line zero ends the sequence, no source follows it
+              rows.push((addr, 0, 0));
+              (addr, file, line) = (0, 1, 1);
+            }
+            DW_LNE_SET_ADDRESS => addr = unit.uint(len.saturating_sub(1))?,
+            DW_LNE_DEFINE_FILE => {
+              return Err(ElfError::DebugLine("DW_LNE_define_file"));
+            }
+            _ => {}
+          }
+          unit.pos = end;
+        }
+        DW_LNS_COPY => rows.extend(row(addr, file, line)),
+        DW_LNS_ADVANCE_PC => {
+          addr = addr.wrapping_add(unit.uleb()?.wrapping_mul(min_inst));
+        }
+        DW_LNS_ADVANCE_LINE => line = line.wrapping_add(unit.sleb()?),
+        DW_LNS_SET_FILE => file = unit.uleb()?,
+        DW_LNS_CONST_ADD_PC => {
+          let adjusted = (255 - opcode_base) as u64;
+          addr = addr.wrapping_add(adjusted / range * min_inst);
+        }
+        DW_LNS_FIXED_ADVANCE_PC => addr = addr.wrapping_add(unit.uint(2)?),
+        op => {
+          // Operands of opcodes that only touch unused registers
+          for _ in 0..lengths[op as usize - 1] {
+            unit.uleb()?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Elf<'_> {
+  /// Line table of `.debug_line`, empty without debug info.
+  pub fn lines(&self) -> Result<LineTable, ElfError> {
+    let Some(section) = self.section(".debug_line")? else {
+      return Ok(LineTable::default());
+    };
+    let strings = Strings {
+      str: self.section(".debug_str")?.unwrap_or_default(),
+      line_str: self.section(".debug_line_str")?.unwrap_or_default(),
+    };
+
+    let mut builder = Builder::default();
+    let mut lines = Cursor { bytes: section, pos: 0 };
+    while lines.pos < section.len() {
+      // 64-bit DWARF escapes its length and widens offsets
+      let (len, offset) = match lines.uint(4)? {
+        0xffff_ffff => (lines.uint(8)?, 8),
+        len => (len, 4),
+      };
+      let bytes = lines.take(len as usize)?;
+      builder.unit(&mut Cursor { bytes, pos: 0 }, &strings, offset)?;
+    }
+
+    let mut table = builder.table;
+    // Ends of sequences first, so that a sequence starting there wins
+    table.rows.sort_by_key(|&(addr, _, line)| (addr, line != 0));
+    Ok(table)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Minimum instruction length, maximum operations, `is_stmt`, line base
+  /// and range, opcode base and the operand counts of the standard opcodes.
+  const PARAMS: [u8; 18] =
+    [1, 1, 1, 0xfb, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+  /// A line number program without its length.
+  fn unit(version: u16, header: &[u8], program: &[u8]) -> Vec<u8> {
+    let mut bytes = version.to_le_bytes().to_vec();
+    if version >= 5 {
+      bytes.extend([8, 0]);
+    }
+    bytes.extend((header.len() as u32).to_le_bytes());
+    bytes.extend(header);
+    bytes.extend(program);
+    bytes
+  }
+
+  fn run(bytes: &[u8], line_str: &[u8]) -> Result<LineTable, ElfError> {
+    let strings = Strings { str: &[], line_str };
+    let mut builder = Builder::default();
+    builder.unit(&mut Cursor { bytes, pos: 0 }, &strings, 4)?;
+    Ok(builder.table)
+  }
+
+  #[test]
+  fn leb128() {
+    let mut cursor =
+      Cursor { bytes: &[0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0x7f], pos: 0 };
+    assert_eq!(cursor.uleb(), Ok(624485));
+    assert_eq!(cursor.sleb(), Ok(-123456));
+    assert_eq!(cursor.sleb(), Ok(-1));
+    assert_eq!(cursor.uleb(), Err(ElfError::Truncated));
+  }
+
+  #[test]
+  fn version_4() {
+    let mut header = vec![2];
+    header.extend(&PARAMS[1..]);
+    header.extend(b"src\0\0main.c\0\x01\0\0/abs/lib.h\0\0\0\0\0");
+    let mut program = vec![0, 9, DW_LNE_SET_ADDRESS];
+    program.extend(0x8000_0000_u64.to_le_bytes());
+    program.extend([
+      DW_LNS_ADVANCE_LINE,
+      9,
+      DW_LNS_COPY,
+      // Two instructions and one line further
+      13 + 2 * 14 + 6,
+      DW_LNS_SET_FILE,
+      2,
+      DW_LNS_ADVANCE_PC,
+      4,
+      DW_LNS_ADVANCE_LINE,
+      0x78,
+      DW_LNS_COPY,
+      DW_LNS_CONST_ADD_PC,
+      DW_LNS_FIXED_ADVANCE_PC,
+      2,
+      0,
+      // `DW_LNS_set_isa` is skipped over
+      12,
+      5,
+      0,
+      1,
+      DW_LNE_END_SEQUENCE,
+    ]);
+
+    let table = run(&unit(4, &header, &program), &[]).unwrap();
+    assert_eq!(table.files, ["src/main.c", "/abs/lib.h"]);
+    assert_eq!(
+      table.rows,
+      [
+        (0x8000_0000, 0, 10),
+        (0x8000_0004, 0, 11),
+        (0x8000_000c, 1, 3),
+        (0x8000_0030, 0, 0)
+      ]
+    );
+  }
+
+  #[test]
+  fn version_5() {
+    let mut header = PARAMS.to_vec();
+    // Directories by `.debug_line_str` offset
+    header.extend([
+      1,
+      DW_LNCT_PATH as u8,
+      DW_FORM_LINE_STRP as u8,
+      1,
+      0,
+      0,
+      0,
+      0,
+    ]);
+    // Files with an inline name, a directory and an MD5 sum
+    header.extend([3, 1, 0x08, 2, 0x0f, 5, 0x1e, 1]);
+    header.extend(b"main.c\0\0");
+    header.extend([0; 16]);
+    let mut program = vec![0, 9, DW_LNE_SET_ADDRESS];
+    program.extend(0x1000_u64.to_le_bytes());
+    program.extend([
+      DW_LNS_SET_FILE,
+      0,
+      DW_LNS_COPY,
+      0,
+      1,
+      DW_LNE_END_SEQUENCE,
+    ]);
+
+    let table = run(&unit(5, &header, &program), b"/home/src\0").unwrap();
+    assert_eq!(table.files, ["/home/src/main.c"]);
+    assert_eq!(table.rows, [(0x1000, 0, 1), (0x1000, 0, 0)]);
+  }
+
+  #[test]
+  fn rejects_malformed_programs() {
+    let header = [&PARAMS[..], b"\0\0"].concat();
+    assert_eq!(
+      run(&unit(6, &header, &[]), &[]).err(),
+      Some(ElfError::DebugLine("unknown version"))
+    );
+    let define = [0, 2, DW_LNE_DEFINE_FILE, 0];
+    assert_eq!(
+      run(&unit(4, &header, &define), &[]).err(),
+      Some(ElfError::DebugLine("DW_LNE_define_file"))
+    );
+    let bytes = unit(4, &header, &[DW_LNS_ADVANCE_PC]);
+    assert_eq!(run(&bytes, &[]).err(), Some(ElfError::Truncated));
+  }
+}
@@ -0,0 +1,439 @@
+use {super::Machine, crate::repr::session::Symbol};
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LE: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHF_EXECINSTR: u64 = 0x4;
+
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+const SHDR_SIZE: u64 = 64;
+const SYM_SIZE: u64 = 24;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+  #[error("file is truncated")]
+  Truncated,
+  #[error("only 64-bit ELF files run on RV64")]
+  Class,
+  #[error("big-endian ELF files are not supported")]
+  Endian,
+  #[error("ELF file is for machine {0}, not RISC-V")]
+  Machine(u16),
+  #[error("ELF file of type {0} is not an executable")]
+  Type(u16),
+  #[error("segment {0} holds more bytes than it occupies in memory")]
+  Segment(usize),
+  #[error("{size:#x} bytes at {addr:#x} do not fit in a memory region")]
+  Unmapped { addr: u64, size: u64 },
+  #[error("unsupported .debug_line: {0}")]
+  DebugLine(&'static str),
+}
+
+/// Loadable segment of an ELF file.
+#[derive(Debug, Copy, Clone)]
+pub struct Segment {
+  pub paddr: u64,
+  pub offset: u64,
+  pub filesz: u64,
+  pub memsz: u64,
+}
+
+/// Header fields of an ELF64 RISC-V executable.
+#[derive(Debug, Clone)]
+pub struct Elf<'a> {
+  pub bytes: &'a [u8],
+  pub entry: u64,
+  pub segments: Vec<Segment>,
+  shoff: u64,
+  shentsize: u64,
+  shnum: u64,
+  shstrndx: u64,
+}
+
+/// Zero-terminated string at `offset` of a string table.
+pub(super) fn cstr(table: &[u8], offset: usize) -> &[u8] {
+  let tail = table.get(offset..).unwrap_or_default();
+  tail.split(|&byte| byte == 0).next().unwrap_or_default()
+}
+
+/// Offset of entry `idx` of a table at `base`, file offsets are untrusted.
+fn offset(base: u64, idx: u64, size: u64) -> Result<u64, ElfError> {
+  let offset = idx.checked_mul(size).and_then(|off| base.checked_add(off));
+  offset.ok_or(ElfError::Truncated)
+}
+
+/// Little-endian reads at offsets of the file, failing past its end.
+pub(super) struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+  pub fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], ElfError> {
+    let start = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+    let len = usize::try_from(len).map_err(|_| ElfError::Truncated)?;
+    let end = start.checked_add(len).ok_or(ElfError::Truncated)?;
+    self.0.get(start..end).ok_or(ElfError::Truncated)
+  }
+
+  pub fn uint(&self, offset: u64, size: u64) -> Result<u64, ElfError> {
+    let bytes = self.bytes(offset, size)?;
+    Ok(bytes.iter().rev().fold(0, |acc, &byte| acc << 8 | byte as u64))
+  }
+
+  pub fn u16(&self, offset: u64) -> Result<u16, ElfError> {
+    self.uint(offset, 2).map(|val| val as u16)
+  }
+
+  pub fn u32(&self, offset: u64) -> Result<u32, ElfError> {
+    self.uint(offset, 4).map(|val| val as u32)
+  }
+
+  pub fn u64(&self, offset: u64) -> Result<u64, ElfError> {
+    self.uint(offset, 8)
+  }
+}
+
+impl<'a> Elf<'a> {
+  pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+  }
+
+  pub fn parse(bytes: &'a [u8]) -> Result<Self, ElfError> {
+    let file = Reader(bytes);
+    let ident = file.bytes(0, EHDR_SIZE)?;
+    if ident[4] != CLASS_64 {
+      return Err(ElfError::Class);
+    } else if ident[5] != DATA_LE {
+      return Err(ElfError::Endian);
+    }
+
+    match file.u16(0x12)? {
+      EM_RISCV => {}
+      machine => return Err(ElfError::Machine(machine)),
+    }
+    match file.u16(0x10)? {
+      ET_EXEC | ET_DYN => {}
+      kind => return Err(ElfError::Type(kind)),
+    }
+
+    let entry = file.u64(0x18)?;
+    let phoff = file.u64(0x20)?;
+    let phentsize = file.u16(0x36)? as u64;
+    let phnum = file.u16(0x38)? as u64;
+    let shoff = file.u64(0x28)?;
+    let shentsize = (file.u16(0x3a)? as u64).max(SHDR_SIZE);
+    let shnum = file.u16(0x3c)? as u64;
+    let shstrndx = file.u16(0x3e)? as u64;
+
+    let mut segments = vec![];
+    for idx in 0..phnum {
+      let phdr = offset(phoff, idx, phentsize.max(PHDR_SIZE))?;
+      // Checked whole, so that the fields cannot overflow
+      file.bytes(phdr, PHDR_SIZE)?;
+      if file.u32(phdr)? != PT_LOAD {
+        continue;
+      }
+      let segment = Segment {
+        offset: file.u64(phdr + 8)?,
+        paddr: file.u64(phdr + 24)?,
+        filesz: file.u64(phdr + 32)?,
+        memsz: file.u64(phdr + 40)?,
+      };
+      if segment.filesz > segment.memsz {
+        return Err(ElfError::Segment(idx as usize));
+      }
+      file.bytes(segment.offset, segment.filesz)?;
+      segments.push(segment);
+    }
+    Ok(Self { bytes, entry, segments, shoff, shentsize, shnum, shstrndx })
+  }
+
+  fn shdr(&self, idx: u64) -> Result<u64, ElfError> {
+    let shdr = offset(self.shoff, idx, self.shentsize)?;
+    Reader(self.bytes).bytes(shdr, SHDR_SIZE)?;
+    Ok(shdr)
+  }
+
+  /// Contents of the section called `name`, if there is one.
+  pub fn section(&self, name: &str) -> Result<Option<&'a [u8]>, ElfError> {
+    // Stripped of section headers, or of their names
+    if self.shstrndx >= self.shnum {
+      return Ok(None);
+    }
+    let file = Reader(self.bytes);
+    let names = self.shdr(self.shstrndx)?;
+    let names = file.bytes(file.u64(names + 0x18)?, file.u64(names + 0x20)?)?;
+
+    for idx in 0..self.shnum {
+      let shdr = self.shdr(idx)?;
+      if cstr(names, file.u32(shdr)? as usize) == name.as_bytes() {
+        let (offset, size) = (file.u64(shdr + 0x18)?, file.u64(shdr + 0x20)?);
+        return file.bytes(offset, size).map(Some);
+      }
+    }
+    Ok(None)
+  }
+
+  /// Named functions and objects of `.symtab`, empty once stripped.
+  pub fn symbols(&self) -> Result<Vec<Symbol>, ElfError> {
+    let file = Reader(self.bytes);
+
+    let mut symbols = vec![];
+    for idx in 0..self.shnum {
+      let shdr = self.shdr(idx)?;
+      if file.u32(shdr + 4)? != SHT_SYMTAB {
+        continue;
+      }
+      let (offset, size) = (file.u64(shdr + 0x18)?, file.u64(shdr + 0x20)?);
+      let strtab = self.shdr(file.u32(shdr + 0x28)? as u64)?;
+      let strings =
+        file.bytes(file.u64(strtab + 0x18)?, file.u64(strtab + 0x20)?)?;
+
+      file.bytes(offset, size)?;
+      for sym in (offset..offset + size).step_by(SYM_SIZE as usize) {
+        let info = file.uint(sym + 4, 1)? as u8;
+        if !matches!(info & 0xf, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+          || file.u16(sym + 6)? == SHN_UNDEF
+        {
+          continue;
+        }
+        let shndx = file.u16(sym + 6)? as u64;
+        // Labels without a type are code when their section is
+        let code = info & 0xf != STT_OBJECT
+          && shndx < self.shnum
+          && file.u64(self.shdr(shndx)? + 8)? & SHF_EXECINSTR != 0;
+        let name = cstr(strings, file.u32(sym)? as usize);
+        // Mapping symbols like `$x` mark code and data, not names
+        if name.is_empty() || name.starts_with(b"$") {
+          continue;
+        }
+        symbols.push(Symbol {
+          name: String::from_utf8_lossy(name).into_owned(),
+          addr: file.u64(sym + 8)?,
+          size: file.u64(sym + 16)?,
+          code,
+        });
+      }
+    }
+    Ok(symbols)
+  }
+}
+
+impl Machine {
+  /// Load an ELF executable at its physical addresses and jump to its entry,
+  /// handing it back for its debug info.
+  ///
+  /// Anything else is a flat image copied to the start of the code region.
+  pub fn load_image<'a>(
+    &mut self,
+    bytes: &'a [u8],
+  ) -> Result<Option<Elf<'a>>, ElfError> {
+    if !Elf::is_elf(bytes) {
+      let pc = self.cpu.pc;
+      let base = self.bus.code(pc).map_or(0, |region| region.base);
+      let size = bytes.len() as u64;
+      self
+        .bus
+        .write(base, bytes)
+        .ok_or(ElfError::Unmapped { addr: base, size })?;
+      self.cpu.pc = base;
+      self.tlb.flush();
+      return Ok(None);
+    }
+
+    let elf = Elf::parse(bytes)?;
+    // Checked first, so that a bad segment leaves the memory untouched
+    for segment in &elf.segments {
+      let (addr, size) = (segment.paddr, segment.memsz);
+      if size != 0 && !self.bus.fits(addr, size) {
+        return Err(ElfError::Unmapped { addr, size });
+      }
+    }
+    let file = Reader(elf.bytes);
+    for segment in &elf.segments {
+      let data = file.bytes(segment.offset, segment.filesz)?;
+      let bss = vec![0; (segment.memsz - segment.filesz) as usize];
+      self.bus.write(segment.paddr, data);
+      // `.bss` and the like have no bytes in the file
+      self.bus.write(segment.paddr + segment.filesz, &bss);
+    }
+    self.cpu.pc = elf.entry;
+    self.cpu.reservation = None;
+    self.tlb.flush();
+    Ok(Some(elf))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ENTRY: u64 = Machine::RAM + 4;
+  const CODE: [u8; 8] = [0x13, 0, 0, 0, 0x73, 0, 0x10, 0];
+  const SHOFF: usize = 0x1a0;
+
+  fn put(bytes: &mut [u8], offset: usize, size: usize, val: u64) {
+    bytes[offset..offset + size].copy_from_slice(&val.to_le_bytes()[..size]);
+  }
+
+  fn sym(bytes: &mut [u8], idx: usize, name: u64, info: u64, addr: u64) {
+    let sym = 0x140 + idx * SYM_SIZE as usize;
+    put(bytes, sym, 4, name);
+    put(bytes, sym + 4, 1, info);
+    put(bytes, sym + 6, 2, 1);
+    put(bytes, sym + 8, 8, addr);
+    put(bytes, sym + 16, 8, 4);
+  }
+
+  fn shdr(bytes: &mut [u8], idx: usize, fields: [u64; 5]) {
+    let [name, kind, flags, offset, size] = fields;
+    let shdr = SHOFF + idx * SHDR_SIZE as usize;
+    put(bytes, shdr, 4, name);
+    put(bytes, shdr + 4, 4, kind);
+    put(bytes, shdr + 8, 8, flags);
+    put(bytes, shdr + 0x18, 8, offset);
+    put(bytes, shdr + 0x20, 8, size);
+  }
+
+  /// Executable loading `CODE` and 8 bytes of `.bss` at the start of RAM,
+  /// with `.text`, `.shstrtab`, `.symtab` and `.strtab` sections.
+  fn executable() -> Vec<u8> {
+    let mut bytes = vec![0; SHOFF + 5 * SHDR_SIZE as usize];
+    bytes[..4].copy_from_slice(MAGIC);
+    (bytes[4], bytes[5]) = (CLASS_64, DATA_LE);
+    put(&mut bytes, 0x10, 2, ET_EXEC as u64);
+    put(&mut bytes, 0x12, 2, EM_RISCV as u64);
+    put(&mut bytes, 0x18, 8, ENTRY);
+    put(&mut bytes, 0x20, 8, EHDR_SIZE);
+    put(&mut bytes, 0x28, 8, SHOFF as u64);
+    put(&mut bytes, 0x36, 2, PHDR_SIZE);
+    put(&mut bytes, 0x38, 2, 1);
+    put(&mut bytes, 0x3a, 2, SHDR_SIZE);
+    put(&mut bytes, 0x3c, 2, 5);
+    put(&mut bytes, 0x3e, 2, 2);
+
+    let phdr = EHDR_SIZE as usize;
+    put(&mut bytes, phdr, 4, PT_LOAD as u64);
+    put(&mut bytes, phdr + 8, 8, 0x100);
+    put(&mut bytes, phdr + 24, 8, Machine::RAM);
+    put(&mut bytes, phdr + 32, 8, 8);
+    put(&mut bytes, phdr + 40, 8, 16);
+    bytes[0x100..0x108].copy_from_slice(&CODE);
+
+    let names = b"\0.shstrtab\0.symtab\0.strtab\0.text\0";
+    bytes[0x108..0x108 + names.len()].copy_from_slice(names);
+    let strings = b"\0_start\0buf\0$x\0";
+    bytes[0x130..0x130 + strings.len()].copy_from_slice(strings);
+    sym(&mut bytes, 1, 1, 1 << 4 | STT_FUNC as u64, ENTRY);
+    sym(&mut bytes, 2, 8, STT_OBJECT as u64, Machine::RAM + 8);
+    sym(&mut bytes, 3, 12, STT_NOTYPE as u64, Machine::RAM);
+
+    shdr(&mut bytes, 1, [27, 1, 0x6, 0x100, 8]);
+    shdr(&mut bytes, 2, [1, 3, 0, 0x108, names.len() as u64]);
+    shdr(&mut bytes, 3, [11, SHT_SYMTAB as u64, 0, 0x140, 4 * SYM_SIZE]);
+    put(&mut bytes, SHOFF + 3 * SHDR_SIZE as usize + 0x28, 4, 4);
+    shdr(&mut bytes, 4, [19, 3, 0, 0x130, strings.len() as u64]);
+    bytes
+  }
+
+  #[test]
+  fn parses_an_executable() {
+    let bytes = executable();
+    let elf = Elf::parse(&bytes).unwrap();
+    assert_eq!(elf.entry, ENTRY);
+    let [segment] = elf.segments[..] else { panic!("one segment") };
+    assert_eq!(
+      (segment.paddr, segment.filesz, segment.memsz),
+      (Machine::RAM, 8, 16)
+    );
+
+    assert_eq!(elf.section(".text"), Ok(Some(&CODE[..])));
+    assert_eq!(elf.section(".debug_line"), Ok(None));
+    let symbol = |name: &str, addr, code| Symbol {
+      name: name.to_string(),
+      addr,
+      size: 4,
+      code,
+    };
+    assert_eq!(
+      elf.symbols(),
+      Ok(vec![
+        symbol("_start", ENTRY, true),
+        symbol("buf", Machine::RAM + 8, false)
+      ])
+    );
+  }
+
+  #[test]
+  fn loads_segments() {
+    let bytes = executable();
+    let mut machine = Machine::default();
+    machine.bus.write(Machine::RAM + 8, &[0xff; 8]).unwrap();
+    machine.load_image(&bytes).unwrap().unwrap();
+    assert_eq!(machine.cpu.pc, ENTRY);
+    assert_eq!(
+      machine.bus.peek(Machine::RAM, 8),
+      Some(u64::from_le_bytes(CODE))
+    );
+    assert_eq!(machine.bus.peek(Machine::RAM + 8, 8), Some(0));
+
+    // Flat images land at the start of the code region
+    machine.load_image(&CODE[4..]).unwrap();
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+    assert_eq!(machine.bus.peek(Machine::RAM, 4), Some(0x00100073));
+  }
+
+  #[test]
+  fn rejects_malformed_headers() {
+    let patched = |offset, size, val| {
+      let mut bytes = executable();
+      put(&mut bytes, offset, size, val);
+      Elf::parse(&bytes).map(|_| ())
+    };
+    assert_eq!(
+      Elf::parse(&executable()[..0x104]).err(),
+      Some(ElfError::Truncated)
+    );
+    assert_eq!(patched(4, 1, 1), Err(ElfError::Class));
+    assert_eq!(patched(5, 1, 2), Err(ElfError::Endian));
+    assert_eq!(patched(0x12, 2, 62), Err(ElfError::Machine(62)));
+    assert_eq!(patched(0x10, 2, 1), Err(ElfError::Type(1)));
+    assert_eq!(patched(0x20, 8, u64::MAX - 8), Err(ElfError::Truncated));
+    assert_eq!(patched(0x48, 8, u64::MAX), Err(ElfError::Truncated));
+    assert_eq!(patched(0x68, 8, 4), Err(ElfError::Segment(0)));
+
+    let mut bytes = executable();
+    put(&mut bytes, 0x58, 8, 0);
+    let mut machine = Machine::default();
+    let unmapped = ElfError::Unmapped { addr: 0, size: 16 };
+    assert_eq!(machine.load_image(&bytes).err(), Some(unmapped));
+  }
+
+  #[test]
+  fn malformed_sections() {
+    let mut bytes = executable();
+    put(&mut bytes, 0x28, 8, u64::MAX);
+    let elf = Elf::parse(&bytes).unwrap();
+    assert_eq!(elf.section(".text"), Err(ElfError::Truncated));
+    assert_eq!(elf.symbols(), Err(ElfError::Truncated));
+
+    let mut bytes = executable();
+    put(&mut bytes, SHOFF + 3 * SHDR_SIZE as usize + 0x20, 8, u64::MAX);
+    assert_eq!(Elf::parse(&bytes).unwrap().symbols(), Err(ElfError::Truncated));
+
+    // Stripped of its section headers
+    let mut bytes = executable();
+    put(&mut bytes, 0x3c, 4, 0);
+    let elf = Elf::parse(&bytes).unwrap();
+    assert_eq!(elf.section(".text"), Ok(None));
+    assert_eq!(elf.symbols(), Ok(vec![]));
+  }
+}
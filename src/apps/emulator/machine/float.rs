@@ -0,0 +1,896 @@
+use {
+  super::{Exception, FREGS, Machine, XREGS, csr::status::FS},
+  std::{
+    num::FpCategory,
+    ops::{Add, Div, Mul, Neg, Sub},
+  },
+};
+
+/// Accrued exception flags of `fcsr`.
+pub const NX: u32 = 1 << 0;
+pub const UF: u32 = 1 << 1;
+pub const OF: u32 = 1 << 2;
+pub const DZ: u32 = 1 << 3;
+pub const NV: u32 = 1 << 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rm {
+  /// Round to nearest, ties to even
+  Rne,
+  /// Round towards zero
+  Rtz,
+  /// Round down
+  Rdn,
+  /// Round up
+  Rup,
+  /// Round to nearest, ties to max magnitude
+  Rmm,
+}
+
+impl Rm {
+  pub fn new(bits: u32) -> Option<Self> {
+    Some(match bits {
+      0b000 => Self::Rne,
+      0b001 => Self::Rtz,
+      0b010 => Self::Rdn,
+      0b011 => Self::Rup,
+      0b100 => Self::Rmm,
+      _ => return None,
+    })
+  }
+}
+
+pub trait Float:
+  Copy
+  + PartialOrd
+  + Neg<Output = Self>
+  + Add<Output = Self>
+  + Sub<Output = Self>
+  + Mul<Output = Self>
+  + Div<Output = Self>
+{
+  const BITS: u32;
+  const ZERO: Self;
+  const MAX: Self;
+  const MIN_POSITIVE: Self;
+  /// Register value of the canonical quiet NaN.
+  const NAN: u64;
+
+  /// Reinterpret the low [`Self::BITS`] of `raw`.
+  fn from_raw(raw: u64) -> Self;
+  /// Read from a register, improperly NaN-boxed values become the canonical NaN.
+  fn unbox(reg: u64) -> Self;
+  /// Register value, NaN-boxed when narrower than the register.
+  fn boxed(self) -> u64;
+
+  fn is_nan(self) -> bool;
+  fn is_snan(self) -> bool;
+  fn is_finite(self) -> bool;
+  fn is_sign_negative(self) -> bool;
+  fn classify(self) -> FpCategory;
+  fn abs(self) -> Self;
+  fn sqrt(self) -> Self;
+  fn mul_add(self, a: Self, b: Self) -> Self;
+  fn next_up(self) -> Self;
+  fn next_down(self) -> Self;
+
+  fn to_f64(self) -> f64;
+  fn to_i128(self) -> i128;
+  fn from_i128(val: i128) -> Self;
+}
+
+macro_rules! float {
+  ($ty:ty => $bits:ty, box: $box:expr, quiet: $quiet:expr) => {
+    impl Float for $ty {
+      const BITS: u32 = <$bits>::BITS;
+      const ZERO: Self = 0.0;
+      const MAX: Self = <$ty>::MAX;
+      const MIN_POSITIVE: Self = <$ty>::MIN_POSITIVE;
+      const NAN: u64 = $box | <$ty>::NAN.to_bits() as u64;
+
+      fn from_raw(raw: u64) -> Self {
+        <$ty>::from_bits(raw as $bits)
+      }
+
+      fn unbox(reg: u64) -> Self {
+        if reg & $box == $box { Self::from_raw(reg) } else { <$ty>::NAN }
+      }
+
+      fn boxed(self) -> u64 {
+        $box | self.to_bits() as u64
+      }
+
+      fn is_nan(self) -> bool {
+        <$ty>::is_nan(self)
+      }
+
+      fn is_snan(self) -> bool {
+        self.is_nan() && self.to_bits() & $quiet == 0
+      }
+
+      fn is_finite(self) -> bool {
+        <$ty>::is_finite(self)
+      }
+
+      fn is_sign_negative(self) -> bool {
+        <$ty>::is_sign_negative(self)
+      }
+
+      fn classify(self) -> FpCategory {
+        <$ty>::classify(self)
+      }
+
+      fn abs(self) -> Self {
+        <$ty>::abs(self)
+      }
+
+      fn sqrt(self) -> Self {
+        <$ty>::sqrt(self)
+      }
+
+      fn mul_add(self, a: Self, b: Self) -> Self {
+        <$ty>::mul_add(self, a, b)
+      }
+
+      fn next_up(self) -> Self {
+        <$ty>::next_up(self)
+      }
+
+      fn next_down(self) -> Self {
+        <$ty>::next_down(self)
+      }
+
+      fn to_f64(self) -> f64 {
+        self as f64
+      }
+
+      fn to_i128(self) -> i128 {
+        self as i128
+      }
+
+      fn from_i128(val: i128) -> Self {
+        val as Self
+      }
+    }
+  };
+}
+
+float!(f32 => u32, box: 0xffff_ffff_0000_0000, quiet: 1 << 22);
+float!(f64 => u64, box: 0, quiet: 1 << 51);
+
+/// Adjust the round-to-nearest-even `val` to `rm`.
+///
+/// `err` is the residual `exact - val`: only its sign matters, except for
+/// [`Rm::Rmm`] which needs it exact to detect ties.
+fn round<F: Float>(val: F, err: F, rm: Rm) -> F {
+  if err == F::ZERO || err.is_nan() {
+    return val;
+  }
+  let next = if err > F::ZERO { val.next_up() } else { val.next_down() };
+
+  match rm {
+    Rm::Rne => val,
+    Rm::Rtz if next.abs() < val.abs() => next,
+    Rm::Rdn if err < F::ZERO => next,
+    Rm::Rup if err > F::ZERO => next,
+    Rm::Rmm
+      if next.abs() > val.abs() && (err + err).abs() == (next - val).abs() =>
+    {
+      next
+    }
+    _ => val,
+  }
+}
+
+/// `a + b` rounded to nearest and its exact residual.
+fn two_sum<F: Float>(a: F, b: F) -> (F, F) {
+  let sum = a + b;
+  let bb = sum - a;
+  (sum, (a - (sum - bb)) + (b - bb))
+}
+
+/// `a * b + c` rounded to nearest once, and its residual for [`round`].
+///
+/// The residual is exact barring underflow, following Boldo and Muller's
+/// ErrFma: the exact error is the sum of two floats `r2 + r3`.
+fn fma<F: Float>(a: F, b: F, c: F) -> (F, F) {
+  let r1 = a.mul_add(b, c);
+  let u1 = a * b;
+  let u2 = a.mul_add(b, -u1);
+  let (alpha1, alpha2) = two_sum(c, u2);
+  let (beta1, beta2) = two_sum(u1, alpha1);
+  let gamma = (beta1 - r1) + beta2;
+  let (r2, r3) = two_sum(gamma, alpha2);
+
+  // `r3` breaks what would look like a tie, without changing the sign
+  let err = if r3 == F::ZERO || r3.is_nan() {
+    r2
+  } else if r2 == F::ZERO {
+    r3
+  } else if r3 > F::ZERO {
+    r2.next_up()
+  } else {
+    r2.next_down()
+  };
+  (r1, err)
+}
+
+/// Result of an overflow, which only round-to-nearest makes infinite.
+fn overflow<F: Float>(neg: bool, rm: Rm) -> F {
+  let inf = F::MAX + F::MAX;
+  let (max, inf) = if neg { (-F::MAX, -inf) } else { (F::MAX, inf) };
+  match rm {
+    Rm::Rne | Rm::Rmm => inf,
+    Rm::Rtz => max,
+    Rm::Rdn if neg => inf,
+    Rm::Rup if !neg => inf,
+    _ => max,
+  }
+}
+
+/// Round `exact` to `F`, returning whether it was inexact.
+fn from_int<F: Float>(exact: i128, rm: Rm) -> (F, bool) {
+  let val = F::from_i128(exact);
+  let err = exact - val.to_i128();
+  (round(val, F::from_i128(err), rm), err != 0)
+}
+
+/// Round `val` to an integer in `min..=max`, saturating with `NV` otherwise.
+fn to_int<F: Float>(val: F, rm: Rm, min: i128, max: i128) -> (i128, u32) {
+  let val = val.to_f64();
+  if val.is_nan() {
+    return (max, NV);
+  }
+  let int = match rm {
+    Rm::Rne => val.round_ties_even(),
+    Rm::Rtz => val.trunc(),
+    Rm::Rdn => val.floor(),
+    Rm::Rup => val.ceil(),
+    Rm::Rmm => val.round(),
+  };
+  // `max` itself may round up when converted, its successor never does
+  if int < min as f64 {
+    (min, NV)
+  } else if int >= (max + 1) as f64 {
+    (max, NV)
+  } else {
+    (int as i128, if int != val { NX } else { 0 })
+  }
+}
+
+/// Fields of an F/D instruction, which `raki` does not decode.
+struct Fields {
+  opcode: u32,
+  rd: usize,
+  rs1: usize,
+  rs2: usize,
+  rs3: usize,
+  funct3: u32,
+  funct7: u32,
+}
+
+impl Fields {
+  fn new(raw: u32) -> Self {
+    Self {
+      opcode: raw & 0x7f,
+      rd: (raw >> 7 & 0x1f) as usize,
+      funct3: raw >> 12 & 0b111,
+      rs1: (raw >> 15 & 0x1f) as usize,
+      rs2: (raw >> 20 & 0x1f) as usize,
+      rs3: (raw >> 27) as usize,
+      funct7: raw >> 25,
+    }
+  }
+}
+
+pub const LOAD_FP: u32 = 0b000_0111;
+pub const STORE_FP: u32 = 0b010_0111;
+const MADD: u32 = 0b100_0011;
+const MSUB: u32 = 0b100_0111;
+const NMSUB: u32 = 0b100_1011;
+const NMADD: u32 = 0b100_1111;
+const OP_FP: u32 = 0b101_0011;
+
+pub fn is_float(raw: u32) -> bool {
+  matches!(raw & 0x7f, LOAD_FP | STORE_FP | MADD | MSUB | NMSUB | NMADD | OP_FP)
+}
+
+/// An F/D instruction in assembly, the rounding mode shown unless dynamic.
+pub fn disassemble(raw: u32) -> Option<String> {
+  if !is_float(raw) {
+    return None;
+  }
+  let Fields { opcode, rd, rs1, rs2, rs3, funct3, funct7 } = Fields::new(raw);
+  let (f, x) = (|idx: usize| FREGS[idx], |idx: usize| XREGS[idx]);
+
+  if let LOAD_FP | STORE_FP = opcode {
+    let size = match funct3 {
+      0b010 => 'w',
+      0b011 => 'd',
+      _ => return None,
+    };
+    return Some(if opcode == LOAD_FP {
+      format!("fl{size} {}, {}({})", f(rd), raw as i32 >> 20, x(rs1))
+    } else {
+      let imm = (raw as i32 >> 25 << 5) | (raw as i32 >> 7 & 0x1f);
+      format!("fs{size} {}, {imm}({})", f(rs2), x(rs1))
+    });
+  }
+
+  let (fmt, int) = match funct7 & 0b11 {
+    0b00 => ("s", "w"),
+    0b01 => ("d", "d"),
+    _ => return None,
+  };
+  let rm = match funct3 {
+    0b111 => Some(""),
+    0b000 => Some(", rne"),
+    0b001 => Some(", rtz"),
+    0b010 => Some(", rdn"),
+    0b011 => Some(", rup"),
+    0b100 => Some(", rmm"),
+    _ => None,
+  };
+  let (fd, fs1, fs2) = (f(rd), f(rs1), f(rs2));
+
+  if opcode != OP_FP {
+    let name = match opcode {
+      MADD => "fmadd",
+      MSUB => "fmsub",
+      NMSUB => "fnmsub",
+      _ => "fnmadd",
+    };
+    let fs3 = f(rs3);
+    return Some(format!("{name}.{fmt} {fd}, {fs1}, {fs2}, {fs3}{}", rm?));
+  }
+
+  let funct5 = funct7 >> 2;
+  Some(match (funct5, funct3, rs2) {
+    (0b00000..=0b00011, ..) => {
+      let name = ["fadd", "fsub", "fmul", "fdiv"][funct5 as usize];
+      format!("{name}.{fmt} {fd}, {fs1}, {fs2}{}", rm?)
+    }
+    (0b01011, _, 0) => format!("fsqrt.{fmt} {fd}, {fs1}{}", rm?),
+    (0b00100, 0b000..=0b010, _) => {
+      let name = ["fsgnj", "fsgnjn", "fsgnjx"][funct3 as usize];
+      format!("{name}.{fmt} {fd}, {fs1}, {fs2}")
+    }
+    (0b00101, 0b000..=0b001, _) => {
+      let name = ["fmin", "fmax"][funct3 as usize];
+      format!("{name}.{fmt} {fd}, {fs1}, {fs2}")
+    }
+    (0b01000, _, 1) if fmt == "s" => format!("fcvt.s.d {fd}, {fs1}{}", rm?),
+    // Widening is exact whatever the rounding mode, so it shows none
+    (0b01000, _, 0) if fmt == "d" => format!("fcvt.d.s {fd}, {fs1}"),
+    (0b10100, 0b000..=0b010, _) => {
+      let name = ["fle", "flt", "feq"][funct3 as usize];
+      format!("{name}.{fmt} {}, {fs1}, {fs2}", x(rd))
+    }
+    (0b11000, _, 0..=3) => {
+      let ty = ["w", "wu", "l", "lu"][rs2];
+      format!("fcvt.{ty}.{fmt} {}, {fs1}{}", x(rd), rm?)
+    }
+    (0b11010, _, 0..=3) => {
+      let ty = ["w", "wu", "l", "lu"][rs2];
+      let rm = if fmt == "d" && rs2 < 2 { "" } else { rm? };
+      format!("fcvt.{fmt}.{ty} {fd}, {}{rm}", x(rs1))
+    }
+    (0b11100, 0b000, 0) => format!("fmv.x.{int} {}, {fs1}", x(rd)),
+    (0b11100, 0b001, 0) => format!("fclass.{fmt} {}, {fs1}", x(rd)),
+    (0b11110, 0b000, 0) => format!("fmv.{int}.x {fd}, {}", x(rs1)),
+    _ => return None,
+  })
+}
+
+impl Machine {
+  pub(super) fn exec_float(
+    &mut self,
+    raw: u32,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    let illegal = Exception::IllegalInstruction(raw as u64);
+    let fields = Fields::new(raw);
+
+    // Disabled until `mstatus.FS` is turned on, any use leaves it dirty
+    if self.cpu.csrs.mstatus & FS == 0 {
+      return Err(illegal);
+    }
+    self.cpu.csrs.mstatus |= FS;
+
+    match fields.opcode {
+      LOAD_FP | STORE_FP => {
+        self.float_memory(raw, &fields).ok_or(illegal)??
+      }
+      _ => match fields.funct7 & 0b11 {
+        0b00 => self.float_op::<f32>(&fields).ok_or(illegal)?,
+        0b01 => self.float_op::<f64>(&fields).ok_or(illegal)?,
+        _ => return Err(illegal),
+      },
+    }
+    Ok(self.cpu.pc.wrapping_add(len))
+  }
+
+  fn float_memory(
+    &mut self,
+    raw: u32,
+    &Fields { opcode, rd, rs1, rs2, funct3, .. }: &Fields,
+  ) -> Option<Result<(), Exception>> {
+    let size = match funct3 {
+      0b010 => 4,
+      0b011 => 8,
+      _ => return None,
+    };
+    let base = self.cpu.x(rs1);
+
+    Some(if opcode == LOAD_FP {
+      let addr = base.wrapping_add((raw as i32 >> 20) as u64);
+      self.load(addr, size).map(|val| {
+        self.cpu.fregs[rd] =
+          if size == 4 { f32::from_raw(val).boxed() } else { val };
+      })
+    } else {
+      let imm = (raw as i32 >> 25 << 5) | (raw as i32 >> 7 & 0x1f);
+      let addr = base.wrapping_add(imm as u64);
+      self.store(addr, size, self.cpu.fregs[rs2])
+    })
+  }
+
+  fn rm(&self, funct3: u32) -> Option<Rm> {
+    Rm::new(if funct3 == 0b111 { self.cpu.fcsr >> 5 & 0b111 } else { funct3 })
+  }
+
+  /// Canonicalize NaNs, overflow and rounding of an arithmetic `val`.
+  fn float_result<F: Float>(
+    &mut self,
+    inputs: &[F],
+    val: F,
+    err: F,
+    rm: Rm,
+  ) -> u64 {
+    let mut flags = 0;
+    if inputs.iter().any(|x| x.is_snan()) {
+      flags |= NV;
+    }
+
+    let val = if val.is_nan() {
+      if !inputs.iter().any(|x| x.is_nan()) {
+        flags |= NV;
+      }
+      F::unbox(F::NAN)
+    } else if !val.is_finite() {
+      if inputs.iter().all(|x| x.is_finite()) {
+        flags |= OF | NX;
+        overflow(val.is_sign_negative(), rm)
+      } else {
+        val
+      }
+    } else {
+      let rounded = round(val, err, rm);
+      if err != F::ZERO && !err.is_nan() {
+        flags |= NX;
+        if !rounded.is_finite() {
+          flags |= OF;
+        } else if rounded.abs() < F::MIN_POSITIVE {
+          flags |= UF;
+        }
+      }
+      rounded
+    };
+
+    self.cpu.fcsr |= flags;
+    val.boxed()
+  }
+
+  fn float_op<F: Float>(&mut self, fields: &Fields) -> Option<()> {
+    let &Fields { opcode, rd, rs1, rs2, rs3, funct3, funct7 } = fields;
+
+    let (a, b, c) = (
+      F::unbox(self.cpu.fregs[rs1]),
+      F::unbox(self.cpu.fregs[rs2]),
+      F::unbox(self.cpu.fregs[rs3]),
+    );
+
+    if opcode != OP_FP {
+      let rm = self.rm(funct3)?;
+      let (a, c) = match opcode {
+        MADD => (a, c),
+        MSUB => (a, -c),
+        NMSUB => (-a, c),
+        _ => (-a, -c),
+      };
+      let (val, err) = fma(a, b, c);
+      self.cpu.fregs[rd] = self.float_result(&[a, b, c], val, err, rm);
+      return Some(());
+    }
+
+    let val = match funct7 >> 2 {
+      0b00000 => {
+        let rm = self.rm(funct3)?;
+        let (sum, err) = two_sum(a, b);
+        self.float_result(&[a, b], sum, err, rm)
+      }
+      0b00001 => {
+        let (rm, b) = (self.rm(funct3)?, -b);
+        let (sum, err) = two_sum(a, b);
+        self.float_result(&[a, b], sum, err, rm)
+      }
+      0b00010 => {
+        let rm = self.rm(funct3)?;
+        let prod = a * b;
+        self.float_result(&[a, b], prod, a.mul_add(b, -prod), rm)
+      }
+      0b00011 => {
+        let rm = self.rm(funct3)?;
+        let quot = a / b;
+        if b == F::ZERO && a.is_finite() && a != F::ZERO {
+          self.cpu.fcsr |= DZ;
+          quot.boxed()
+        } else {
+          let rem = (-quot).mul_add(b, a);
+          self.float_result(&[a, b], quot, rem / b, rm)
+        }
+      }
+      0b01011 if rs2 == 0 => {
+        let rm = self.rm(funct3)?;
+        let root = a.sqrt();
+        let rem = (-root).mul_add(root, a);
+        self.float_result(&[a], root, rem / (root + root), rm)
+      }
+      0b00100 => {
+        let sign = match funct3 {
+          0b000 => b.is_sign_negative(),
+          0b001 => !b.is_sign_negative(),
+          0b010 => a.is_sign_negative() != b.is_sign_negative(),
+          _ => return None,
+        };
+        let abs = a.abs();
+        (if sign { -abs } else { abs }).boxed()
+      }
+      0b00101 => {
+        let max = match funct3 {
+          0b000 => false,
+          0b001 => true,
+          _ => return None,
+        };
+        if a.is_snan() || b.is_snan() {
+          self.cpu.fcsr |= NV;
+        }
+        let val = match (a.is_nan(), b.is_nan()) {
+          (true, true) => F::unbox(F::NAN),
+          (true, false) => b,
+          (false, true) => a,
+          // -0.0 orders before +0.0
+          _ if a == b => {
+            if a.is_sign_negative() != max {
+              a
+            } else {
+              b
+            }
+          }
+          _ if (a < b) != max => a,
+          _ => b,
+        };
+        val.boxed()
+      }
+      0b01000 => return self.float_convert(fields),
+      0b10100 => {
+        let res = match funct3 {
+          0b010 => {
+            if a.is_snan() || b.is_snan() {
+              self.cpu.fcsr |= NV;
+            }
+            a == b
+          }
+          0b001 | 0b000 => {
+            if a.is_nan() || b.is_nan() {
+              self.cpu.fcsr |= NV;
+            }
+            if funct3 == 0b001 { a < b } else { a <= b }
+          }
+          _ => return None,
+        };
+        self.cpu.set_x(rd, res as u64);
+        return Some(());
+      }
+      0b11000 => {
+        let rm = self.rm(funct3)?;
+        let (val, flags) = match rs2 {
+          0b00 => to_int(a, rm, i32::MIN as i128, i32::MAX as i128),
+          0b01 => to_int(a, rm, 0, u32::MAX as i128),
+          0b10 => to_int(a, rm, i64::MIN as i128, i64::MAX as i128),
+          0b11 => to_int(a, rm, 0, u64::MAX as i128),
+          _ => return None,
+        };
+        self.cpu.fcsr |= flags;
+        // Word results are sign-extended, even unsigned ones
+        let val = if rs2 < 0b10 { val as i32 as u64 } else { val as u64 };
+        self.cpu.set_x(rd, val);
+        return Some(());
+      }
+      0b11010 => {
+        let rm = self.rm(funct3)?;
+        let x = self.cpu.x(rs1);
+        let exact = match rs2 {
+          0b00 => x as i32 as i128,
+          0b01 => x as u32 as i128,
+          0b10 => x as i64 as i128,
+          0b11 => x as i128,
+          _ => return None,
+        };
+        let (val, inexact) = from_int::<F>(exact, rm);
+        if inexact {
+          self.cpu.fcsr |= NX;
+        }
+        val.boxed()
+      }
+      0b11100 if rs2 == 0 => {
+        let shift = 64 - F::BITS;
+        let val = match funct3 {
+          // Raw bits, sign-extended for singles
+          0b000 => ((self.cpu.fregs[rs1] << shift) as i64 >> shift) as u64,
+          0b001 => classify(a),
+          _ => return None,
+        };
+        self.cpu.set_x(rd, val);
+        return Some(());
+      }
+      0b11110 if rs2 == 0 && funct3 == 0b000 => {
+        F::from_raw(self.cpu.x(rs1)).boxed()
+      }
+      _ => return None,
+    };
+
+    self.cpu.fregs[rd] = val;
+    Some(())
+  }
+
+  /// `fcvt.s.d` and `fcvt.d.s`.
+  fn float_convert(
+    &mut self,
+    &Fields { rd, rs1, rs2, funct3, funct7, .. }: &Fields,
+  ) -> Option<()> {
+    let rm = self.rm(funct3)?;
+    let reg = self.cpu.fregs[rs1];
+
+    self.cpu.fregs[rd] = match (funct7 & 0b11, rs2) {
+      (0b00, 0b01) => {
+        let wide = f64::unbox(reg);
+        if wide.is_snan() {
+          self.cpu.fcsr |= NV;
+        }
+        if wide.is_nan() {
+          f32::NAN.boxed()
+        } else if wide.is_infinite() {
+          (wide as f32).boxed()
+        } else {
+          let val = wide as f32;
+          // Exact, but may fall below the single range: keep its sign then
+          let err = wide - val as f64;
+          let err = match err as f32 {
+            0.0 if err != 0.0 => f32::from_bits(1).copysign(err as f32),
+            err => err,
+          };
+          self.float_result(&[], val, err, rm)
+        }
+      }
+      (0b01, 0b00) => {
+        let narrow = f32::unbox(reg);
+        if narrow.is_snan() {
+          self.cpu.fcsr |= NV;
+        }
+        if narrow.is_nan() { f64::NAN } else { narrow as f64 }.boxed()
+      }
+      _ => return None,
+    };
+    Some(())
+  }
+}
+
+/// `fclass` mask: one bit per category, from negative infinity up to quiet NaN.
+fn classify<F: Float>(val: F) -> u64 {
+  let neg = val.is_sign_negative();
+  let bit = match val.classify() {
+    FpCategory::Nan if val.is_snan() => 8,
+    FpCategory::Nan => 9,
+    FpCategory::Infinite => {
+      if neg {
+        0
+      } else {
+        7
+      }
+    }
+    FpCategory::Normal => {
+      if neg {
+        1
+      } else {
+        6
+      }
+    }
+    FpCategory::Subnormal => {
+      if neg {
+        2
+      } else {
+        5
+      }
+    }
+    FpCategory::Zero => {
+      if neg {
+        3
+      } else {
+        4
+      }
+    }
+  };
+  1 << bit
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::repr::session::CpuRepr};
+
+  const RNE: u32 = 0b000;
+  const RTZ: u32 = 0b001;
+  const RDN: u32 = 0b010;
+  const RUP: u32 = 0b011;
+  const RMM: u32 = 0b100;
+  const S: u32 = 0b00;
+  const D: u32 = 0b01;
+
+  fn machine(regs: &[u64]) -> Machine {
+    let mut machine = Machine::default();
+    machine.cpu.csrs.mstatus |= FS;
+    machine.cpu.fregs[1..=regs.len()].copy_from_slice(regs);
+    machine
+  }
+
+  /// `fmadd f4, f1, f2, f3` and the flags it raised.
+  fn fmadd(fmt: u32, rm: u32, regs: [u64; 3]) -> (u64, u32) {
+    let raw = 3 << 27 | fmt << 25 | 2 << 20 | 1 << 15 | rm << 12 | 4 << 7;
+    let mut machine = machine(&regs);
+    machine.exec_float(raw | MADD, 4).unwrap();
+    (machine.cpu.fregs[4], machine.cpu.fcsr)
+  }
+
+  /// `op f3, f1, f2` or into `x3`, `rs2` as given.
+  fn op(funct5: u32, fmt: u32, rs2: u32, rm: u32, regs: &[u64]) -> Machine {
+    let raw = (funct5 << 2 | fmt) << 25 | rs2 << 20 | 1 << 15 | rm << 12;
+    let mut machine = machine(regs);
+    machine.exec_float(raw | 3 << 7 | OP_FP, 4).unwrap();
+    machine
+  }
+
+  fn d(val: f64) -> u64 {
+    val.to_bits()
+  }
+
+  #[test]
+  fn fma_double_rounds_by_mode() {
+    let eps = f64::EPSILON;
+    // 1 - 2^-104, just below one
+    let regs = [d(1.0 + eps), d(1.0 - eps), d(0.0)];
+    assert_eq!(fmadd(D, RNE, regs), (d(1.0), NX));
+    assert_eq!(fmadd(D, RUP, regs), (d(1.0), NX));
+    assert_eq!(fmadd(D, RDN, regs), (d(1.0f64.next_down()), NX));
+    assert_eq!(fmadd(D, RTZ, regs), (d(1.0f64.next_down()), NX));
+  }
+
+  #[test]
+  fn fma_double_ties() {
+    // 1 + 2^-53, halfway between one and its successor
+    let regs = [d(f64::EPSILON / 2.0), d(1.0), d(1.0)];
+    assert_eq!(fmadd(D, RNE, regs), (d(1.0), NX));
+    assert_eq!(fmadd(D, RMM, regs), (d(1.0f64.next_up()), NX));
+  }
+
+  #[test]
+  fn fma_single_rounds_once() {
+    // 1 + 2^-23 + 2^-24 - 2^-60, which rounds to a tie in double first
+    let a = 1.0 + 2f32.powi(-18);
+    let b = 2f32.powi(-24) - 2f32.powi(-42);
+    let c = 1.0 + f32::EPSILON;
+    let regs = [a.boxed(), b.boxed(), c.boxed()];
+    assert_eq!(fmadd(S, RNE, regs), (c.boxed(), NX));
+  }
+
+  #[test]
+  fn fma_exact() {
+    let regs = [d(2.0), d(3.0), d(1.0)];
+    assert_eq!(fmadd(D, RDN, regs), (d(7.0), 0));
+  }
+
+  #[test]
+  fn divide_by_zero() {
+    let machine = op(0b00011, D, 2, RNE, &[d(1.0), d(0.0)]);
+    assert_eq!(machine.cpu.fregs[3], d(f64::INFINITY));
+    assert_eq!(machine.cpu.fcsr, DZ);
+  }
+
+  #[test]
+  fn sqrt_of_negative_is_canonical_nan() {
+    let machine = op(0b01011, D, 0, RNE, &[d(-1.0)]);
+    assert_eq!(machine.cpu.fregs[3], <f64 as Float>::NAN);
+    assert_eq!(machine.cpu.fcsr, NV);
+  }
+
+  #[test]
+  fn add_rounds_by_mode() {
+    // 1 + 2^-60 is inexact in either direction
+    let regs = [d(1.0), d(2f64.powi(-60))];
+    assert_eq!(op(0, D, 2, RUP, &regs).cpu.fregs[3], d(1.0f64.next_up()));
+    assert_eq!(op(0, D, 2, RDN, &regs).cpu.fregs[3], d(1.0));
+    assert_eq!(op(0, D, 2, RDN, &regs).cpu.fcsr, NX);
+  }
+
+  #[test]
+  fn convert_saturates() {
+    let machine = op(0b11000, D, 0, RTZ, &[d(1e20)]);
+    assert_eq!(machine.cpu.x(3), i32::MAX as u64);
+    assert_eq!(machine.cpu.fcsr, NV);
+    let machine = op(0b11000, D, 0, RTZ, &[d(-2.5)]);
+    assert_eq!(machine.cpu.x(3), -2i64 as u64);
+    assert_eq!(machine.cpu.fcsr, NX);
+  }
+
+  #[test]
+  fn singles_are_nan_boxed() {
+    // A double in a single register reads as the canonical NaN
+    let machine = op(0b00000, S, 2, RNE, &[d(1.0), 1f32.boxed()]);
+    assert_eq!(machine.cpu.fregs[3], <f32 as Float>::NAN);
+    let machine = op(0b00000, S, 2, RNE, &[1f32.boxed(), 2f32.boxed()]);
+    assert_eq!(machine.cpu.fregs[3], 3f32.boxed());
+  }
+
+  #[test]
+  fn disassembles() {
+    let cases = [
+      (0x00452507, "flw fa0, 4(a0)"),
+      (0xfeb52e27, "fsw fa1, -4(a0)"),
+      (0x68c59547, "fmsub.s fa0, fa1, fa2, fa3, rtz"),
+      (0x08c58553, "fsub.s fa0, fa1, fa2, rne"),
+      (0xc0159553, "fcvt.wu.s a0, fa1, rtz"),
+      (0x6ac5c54b, "fnmsub.d fa0, fa1, fa2, fa3, rmm"),
+      (0x4015f553, "fcvt.s.d fa0, fa1"),
+      (0x42058553, "fcvt.d.s fa0, fa1"),
+      (0xc225a553, "fcvt.l.d a0, fa1, rdn"),
+      (0xd2058553, "fcvt.d.w fa0, a1"),
+      (0xf2058553, "fmv.d.x fa0, a1"),
+      (0xa2c5a553, "feq.d a0, fa1, fa2"),
+    ];
+    for (raw, text) in cases {
+      assert_eq!(disassemble(raw).as_deref(), Some(text), "{raw:#010x}");
+    }
+    assert_eq!(
+      Machine::disassemble(0x2508, 2).as_deref(),
+      Some("c.fld fa0, 8(a0)")
+    );
+    assert_eq!(
+      Machine::disassemble(0x2462, 2).as_deref(),
+      Some("c.fld fs0, 24(sp)")
+    );
+  }
+
+  #[test]
+  fn rejects_reserved_encodings() {
+    // Quad precision, a reserved rounding mode and a non-float opcode
+    assert_eq!(disassemble(0x06c58553), None);
+    assert_eq!(disassemble(0x08c5d553), None);
+    assert_eq!(disassemble(0x00b50533), None);
+  }
+
+  #[test]
+  fn sessions_keep_every_bit() {
+    let boxed = 0xffff_ffff_3f80_0000;
+    let regs = [boxed, f64::INFINITY.to_bits(), 1.5_f64.to_bits()];
+    let repr = machine(&regs).repr();
+    // JSON numbers cannot hold the first two, older readers see zeros
+    assert_eq!(repr.fregs[1..4], [0.0, 0.0, 1.5]);
+    let json = json::to_string(&repr).unwrap();
+    let machine = Machine::new(json::from_str(&json).unwrap());
+    assert_eq!(machine.cpu.fregs[1..4], regs);
+
+    // Sessions saved before `fbits` existed
+    let legacy = CpuRepr { fbits: vec![], ..machine.repr() };
+    let machine = Machine::new(legacy);
+    assert_eq!(machine.cpu.fregs[1..4], [0, 0, 1.5_f64.to_bits()]);
+  }
+}
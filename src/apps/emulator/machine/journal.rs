@@ -0,0 +1,29 @@
+use super::Machine;
+
+/// Memory contents a store overwrote.
+#[derive(Debug, Copy, Clone)]
+pub struct Store {
+  pub addr: u64,
+  pub size: usize,
+  pub old: u64,
+}
+
+/// Stores of the current step, recorded while `enabled` so that whoever
+/// keeps a history can undo them.
+#[derive(Debug, Default, Clone)]
+pub struct Journal {
+  pub enabled: bool,
+  pub stores: Vec<Store>,
+}
+
+impl Machine {
+  /// Remember the memory a store of `size` bytes at `paddr` overwrites.
+  pub(super) fn journal(&mut self, paddr: u64, size: usize) {
+    if self.journal.enabled {
+      // Devices keep their state in registers, not in memory
+      if let Some(old) = self.bus.peek(paddr, size) {
+        self.journal.stores.push(Store { addr: paddr, size, old });
+      }
+    }
+  }
+}
@@ -0,0 +1,436 @@
+use {
+  super::{Exception, Machine, Priv, csr::status},
+  std::collections::HashMap,
+};
+
+pub const PAGE: u64 = 4096;
+
+/// Page table entry bits.
+pub mod pte {
+  pub const V: u64 = 1 << 0;
+  pub const R: u64 = 1 << 1;
+  pub const W: u64 = 1 << 2;
+  pub const X: u64 = 1 << 3;
+  pub const U: u64 = 1 << 4;
+  pub const G: u64 = 1 << 5;
+  pub const A: u64 = 1 << 6;
+  pub const D: u64 = 1 << 7;
+}
+
+/// Upper bits reserved for extensions this machine does not implement.
+const PTE_RESERVED: u64 = 0x3ff << 54;
+const PPN_MASK: u64 = (1 << 44) - 1;
+
+/// Entries kept before the whole TLB is dropped.
+const TLB_CAPACITY: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+  Fetch,
+  Load,
+  Store,
+}
+
+impl Access {
+  fn page_fault(self, addr: u64) -> Exception {
+    match self {
+      Access::Fetch => Exception::InstructionPageFault(addr),
+      Access::Load => Exception::LoadPageFault(addr),
+      Access::Store => Exception::StorePageFault(addr),
+    }
+  }
+
+  fn access_fault(self, addr: u64) -> Exception {
+    match self {
+      Access::Fetch => Exception::InstructionAccessFault(addr),
+      Access::Load => Exception::LoadAccessFault(addr),
+      Access::Store => Exception::StoreAccessFault(addr),
+    }
+  }
+}
+
+/// Translation mode selected by `satp`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+  Bare,
+  Sv39,
+  Sv48,
+}
+
+impl Mode {
+  pub fn new(satp: u64) -> Option<Self> {
+    Some(match satp >> 60 {
+      0 => Self::Bare,
+      8 => Self::Sv39,
+      9 => Self::Sv48,
+      _ => return None,
+    })
+  }
+
+  pub fn levels(self) -> usize {
+    match self {
+      Mode::Bare => 0,
+      Mode::Sv39 => 3,
+      Mode::Sv48 => 4,
+    }
+  }
+}
+
+/// One page table entry read during a walk.
+#[derive(Debug, Copy, Clone)]
+pub struct Level {
+  pub level: usize,
+  pub addr: u64,
+  pub pte: u64,
+}
+
+/// Leaf of a successful walk.
+#[derive(Debug, Copy, Clone)]
+pub struct Leaf {
+  pub level: usize,
+  pub addr: u64,
+  pub pte: u64,
+}
+
+impl Leaf {
+  /// Physical address of `vaddr` within this (super)page.
+  pub fn paddr(&self, vaddr: u64) -> u64 {
+    let shift = 12 + 9 * self.level;
+    let ppn = (self.pte >> 10 & PPN_MASK) << 12;
+    ppn >> shift << shift | vaddr & ((1 << shift) - 1)
+  }
+}
+
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WalkError {
+  #[error("translation is disabled")]
+  Bare,
+  #[error("address is not sign-extended from its top translated bit")]
+  NonCanonical,
+  #[error("page table entry at {0:#x} is outside physical memory")]
+  AccessFault(u64),
+  #[error("invalid entry at level {0}")]
+  Invalid(usize),
+  #[error("reserved encoding at level {0}")]
+  Reserved(usize),
+  #[error("superpage at level {0} is not aligned to its size")]
+  Misaligned(usize),
+  #[error("no leaf entry at the last level")]
+  TooDeep,
+}
+
+/// Every step of a page walk, as the debugger shows it.
+#[derive(Debug, Clone)]
+pub struct Walk {
+  pub mode: Mode,
+  /// Physical address of the root page table.
+  pub root: u64,
+  pub levels: Vec<Level>,
+  pub leaf: Result<Leaf, WalkError>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Tlb {
+  /// Leaves by virtual page number, superpages are cached per 4K page.
+  entries: HashMap<u64, Leaf>,
+}
+
+impl Tlb {
+  pub fn flush(&mut self) {
+    self.entries.clear();
+  }
+
+  /// Bytes the cached entries take on the heap.
+  pub fn heap_size(&self) -> usize {
+    self.entries.capacity() * size_of::<(u64, Leaf)>()
+  }
+}
+
+impl Machine {
+  /// Walk the page tables for `vaddr` without touching any state.
+  pub fn walk(&self, vaddr: u64) -> Walk {
+    let satp = self.cpu.csrs.satp;
+    let mode = Mode::new(satp).unwrap_or(Mode::Bare);
+    let root = (satp & PPN_MASK) * PAGE;
+    let mut walk =
+      Walk { mode, root, levels: vec![], leaf: Err(WalkError::TooDeep) };
+
+    let bits = 12 + 9 * mode.levels() as u32;
+    let sext = ((vaddr << (64 - bits)) as i64 >> (64 - bits)) as u64;
+    if mode == Mode::Bare {
+      walk.leaf = Err(WalkError::Bare);
+      return walk;
+    } else if sext != vaddr {
+      walk.leaf = Err(WalkError::NonCanonical);
+      return walk;
+    }
+
+    let mut table = root;
+    for level in (0..mode.levels()).rev() {
+      let vpn = vaddr >> (12 + 9 * level) & 0x1ff;
+      let addr = table + vpn * 8;
+      let Some(pte) = self.bus.peek(addr, 8) else {
+        walk.leaf = Err(WalkError::AccessFault(addr));
+        return walk;
+      };
+      walk.levels.push(Level { level, addr, pte });
+
+      if pte & pte::V == 0 {
+        walk.leaf = Err(WalkError::Invalid(level));
+        return walk;
+      }
+      if pte & PTE_RESERVED != 0 || pte & (pte::R | pte::W) == pte::W {
+        walk.leaf = Err(WalkError::Reserved(level));
+        return walk;
+      }
+      if pte & (pte::R | pte::X) != 0 {
+        let ppn = pte >> 10 & PPN_MASK;
+        walk.leaf = if ppn & ((1 << (9 * level)) - 1) != 0 {
+          Err(WalkError::Misaligned(level))
+        } else {
+          Ok(Leaf { level, addr, pte })
+        };
+        return walk;
+      }
+      table = (pte >> 10 & PPN_MASK) * PAGE;
+    }
+    walk
+  }
+
+  /// Physical address a debugger means by `vaddr`, through the page tables
+  /// outside of M-mode.
+  pub fn debug_paddr(&self, vaddr: u64) -> Option<u64> {
+    if self.cpu.mode == Priv::Machine {
+      return Some(vaddr);
+    }
+    let walk = self.walk(vaddr);
+    match walk.leaf {
+      Ok(leaf) => Some(leaf.paddr(vaddr)),
+      Err(WalkError::Bare) => Some(vaddr),
+      Err(_) => None,
+    }
+  }
+
+  /// Translate `vaddr` for `access`, updating the A/D bits and the TLB.
+  pub(super) fn translate(
+    &mut self,
+    vaddr: u64,
+    access: Access,
+  ) -> Result<u64, Exception> {
+    let status = self.cpu.csrs.mstatus;
+    let mode = match access {
+      // Loads and stores may run with the privilege of the trapped mode
+      Access::Load | Access::Store
+        if self.cpu.mode == Priv::Machine && status & status::MPRV != 0 =>
+      {
+        Priv::new(status >> 11 & 0b11).unwrap_or_default()
+      }
+      _ => self.cpu.mode,
+    };
+    let bare = Mode::new(self.cpu.csrs.satp) == Some(Mode::Bare);
+    if mode == Priv::Machine || bare {
+      return Ok(vaddr);
+    }
+
+    let fault = access.page_fault(vaddr);
+    let dirty = |pte: u64| access != Access::Store || pte & pte::D != 0;
+
+    let vpn = vaddr / PAGE;
+    let leaf = match self.tlb.entries.get(&vpn) {
+      Some(leaf) if leaf.pte & pte::A != 0 && dirty(leaf.pte) => *leaf,
+      _ => {
+        let mut leaf = match self.walk(vaddr).leaf {
+          Ok(leaf) => leaf,
+          // `tval` holds the address accessed, not the one of the PTE
+          Err(WalkError::AccessFault(_)) => {
+            return Err(access.access_fault(vaddr));
+          }
+          Err(_) => return Err(fault),
+        };
+        if !self.permits(leaf.pte, mode, access) {
+          return Err(fault);
+        }
+
+        let updated =
+          leaf.pte | pte::A | if access == Access::Store { pte::D } else { 0 };
+        if updated != leaf.pte {
+          self.journal(leaf.addr, 8);
+          self
+            .bus
+            .store(leaf.addr, 8, updated)
+            .ok_or(access.access_fault(vaddr))?;
+          leaf.pte = updated;
+        }
+        if self.tlb.entries.len() >= TLB_CAPACITY {
+          self.tlb.flush();
+        }
+        self.tlb.entries.insert(vpn, leaf);
+        leaf
+      }
+    };
+
+    // Cached entries are checked again: `mstatus` may have changed since
+    if !self.permits(leaf.pte, mode, access) {
+      return Err(fault);
+    }
+    Ok(leaf.paddr(vaddr))
+  }
+
+  fn permits(&self, pte: u64, mode: Priv, access: Access) -> bool {
+    let status = self.cpu.csrs.mstatus;
+    let user = pte & pte::U != 0;
+
+    let privilege = match mode {
+      Priv::User => user,
+      // Supervisor code never runs from user pages
+      _ if user => access != Access::Fetch && status & status::SUM != 0,
+      _ => true,
+    };
+    let kind = match access {
+      Access::Fetch => pte & pte::X != 0,
+      Access::Load => {
+        pte & pte::R != 0 || status & status::MXR != 0 && pte & pte::X != 0
+      }
+      Access::Store => pte & pte::W != 0,
+    };
+    privilege && kind
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{super::csr::SATP, *};
+
+  const ROOT: u64 = Machine::RAM + 0x1000;
+  const PAGE_RW: u64 = Machine::RAM + 0x5000;
+  const PAGE_R: u64 = Machine::RAM + 0x6000;
+  const USER: u64 = Machine::RAM + 0x7000;
+
+  fn next(table: u64) -> u64 {
+    (table / PAGE) << 10 | pte::V
+  }
+
+  fn leaf(paddr: u64, flags: u64) -> u64 {
+    (paddr / PAGE) << 10 | flags | pte::V
+  }
+
+  /// Supervisor mode under Sv39 with:
+  /// - `0x4000_0000` read-write, `0x4000_1000` read-only and `0x4000_2000`
+  ///   a user page, through three levels
+  /// - a gigapage at `0x8000_0000` mapped onto the memory
+  /// - a misaligned gigapage at `0xc000_0000`
+  fn sv39() -> Machine {
+    let mut machine = Machine::with_code(&[]);
+    let (l1, l0) = (ROOT + PAGE, ROOT + 2 * PAGE);
+    let ptes = [
+      (ROOT + 8, next(l1)),
+      (ROOT + 16, leaf(Machine::RAM, pte::R | pte::W | pte::X)),
+      (ROOT + 24, leaf(Machine::RAM + PAGE, pte::R)),
+      (l1, next(l0)),
+      (l0, leaf(PAGE_RW, pte::R | pte::W)),
+      (l0 + 8, leaf(PAGE_R, pte::R)),
+      (l0 + 16, leaf(USER, pte::R | pte::U)),
+    ];
+    for (addr, pte) in ptes {
+      machine.bus.store(addr, 8, pte).unwrap();
+    }
+    machine.csr_write(SATP, 8 << 60 | (ROOT / PAGE)).unwrap();
+    machine.cpu.mode = Priv::Supervisor;
+    machine
+  }
+
+  #[test]
+  fn walks_the_tables() {
+    let machine = sv39();
+    let walk = machine.walk(0x4000_0123);
+    assert_eq!((walk.mode, walk.root), (Mode::Sv39, ROOT));
+    assert_eq!(walk.levels.len(), 3);
+    let leaf = walk.leaf.unwrap();
+    assert_eq!((leaf.level, leaf.paddr(0x4000_0123)), (0, PAGE_RW + 0x123));
+
+    let leaf = machine.walk(0x8001_2345).leaf.unwrap();
+    let paddr = Machine::RAM + 0x1_2345;
+    assert_eq!((leaf.level, leaf.paddr(0x8001_2345)), (2, paddr));
+
+    assert_eq!(
+      machine.walk(0xc000_0000).leaf.unwrap_err(),
+      WalkError::Misaligned(2)
+    );
+    assert_eq!(
+      machine.walk(0x4000_3000).leaf.unwrap_err(),
+      WalkError::Invalid(0)
+    );
+    assert_eq!(
+      machine.walk(1 << 40).leaf.unwrap_err(),
+      WalkError::NonCanonical
+    );
+    assert_eq!(machine.debug_paddr(0x4000_1008), Some(PAGE_R + 8));
+  }
+
+  #[test]
+  fn sets_accessed_and_dirty() {
+    let mut machine = sv39();
+    let pte = |machine: &mut Machine| machine.bus.load(ROOT + 2 * PAGE, 8);
+
+    let paddr = machine.translate(0x4000_0010, Access::Load).unwrap();
+    assert_eq!(paddr, PAGE_RW + 0x10);
+    assert_eq!(pte(&mut machine).unwrap() & (pte::A | pte::D), pte::A);
+
+    machine.translate(0x4000_0010, Access::Store).unwrap();
+    let flags = pte(&mut machine).unwrap() & (pte::A | pte::D);
+    assert_eq!(flags, pte::A | pte::D);
+  }
+
+  #[test]
+  fn faults() {
+    let mut machine = sv39();
+    let vaddr = 0x4000_3000;
+    assert_eq!(
+      machine.translate(vaddr, Access::Load),
+      Err(Exception::LoadPageFault(vaddr))
+    );
+    assert_eq!(
+      machine.translate(0x4000_1000, Access::Store),
+      Err(Exception::StorePageFault(0x4000_1000))
+    );
+    assert_eq!(
+      machine.translate(0x4000_0000, Access::Fetch),
+      Err(Exception::InstructionPageFault(0x4000_0000))
+    );
+
+    // User pages need `SUM` from supervisor mode, and never run
+    assert!(machine.translate(0x4000_2000, Access::Load).is_err());
+    machine.cpu.csrs.mstatus |= status::SUM;
+    assert_eq!(machine.translate(0x4000_2000, Access::Load), Ok(USER));
+    assert!(machine.translate(0x4000_2000, Access::Fetch).is_err());
+    machine.cpu.mode = Priv::User;
+    assert_eq!(
+      machine.translate(0x4000_2000, Access::Fetch),
+      Err(Exception::InstructionPageFault(0x4000_2000))
+    );
+  }
+
+  #[test]
+  fn access_fault_reports_vaddr() {
+    let mut machine = sv39();
+    // The root table lies outside of physical memory
+    machine.csr_write(SATP, 8 << 60 | 0x100_0000).unwrap();
+    assert_eq!(
+      machine.translate(0x4000_0008, Access::Store),
+      Err(Exception::StoreAccessFault(0x4000_0008))
+    );
+  }
+
+  #[test]
+  fn satp_flushes_the_tlb() {
+    let mut machine = sv39();
+    machine.translate(0x4000_0000, Access::Load).unwrap();
+    // Remapped behind the cached translation
+    let pte = leaf(PAGE_R, pte::R | pte::A);
+    machine.bus.store(ROOT + 2 * PAGE, 8, pte).unwrap();
+    assert_eq!(machine.translate(0x4000_0000, Access::Load), Ok(PAGE_RW));
+
+    machine.csr_write(SATP, machine.cpu.csrs.satp).unwrap();
+    assert_eq!(machine.translate(0x4000_0000, Access::Load), Ok(PAGE_R));
+  }
+}
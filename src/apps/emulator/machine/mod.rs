@@ -0,0 +1,370 @@
+use {
+  crate::repr::session::{CpuRepr, CsrRepr},
+  mmu::Access,
+  raki::{
+    BaseIOpcode, COpcode, Decode, DecodingError, Instruction, Isa, OpcodeKind,
+    ZicsrOpcode,
+  },
+};
+
+mod bus;
+mod clint;
+mod csr;
+mod dwarf;
+mod elf;
+mod float;
+mod journal;
+mod mmu;
+mod plic;
+mod rv64a;
+mod rv64i;
+mod rv64m;
+mod rvc;
+mod trace;
+mod trap;
+mod uart;
+mod watch;
+
+pub use {
+  bus::{Bus, MapError, validate, virt},
+  clint::Clint,
+  csr::{CSRS, Csrs, FCSR, FFLAGS, FRM},
+  elf::ElfError,
+  journal::{Journal, Store},
+  mmu::{Mode, PAGE, Tlb, pte},
+  plic::{Plic, SOURCES, UART_IRQ},
+  trace::{Accesses, MemAccess},
+  trap::{INTERRUPTS, Priv},
+  uart::Uart,
+  watch::{WatchHit, Watchpoints},
+};
+
+/// ABI names of the integer registers.
+pub const XREGS: [&str; 32] = [
+  "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1",
+  "a2", "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8",
+  "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// ABI names of the float registers.
+pub const FREGS: [&str; 32] = [
+  "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0",
+  "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5",
+  "fs6", "fs7", "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// Required alignment of every jump and branch target, relaxed by RVC.
+const IALIGN: u64 = 2;
+
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exception {
+  #[error("instruction address misaligned: {0:#x}")]
+  InstructionAddressMisaligned(u64),
+  #[error("instruction access fault: {0:#x}")]
+  InstructionAccessFault(u64),
+  #[error("illegal instruction: {0:#010x}")]
+  IllegalInstruction(u64),
+  #[error("breakpoint at {0:#x}")]
+  Breakpoint(u64),
+  #[error("load address misaligned: {0:#x}")]
+  LoadAddressMisaligned(u64),
+  #[error("load access fault: {0:#x}")]
+  LoadAccessFault(u64),
+  #[error("store address misaligned: {0:#x}")]
+  StoreAddressMisaligned(u64),
+  #[error("store access fault: {0:#x}")]
+  StoreAccessFault(u64),
+  #[error("environment call")]
+  EnvironmentCall,
+  #[error("instruction page fault: {0:#x}")]
+  InstructionPageFault(u64),
+  #[error("load page fault: {0:#x}")]
+  LoadPageFault(u64),
+  #[error("store page fault: {0:#x}")]
+  StorePageFault(u64),
+}
+
+#[derive(Default, Clone, PartialEq)]
+pub struct Cpu {
+  pub pc: u64,
+  pub xregs: [u64; 32],
+  /// Raw bits, singles are NaN-boxed.
+  pub fregs: [u64; 32],
+  pub fcsr: u32,
+  pub mode: Priv,
+  pub csrs: Csrs,
+  /// Address reserved by the last `lr`, consumed by `sc`.
+  pub reservation: Option<u64>,
+}
+
+impl Cpu {
+  pub fn x(&self, idx: usize) -> u64 {
+    self.xregs[idx]
+  }
+
+  pub fn set_x(&mut self, idx: usize, val: u64) {
+    // x0 is hardwired to zero
+    if idx != 0 {
+      self.xregs[idx] = val;
+    }
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct Machine {
+  pub cpu: Cpu,
+  pub bus: Bus,
+  pub tlb: Tlb,
+  pub watch: Watchpoints,
+  pub journal: Journal,
+  pub accesses: Accesses,
+}
+
+impl Machine {
+  pub fn new(CpuRepr { pc, xregs, fregs, fbits, bus, csr }: CpuRepr) -> Self {
+    let mode = Priv::new(csr.mode as u64).unwrap_or_default();
+    let mut cpu = Cpu { pc, mode, ..Cpu::default() };
+    for (reg, val) in cpu.xregs.iter_mut().zip(xregs).skip(1) {
+      *reg = val;
+    }
+    let fbits = if fbits.is_empty() {
+      fregs.iter().map(|val| val.to_bits()).collect()
+    } else {
+      fbits
+    };
+    for (reg, bits) in cpu.fregs.iter_mut().zip(fbits) {
+      *reg = bits;
+    }
+
+    let mut machine = Self { cpu, bus: Bus::new(bus), ..Self::default() };
+    for (addr, val) in csr.regs {
+      machine.csr_write(addr, val);
+    }
+    machine
+  }
+
+  pub fn repr(&self) -> CpuRepr {
+    CpuRepr {
+      pc: self.cpu.pc,
+      xregs: self.cpu.xregs.to_vec(),
+      fregs: (self.cpu.fregs.iter())
+        .map(|&bits| f64::from_bits(bits))
+        .map(|val| if val.is_finite() { val } else { 0.0 })
+        .collect(),
+      fbits: self.cpu.fregs.to_vec(),
+      bus: self.bus.repr(),
+      csr: CsrRepr {
+        mode: self.cpu.mode as u8,
+        regs: csr::PERSISTED
+          .iter()
+          .filter_map(|&addr| Some((addr, self.csr_read(addr)?)))
+          .collect(),
+      },
+    }
+  }
+
+  /// Fetch the raw instruction at `pc` along with its length in bytes.
+  pub fn fetch(&mut self) -> Result<(u32, u64), Exception> {
+    let pc = self.cpu.pc;
+    let mut half = |vaddr: u64| {
+      let paddr = self.translate(vaddr, Access::Fetch)?;
+      // Devices hold no code
+      self.bus.peek(paddr, 2).ok_or(Exception::InstructionAccessFault(vaddr))
+    };
+
+    let low = half(pc)?;
+    if low & 0b11 != 0b11 {
+      return Ok((low as u32, 2));
+    }
+    // The upper half may lie on the next page
+    let high = half(pc.wrapping_add(2))?;
+    Ok(((high << 16 | low) as u32, 4))
+  }
+
+  /// Raw instruction at `vaddr` and its length, read the way a debugger does
+  /// without faults or touching the TLB.
+  pub fn raw(&self, vaddr: u64) -> Option<(u32, u64)> {
+    let half = |vaddr| self.bus.peek(self.debug_paddr(vaddr)?, 2);
+    let low = half(vaddr)?;
+    if low & 0b11 != 0b11 {
+      return Some((low as u32, 2));
+    }
+    let high = half(vaddr.wrapping_add(2))?;
+    Some(((high << 16 | low) as u32, 4))
+  }
+
+  /// Instruction at `vaddr` like [`Self::raw`] reads it.
+  pub fn instruction(&self, vaddr: u64) -> Option<Instruction> {
+    let (raw, len) = self.raw(vaddr)?;
+    Self::decode(raw, len).ok()
+  }
+
+  pub fn decode(raw: u32, len: u64) -> Result<Instruction, DecodingError> {
+    if len == 2 {
+      (raw as u16).decode(Isa::Rv64)
+    } else {
+      raw.decode(Isa::Rv64)
+    }
+  }
+
+  /// Text of an instruction, F/D ones included, `None` for an unknown one.
+  pub fn disassemble(raw: u32, len: u64) -> Option<String> {
+    if let Ok(inst) = Self::decode(raw, len) {
+      return Some(inst.to_string());
+    }
+    if len == 2 {
+      let text = float::disassemble(rvc::expand_float(raw as u16)?)?;
+      Some(format!("c.{text}"))
+    } else {
+      float::disassemble(raw)
+    }
+  }
+
+  /// Return address of the call at `pc`, `None` for any other instruction.
+  pub fn call_return(&mut self) -> Option<u64> {
+    let (raw, len) = self.fetch().ok()?;
+    let inst = Self::decode(raw, len).ok()?;
+
+    let call = match inst.opc {
+      OpcodeKind::BaseI(BaseIOpcode::JAL | BaseIOpcode::JALR) => {
+        inst.rd != Some(0)
+      }
+      // Both link through `ra` implicitly
+      OpcodeKind::C(COpcode::JAL | COpcode::JALR) => true,
+      _ => false,
+    };
+    call.then(|| self.cpu.pc.wrapping_add(len))
+  }
+
+  /// Execute exactly one instruction or enter the trap handler it raised.
+  ///
+  /// Exceptions without a handler are returned with `pc` left untouched.
+  pub fn step(&mut self) -> Result<(), Exception> {
+    // Only the accesses of this step count
+    self.watch.hit = None;
+    if !self.interrupt() {
+      match self.exec() {
+        Ok(next) => {
+          self.cpu.pc = next;
+          let csrs = &mut self.cpu.csrs;
+          csrs.minstret = csrs.minstret.wrapping_add(1);
+        }
+        Err(exc) => self.trap(exc)?,
+      }
+    }
+    self.bus.tick();
+    let csrs = &mut self.cpu.csrs;
+    csrs.mcycle = csrs.mcycle.wrapping_add(1);
+    Ok(())
+  }
+
+  /// Returns the address of the next instruction.
+  fn exec(&mut self) -> Result<u64, Exception> {
+    let (raw, len) = self.fetch()?;
+
+    // `raki` decodes neither F/D nor their compressed loads and stores
+    let float = if len == 2 {
+      rvc::expand_float(raw as u16)
+    } else {
+      Some(raw).filter(|&raw| float::is_float(raw))
+    };
+    if let Some(float) = float {
+      return self.exec_float(float, len);
+    }
+
+    let inst = Self::decode(raw, len)
+      .map_err(|_| Exception::IllegalInstruction(raw as u64))?;
+    self.execute(&inst, raw, len)
+  }
+
+  /// Returns the address of the next instruction.
+  fn execute(
+    &mut self,
+    inst: &Instruction,
+    raw: u32,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    match &inst.opc {
+      OpcodeKind::BaseI(op) => self.exec_base(op, inst, len),
+      OpcodeKind::M(op) => self.exec_m(op, inst, len),
+      OpcodeKind::A(op) => self.exec_a(op, inst, len),
+      OpcodeKind::C(op) => self.exec_c(op, inst, len),
+      OpcodeKind::Zicsr(op) => self.exec_csr(op, raw, len),
+      // Counter reads are plain `csrrs` from a read-only CSR
+      OpcodeKind::Zicntr(_) => self.exec_csr(&ZicsrOpcode::CSRRS, raw, len),
+      OpcodeKind::Priv(op) => self.exec_priv(op, raw, len),
+      // Single hart with no caches: fences have nothing to order
+      OpcodeKind::Zifencei(_) => Ok(self.cpu.pc.wrapping_add(len)),
+      _ => Err(Exception::IllegalInstruction(raw as u64)),
+    }
+  }
+
+  fn jump(&self, target: u64) -> Result<u64, Exception> {
+    if !target.is_multiple_of(IALIGN) {
+      Err(Exception::InstructionAddressMisaligned(target))
+    } else {
+      Ok(target)
+    }
+  }
+
+  /// Accesses crossing a page are split, as each half translates apart.
+  fn split(addr: u64, size: usize) -> usize {
+    size.min((PAGE - addr % PAGE) as usize)
+  }
+
+  fn load(&mut self, addr: u64, size: usize) -> Result<u64, Exception> {
+    let head = Self::split(addr, size);
+    let mut load = |addr: u64, size: usize| {
+      let paddr = self.translate(addr, Access::Load)?;
+      self.bus_load(paddr, size).ok_or(Exception::LoadAccessFault(addr))
+    };
+
+    let mut val = load(addr, head)?;
+    if head < size {
+      let high = load(addr.wrapping_add(head as u64), size - head)?;
+      val |= high << (8 * head);
+    }
+    self.accessed(MemAccess::Load { addr, size });
+    Ok(val)
+  }
+
+  fn store(
+    &mut self,
+    addr: u64,
+    size: usize,
+    val: u64,
+  ) -> Result<(), Exception> {
+    let head = Self::split(addr, size);
+    let tail = addr.wrapping_add(head as u64);
+
+    // Translate both halves first, so that a fault leaves memory untouched
+    let low = self.translate(addr, Access::Store)?;
+    let high =
+      if head < size { self.translate(tail, Access::Store)? } else { low };
+
+    self.bus_store(low, head, val).ok_or(Exception::StoreAccessFault(addr))?;
+    if head < size {
+      self
+        .bus_store(high, size - head, val >> (8 * head))
+        .ok_or(Exception::StoreAccessFault(tail))?;
+    }
+    self.accessed(MemAccess::Store { addr, size, val });
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+impl Machine {
+  /// Start of the default memory, where the code of tests goes.
+  pub(crate) const RAM: u64 = 0x8000_0000;
+
+  /// Machine with `code` at the start of its memory and `pc` on it.
+  pub(crate) fn with_code(code: &[u32]) -> Self {
+    let mut machine = Self::default();
+    for (idx, &raw) in code.iter().enumerate() {
+      machine.bus.store(Self::RAM + 4 * idx as u64, 4, raw as u64).unwrap();
+    }
+    machine.cpu.pc = Self::RAM;
+    machine
+  }
+}
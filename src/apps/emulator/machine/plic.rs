@@ -0,0 +1,203 @@
+/// Bytes of the register window.
+pub const PLIC_SIZE: u64 = 0x400_0000;
+
+/// Interrupt sources, the first one is reserved to mean "none".
+pub const SOURCES: usize = 32;
+/// Machine and supervisor mode of the only hart.
+pub const CONTEXTS: usize = 2;
+
+/// Source wired to the UART, as on the QEMU `virt` board.
+pub const UART_IRQ: usize = 10;
+
+const PENDING: u64 = 0x1000;
+const ENABLE: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// Platform-level interrupt controller with level-triggered gateways.
+#[derive(Clone)]
+pub struct Plic {
+  pub priority: [u32; SOURCES],
+  /// Lines currently raised by the devices.
+  pub level: u32,
+  pub pending: u32,
+  /// Claimed and not yet completed, gated until then.
+  pub claimed: u32,
+  pub enable: [u32; CONTEXTS],
+  pub threshold: [u32; CONTEXTS],
+}
+
+impl Default for Plic {
+  fn default() -> Self {
+    Self {
+      priority: [0; SOURCES],
+      level: 0,
+      pending: 0,
+      claimed: 0,
+      enable: [0; CONTEXTS],
+      threshold: [0; CONTEXTS],
+    }
+  }
+}
+
+impl Plic {
+  pub fn set_level(&mut self, source: usize, high: bool) {
+    let bit = 1 << source;
+    self.level = if high { self.level | bit } else { self.level & !bit };
+    self.pending = self.level & !self.claimed;
+  }
+
+  /// Pending source `context` would claim, highest priority then lowest id.
+  pub fn best(&self, context: usize) -> Option<usize> {
+    let ready = self.pending & self.enable[context];
+    (1..SOURCES)
+      .filter(|&source| ready >> source & 1 != 0)
+      .filter(|&source| self.priority[source] > self.threshold[context])
+      .min_by_key(|&source| std::cmp::Reverse(self.priority[source]))
+  }
+
+  pub fn interrupt(&self, context: usize) -> bool {
+    self.best(context).is_some()
+  }
+
+  pub fn read(&mut self, offset: u64) -> u32 {
+    match offset {
+      0..PENDING => self.priority[(offset / 4) as usize % SOURCES],
+      PENDING => self.pending,
+      ENABLE.. if offset < ENABLE + ENABLE_STRIDE * CONTEXTS as u64 => {
+        match (offset - ENABLE) % ENABLE_STRIDE {
+          0 => self.enable[((offset - ENABLE) / ENABLE_STRIDE) as usize],
+          _ => 0,
+        }
+      }
+      CONTEXT.. if offset < CONTEXT + CONTEXT_STRIDE * CONTEXTS as u64 => {
+        let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+        match (offset - CONTEXT) % CONTEXT_STRIDE {
+          0 => self.threshold[context],
+          4 => self.claim(context),
+          _ => 0,
+        }
+      }
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, offset: u64, val: u32) {
+    match offset {
+      0..PENDING => {
+        let source = (offset / 4) as usize % SOURCES;
+        if source != 0 {
+          self.priority[source] = val & 0b111;
+        }
+      }
+      ENABLE.. if offset < ENABLE + ENABLE_STRIDE * CONTEXTS as u64 => {
+        let context = ((offset - ENABLE) / ENABLE_STRIDE) as usize;
+        // Only the first word holds sources, and source 0 does not exist
+        if (offset - ENABLE) % ENABLE_STRIDE / 4 == 0 {
+          self.enable[context] = val & !1;
+        }
+      }
+      CONTEXT.. if offset < CONTEXT + CONTEXT_STRIDE * CONTEXTS as u64 => {
+        let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+        match (offset - CONTEXT) % CONTEXT_STRIDE {
+          0 => self.threshold[context] = val & 0b111,
+          4 => self.complete(val as usize),
+          _ => {}
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn claim(&mut self, context: usize) -> u32 {
+    let Some(source) = self.best(context) else {
+      return 0;
+    };
+    self.pending &= !(1 << source);
+    self.claimed |= 1 << source;
+    source as u32
+  }
+
+  fn complete(&mut self, source: usize) {
+    if source < SOURCES {
+      self.claimed &= !(1 << source);
+      self.pending = self.level & !self.claimed;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CLAIM: u64 = CONTEXT + 4;
+
+  /// Sources 3 and 5 raised and enabled in context 0.
+  fn plic() -> Plic {
+    let mut plic = Plic::default();
+    plic.write(3 * 4, 1);
+    plic.write(5 * 4, 2);
+    plic.write(ENABLE, 1 << 3 | 1 << 5 | 1);
+    plic.set_level(3, true);
+    plic.set_level(5, true);
+    plic
+  }
+
+  #[test]
+  fn registers() {
+    let mut plic = plic();
+    assert_eq!((plic.read(3 * 4), plic.read(5 * 4)), (1, 2));
+    assert_eq!(plic.read(PENDING), 1 << 3 | 1 << 5);
+    // Source 0 does not exist
+    assert_eq!(plic.read(ENABLE), 1 << 3 | 1 << 5);
+    plic.write(0, 7);
+    assert_eq!(plic.read(0), 0);
+    assert_eq!(plic.read(ENABLE + ENABLE_STRIDE), 0);
+  }
+
+  #[test]
+  fn claim_and_complete() {
+    let mut plic = plic();
+    assert!(plic.interrupt(0));
+    assert!(!plic.interrupt(1));
+    // Highest priority first
+    assert_eq!(plic.read(CLAIM), 5);
+    assert_eq!(plic.read(CLAIM), 3);
+    assert_eq!(plic.read(CLAIM), 0);
+    assert!(!plic.interrupt(0));
+
+    // Gated until completed, even as the line stays up
+    plic.set_level(5, true);
+    assert_eq!(plic.pending, 0);
+    plic.write(CLAIM, 5);
+    assert_eq!(plic.read(CLAIM), 5);
+
+    // A line lowered meanwhile is not pending once completed
+    plic.set_level(3, false);
+    plic.write(CLAIM, 3);
+    assert_eq!(plic.pending, 0);
+  }
+
+  #[test]
+  fn equal_priorities_go_by_id() {
+    let mut plic = plic();
+    plic.write(5 * 4, 1);
+    assert_eq!(plic.best(0), Some(3));
+  }
+
+  #[test]
+  fn threshold() {
+    let mut plic = plic();
+    plic.write(CONTEXT, 1);
+    assert_eq!(plic.read(CONTEXT), 1);
+    assert_eq!(plic.best(0), Some(5));
+    plic.write(CONTEXT, 2);
+    assert!(!plic.interrupt(0));
+    assert_eq!(plic.read(CLAIM), 0);
+    // Each context has its own
+    plic.write(ENABLE + ENABLE_STRIDE, 1 << 3);
+    assert_eq!(plic.read(CONTEXT + CONTEXT_STRIDE), 0);
+    assert_eq!(plic.read(CLAIM + CONTEXT_STRIDE), 3);
+  }
+}
@@ -0,0 +1,156 @@
+use {
+  super::{Exception, Machine, MemAccess, mmu::Access},
+  raki::{AOpcode, Instruction},
+};
+
+impl Machine {
+  pub(super) fn exec_a(
+    &mut self,
+    op: &AOpcode,
+    inst: &Instruction,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    use AOpcode::*;
+
+    let rd = inst.rd.unwrap_or(0);
+    let addr = self.cpu.x(inst.rs1.unwrap_or(0));
+    let rs2 = self.cpu.x(inst.rs2.unwrap_or(0));
+    let next = self.cpu.pc.wrapping_add(len);
+
+    let size = match op {
+      LR_W | SC_W | AMOSWAP_W | AMOADD_W | AMOXOR_W | AMOAND_W | AMOOR_W
+      | AMOMIN_W | AMOMAX_W | AMOMINU_W | AMOMAXU_W => 4,
+      _ => 8,
+    };
+    // Sign-extend words, so that both widths compare and return alike
+    let extend = |val: u64| if size == 4 { val as i32 as u64 } else { val };
+
+    if !addr.is_multiple_of(size as u64) {
+      return Err(match op {
+        LR_W | LR_D => Exception::LoadAddressMisaligned(addr),
+        _ => Exception::StoreAddressMisaligned(addr),
+      });
+    }
+
+    match op {
+      LR_W | LR_D => {
+        let val = extend(self.load(addr, size)?);
+        self.cpu.reservation = Some(addr);
+        self.cpu.set_x(rd, val);
+        return Ok(next);
+      }
+      SC_W | SC_D => {
+        let reserved = self.cpu.reservation.take() == Some(addr);
+        if reserved {
+          self.store(addr, size, rs2)?;
+        }
+        self.cpu.set_x(rd, !reserved as u64);
+        return Ok(next);
+      }
+      _ => {}
+    }
+
+    // Read and write back through one translation, which must allow stores
+    let fault = Exception::StoreAccessFault(addr);
+    let paddr = self.translate(addr, Access::Store)?;
+    let old = extend(self.bus_load(paddr, size).ok_or(fault)?);
+    let src = extend(rs2);
+    let val = match op {
+      AMOSWAP_W | AMOSWAP_D => src,
+      AMOADD_W | AMOADD_D => old.wrapping_add(src),
+      AMOXOR_W | AMOXOR_D => old ^ src,
+      AMOAND_W | AMOAND_D => old & src,
+      AMOOR_W | AMOOR_D => old | src,
+      AMOMIN_W | AMOMIN_D => (old as i64).min(src as i64) as u64,
+      AMOMAX_W | AMOMAX_D => (old as i64).max(src as i64) as u64,
+      AMOMINU_W | AMOMINU_D => old.min(src),
+      AMOMAXU_W | AMOMAXU_D => old.max(src),
+      _ => unreachable!(),
+    };
+    self.bus_store(paddr, size, val).ok_or(fault)?;
+    self.accessed(MemAccess::Load { addr, size });
+    self.accessed(MemAccess::Store { addr, size, val });
+
+    self.cpu.set_x(rd, old);
+    Ok(next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LR_W: u32 = 0x1005262f; // lr.w a2, (a0)
+  const SC_W: u32 = 0x18b526af; // sc.w a3, a1, (a0)
+  const AMOADD_W: u32 = 0x00b5262f; // amoadd.w a2, a1, (a0)
+  const AMOMAX_W: u32 = 0xa0b5262f;
+  const AMOSWAP_D: u32 = 0x08b5362f;
+  const AMOMINU_D: u32 = 0xc0b5362f;
+
+  /// Machine running `code` on the word at `a0`, and that address.
+  fn with_word(code: &[u32], mem: u64) -> (Machine, u64) {
+    let mut machine = Machine::with_code(code);
+    let addr = Machine::RAM + 0x100;
+    machine.bus.store(addr, 8, mem).unwrap();
+    machine.cpu.xregs[10] = addr;
+    (machine, addr)
+  }
+
+  #[test]
+  fn store_conditional_needs_the_reservation() {
+    let (mut machine, addr) = with_word(&[LR_W, SC_W, SC_W], 0x8000_0000);
+    machine.cpu.xregs[11] = 7;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(12), 0xffff_ffff_8000_0000);
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(13), 0);
+    assert_eq!(machine.bus.load(addr, 4), Some(7));
+
+    // The reservation went with the first `sc`
+    machine.cpu.xregs[11] = 9;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(13), 1);
+    assert_eq!(machine.bus.load(addr, 4), Some(7));
+  }
+
+  #[test]
+  fn amo_returns_the_old_value() {
+    let code = [AMOADD_W, AMOMAX_W];
+    let (mut machine, addr) = with_word(&code, 0xffff_fffe);
+    machine.cpu.xregs[11] = 3;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(12), -2_i64 as u64);
+    assert_eq!(machine.bus.load(addr, 8), Some(1));
+
+    // Words compare signed
+    machine.cpu.xregs[11] = 0xffff_fffb;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(12), 1);
+    assert_eq!(machine.bus.load(addr, 8), Some(1));
+
+    let code = [AMOSWAP_D, AMOMINU_D];
+    let (mut machine, addr) = with_word(&code, 5);
+    machine.cpu.xregs[11] = u64::MAX;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(12), 5);
+    assert_eq!(machine.bus.load(addr, 8), Some(u64::MAX));
+    machine.cpu.xregs[11] = 6;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.x(12), u64::MAX);
+    assert_eq!(machine.bus.load(addr, 8), Some(6));
+  }
+
+  #[test]
+  fn misaligned() {
+    let (mut machine, addr) = with_word(&[LR_W], 0);
+    machine.cpu.xregs[10] = addr + 2;
+    let fault = Exception::LoadAddressMisaligned(addr + 2);
+    assert_eq!(machine.step(), Err(fault));
+
+    let (mut machine, addr) = with_word(&[AMOADD_W], 0);
+    machine.cpu.xregs[10] = addr + 2;
+    let fault = Exception::StoreAddressMisaligned(addr + 2);
+    assert_eq!(machine.step(), Err(fault));
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+  }
+}
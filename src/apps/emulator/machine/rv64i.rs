@@ -0,0 +1,221 @@
+use {
+  super::{Exception, Machine},
+  raki::{BaseIOpcode, Instruction},
+};
+
+impl Machine {
+  pub(super) fn exec_base(
+    &mut self,
+    op: &BaseIOpcode,
+    inst: &Instruction,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    use BaseIOpcode::*;
+
+    let pc = self.cpu.pc;
+    let next = pc.wrapping_add(len);
+
+    let rd = inst.rd.unwrap_or(0);
+    let rs1 = self.cpu.x(inst.rs1.unwrap_or(0));
+    let rs2 = self.cpu.x(inst.rs2.unwrap_or(0));
+    let imm = inst.imm.unwrap_or(0) as i64 as u64;
+
+    let addr = rs1.wrapping_add(imm);
+    let branch = |taken: bool| {
+      if taken { self.jump(pc.wrapping_add(imm)) } else { Ok(next) }
+    };
+
+    let val = match op {
+      LUI => imm,
+      AUIPC => pc.wrapping_add(imm),
+      JAL => {
+        let target = self.jump(pc.wrapping_add(imm))?;
+        self.cpu.set_x(rd, next);
+        return Ok(target);
+      }
+      JALR => {
+        let target = self.jump(addr & !1)?;
+        self.cpu.set_x(rd, next);
+        return Ok(target);
+      }
+
+      BEQ => return branch(rs1 == rs2),
+      BNE => return branch(rs1 != rs2),
+      BLT => return branch((rs1 as i64) < (rs2 as i64)),
+      BGE => return branch((rs1 as i64) >= (rs2 as i64)),
+      BLTU => return branch(rs1 < rs2),
+      BGEU => return branch(rs1 >= rs2),
+
+      LB => self.load(addr, 1)? as i8 as u64,
+      LH => self.load(addr, 2)? as i16 as u64,
+      LW => self.load(addr, 4)? as i32 as u64,
+      LD => self.load(addr, 8)?,
+      LBU => self.load(addr, 1)?,
+      LHU => self.load(addr, 2)?,
+      LWU => self.load(addr, 4)?,
+
+      SB | SH | SW | SD => {
+        let size = match op {
+          SB => 1,
+          SH => 2,
+          SW => 4,
+          _ => 8,
+        };
+        self.store(addr, size, rs2)?;
+        return Ok(next);
+      }
+
+      ADDI => rs1.wrapping_add(imm),
+      SLTI => ((rs1 as i64) < (imm as i64)) as u64,
+      SLTIU => (rs1 < imm) as u64,
+      XORI => rs1 ^ imm,
+      ORI => rs1 | imm,
+      ANDI => rs1 & imm,
+      SLLI => rs1 << (imm & 0x3f),
+      SRLI => rs1 >> (imm & 0x3f),
+      SRAI => ((rs1 as i64) >> (imm & 0x3f)) as u64,
+
+      ADD => rs1.wrapping_add(rs2),
+      SUB => rs1.wrapping_sub(rs2),
+      SLL => rs1 << (rs2 & 0x3f),
+      SLT => ((rs1 as i64) < (rs2 as i64)) as u64,
+      SLTU => (rs1 < rs2) as u64,
+      XOR => rs1 ^ rs2,
+      SRL => rs1 >> (rs2 & 0x3f),
+      SRA => ((rs1 as i64) >> (rs2 & 0x3f)) as u64,
+      OR => rs1 | rs2,
+      AND => rs1 & rs2,
+
+      ADDIW => rs1.wrapping_add(imm) as i32 as u64,
+      SLLIW => ((rs1 as u32) << (imm & 0x1f)) as i32 as u64,
+      SRLIW => ((rs1 as u32) >> (imm & 0x1f)) as i32 as u64,
+      SRAIW => ((rs1 as i32) >> (imm & 0x1f)) as u64,
+      ADDW => rs1.wrapping_add(rs2) as i32 as u64,
+      SUBW => rs1.wrapping_sub(rs2) as i32 as u64,
+      SLLW => ((rs1 as u32) << (rs2 & 0x1f)) as i32 as u64,
+      SRLW => ((rs1 as u32) >> (rs2 & 0x1f)) as i32 as u64,
+      SRAW => ((rs1 as i32) >> (rs2 & 0x1f)) as u64,
+
+      ECALL => return Err(Exception::EnvironmentCall),
+      EBREAK => return Err(Exception::Breakpoint(pc)),
+    };
+
+    self.cpu.set_x(rd, val);
+    Ok(next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RA: usize = 1;
+  const T: [usize; 7] = [5, 6, 7, 28, 29, 30, 31];
+  const A: [usize; 8] = [10, 11, 12, 13, 14, 15, 16, 17];
+  const S2: usize = 18;
+  const S3: usize = 19;
+  const S4: usize = 20;
+
+  #[test]
+  fn arithmetic() {
+    let mut machine = Machine::with_code(&[
+      0xfff00513, // addi a0, zero, -1
+      0x03c55593, // srli a1, a0, 60
+      0x43c55613, // srai a2, a0, 60
+      0x00153693, // sltiu a3, a0, 1
+      0x00052713, // slti a4, a0, 0
+      0x7ff5879b, // addiw a5, a1, 2047
+      0x80000837, // lui a6, 0x80000
+      0x010808bb, // addw a7, a6, a6
+      0x40b8593b, // sraw s2, a6, a1
+      0x00500013, // addi zero, zero, 5
+      0x40b009b3, // sub s3, zero, a1
+      0x00b59a33, // sll s4, a1, a1
+    ]);
+    for _ in 0..12 {
+      machine.step().unwrap();
+    }
+
+    let x = |idx| machine.cpu.x(idx);
+    assert_eq!(x(A[0]), u64::MAX);
+    assert_eq!(x(A[1]), 0xf);
+    assert_eq!(x(A[2]), u64::MAX);
+    assert_eq!(x(A[3]), 0);
+    assert_eq!(x(A[4]), 1);
+    assert_eq!(x(A[5]), 0x80e);
+    // Upper immediates and word results are sign-extended
+    assert_eq!(x(A[6]), 0xffff_ffff_8000_0000);
+    assert_eq!(x(A[7]), 0);
+    assert_eq!(x(S2), 0xffff_ffff_ffff_0000);
+    assert_eq!(x(0), 0);
+    assert_eq!(x(S3), -0xf_i64 as u64);
+    assert_eq!(x(S4), 0x78000);
+    assert_eq!(machine.cpu.pc, Machine::RAM + 48);
+  }
+
+  #[test]
+  fn branches_and_jumps() {
+    let mut machine = Machine::with_code(&[
+      0xfff00513, // addi a0, zero, -1
+      0x00050463, // beq a0, zero, 8
+      0x00a06463, // bltu zero, a0, 8
+      0x00100073, // ebreak
+      0x008000ef, // jal ra, 8
+      0x00100073, // ebreak
+      0x00008067, // jalr zero, 0(ra)
+    ]);
+    let mut pcs = vec![];
+    for _ in 0..5 {
+      machine.step().unwrap();
+      pcs.push(machine.cpu.pc - Machine::RAM);
+    }
+    assert_eq!(pcs, [4, 8, 16, 24, 20]);
+    assert_eq!(machine.cpu.x(RA), Machine::RAM + 20);
+
+    let pc = machine.cpu.pc;
+    assert_eq!(machine.step(), Err(Exception::Breakpoint(pc)));
+    assert_eq!(machine.cpu.pc, pc);
+  }
+
+  #[test]
+  fn loads_extend() {
+    let mut machine = Machine::with_code(&[
+      0x10a2b023, // sd a0, 256(t0)
+      0x07f00313, // addi t1, zero, 0x7f
+      0x10628023, // sb t1, 256(t0)
+      0x10028383, // lb t2, 256(t0)
+      0x1012ce03, // lbu t3, 257(t0)
+      0x10029e83, // lh t4, 256(t0)
+      0x1042af03, // lw t5, 260(t0)
+      0x1042ef83, // lwu t6, 260(t0)
+    ]);
+    machine.cpu.xregs[T[0]] = Machine::RAM;
+    machine.cpu.xregs[A[0]] = 0x8091_a2b3_c4d5_e6f7;
+    for _ in 0..8 {
+      machine.step().unwrap();
+    }
+
+    let x = |idx| machine.cpu.x(idx);
+    assert_eq!(x(T[2]), 0x7f);
+    assert_eq!(x(T[3]), 0xe6);
+    assert_eq!(x(T[4]), 0xffff_ffff_ffff_e67f);
+    assert_eq!(x(T[5]), 0xffff_ffff_8091_a2b3);
+    assert_eq!(x(T[6]), 0x8091_a2b3);
+  }
+
+  #[test]
+  fn exceptions_without_handler_leave_pc() {
+    let mut machine = Machine::with_code(&[
+      0xff803a83, // ld s5, -8(zero)
+    ]);
+    let fault = Exception::LoadAccessFault(-8_i64 as u64);
+    assert_eq!(machine.step(), Err(fault));
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+
+    let mut machine = Machine::with_code(&[
+      0x00000073, // ecall
+    ]);
+    assert_eq!(machine.step(), Err(Exception::EnvironmentCall));
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+  }
+}
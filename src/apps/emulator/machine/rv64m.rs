@@ -0,0 +1,119 @@
+use {
+  super::{Exception, Machine},
+  raki::{Instruction, MOpcode},
+};
+
+impl Machine {
+  pub(super) fn exec_m(
+    &mut self,
+    op: &MOpcode,
+    inst: &Instruction,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    use MOpcode::*;
+
+    let rs1 = self.cpu.x(inst.rs1.unwrap_or(0));
+    let rs2 = self.cpu.x(inst.rs2.unwrap_or(0));
+    let (w1, w2) = (rs1 as i32, rs2 as i32);
+
+    // Division never traps: by zero yields all ones (or the dividend for
+    // remainders), and signed overflow yields the dividend (or zero).
+    let val = match op {
+      MUL => rs1.wrapping_mul(rs2),
+      MULH => ((rs1 as i64 as i128 * rs2 as i64 as i128) >> 64) as u64,
+      MULHSU => ((rs1 as i64 as i128 * rs2 as i128) >> 64) as u64,
+      MULHU => ((rs1 as u128 * rs2 as u128) >> 64) as u64,
+      DIV if rs2 == 0 => u64::MAX,
+      DIV => (rs1 as i64).wrapping_div(rs2 as i64) as u64,
+      DIVU => rs1.checked_div(rs2).unwrap_or(u64::MAX),
+      REM if rs2 == 0 => rs1,
+      REM => (rs1 as i64).wrapping_rem(rs2 as i64) as u64,
+      REMU => rs1.checked_rem(rs2).unwrap_or(rs1),
+
+      MULW => w1.wrapping_mul(w2) as u64,
+      DIVW if w2 == 0 => u64::MAX,
+      DIVW => w1.wrapping_div(w2) as u64,
+      DIVUW => {
+        (w1 as u32).checked_div(w2 as u32).unwrap_or(u32::MAX) as i32 as u64
+      }
+      REMW if w2 == 0 => w1 as u64,
+      REMW => w1.wrapping_rem(w2) as u64,
+      REMUW => {
+        (w1 as u32).checked_rem(w2 as u32).unwrap_or(w1 as u32) as i32 as u64
+      }
+    };
+
+    self.cpu.set_x(inst.rd.unwrap_or(0), val);
+    Ok(self.cpu.pc.wrapping_add(len))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MUL: u32 = 0x02b50633; // mul a2, a0, a1
+  const MULH: u32 = 0x02b51633;
+  const MULHSU: u32 = 0x02b52633;
+  const MULHU: u32 = 0x02b53633;
+  const DIV: u32 = 0x02b54633;
+  const DIVU: u32 = 0x02b55633;
+  const REM: u32 = 0x02b56633;
+  const REMU: u32 = 0x02b57633;
+  const MULW: u32 = 0x02b5063b;
+  const DIVW: u32 = 0x02b5463b;
+  const DIVUW: u32 = 0x02b5563b;
+  const REMW: u32 = 0x02b5663b;
+  const REMUW: u32 = 0x02b5763b;
+
+  /// `a2` after `raw` ran on `a0` and `a1`.
+  fn op(raw: u32, a0: u64, a1: u64) -> u64 {
+    let mut machine = Machine::with_code(&[raw]);
+    machine.cpu.xregs[10..12].copy_from_slice(&[a0, a1]);
+    machine.step().unwrap();
+    machine.cpu.x(12)
+  }
+
+  #[test]
+  fn multiply() {
+    let minus = |val: i64| val as u64;
+    assert_eq!(op(MUL, minus(-3), 5), minus(-15));
+    assert_eq!(op(MULH, minus(-3), 5), u64::MAX);
+    assert_eq!(op(MULHU, minus(-3), 5), 4);
+    assert_eq!(op(MULHSU, minus(-3), 5), u64::MAX);
+    assert_eq!(op(MULHSU, 5, minus(-3)), 4);
+    assert_eq!(op(MULW, 0x7fff_ffff, 2), minus(-2));
+  }
+
+  #[test]
+  fn divide_by_zero() {
+    let a0 = 0x1_8000_0000;
+    assert_eq!(op(DIV, a0, 0), u64::MAX);
+    assert_eq!(op(DIVU, a0, 0), u64::MAX);
+    assert_eq!(op(REM, a0, 0), a0);
+    assert_eq!(op(REMU, a0, 0), a0);
+    assert_eq!(op(DIVW, a0, 0), u64::MAX);
+    assert_eq!(op(DIVUW, a0, 0), u64::MAX);
+    // The low word of the dividend, sign-extended
+    assert_eq!(op(REMW, a0, 0), 0xffff_ffff_8000_0000);
+    assert_eq!(op(REMUW, a0, 0), 0xffff_ffff_8000_0000);
+  }
+
+  #[test]
+  fn signed_overflow() {
+    let min = i64::MIN as u64;
+    assert_eq!(op(DIV, min, u64::MAX), min);
+    assert_eq!(op(REM, min, u64::MAX), 0);
+    assert_eq!(op(DIVW, 0x8000_0000, u64::MAX), 0xffff_ffff_8000_0000);
+    assert_eq!(op(REMW, 0x8000_0000, u64::MAX), 0);
+  }
+
+  #[test]
+  fn divide() {
+    let minus = |val: i64| val as u64;
+    assert_eq!(op(DIV, minus(-7), 2), minus(-3));
+    assert_eq!(op(REM, minus(-7), 2), minus(-1));
+    assert_eq!(op(DIVU, minus(-7), 2), u64::MAX / 2 - 3);
+    assert_eq!(op(DIVUW, 0xffff_fff9, 2), 0x7fff_fffc);
+  }
+}
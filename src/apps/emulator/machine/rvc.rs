@@ -0,0 +1,207 @@
+use {
+  super::{
+    Exception, Machine,
+    float::{LOAD_FP, STORE_FP},
+  },
+  raki::{COpcode, Instruction},
+};
+
+const RA: usize = 1;
+const SP: usize = 2;
+
+/// Expand `c.fld`, `c.fsd`, `c.fldsp` and `c.fsdsp`, which `raki` does not
+/// decode, into their 32-bit forms.
+pub fn expand_float(raw: u16) -> Option<u32> {
+  let raw = raw as u32;
+  let bits = |hi: u32, lo: u32| raw >> lo & ((1 << (hi - lo + 1)) - 1);
+  let (rd, rs1) = (bits(4, 2) + 8, bits(9, 7) + 8);
+
+  let load = |rd: u32, rs1: u32, imm: u32| {
+    imm << 20 | rs1 << 15 | 0b011 << 12 | rd << 7 | LOAD_FP
+  };
+  let store = |rs2: u32, rs1: u32, imm: u32| {
+    (imm >> 5) << 25
+      | rs2 << 20
+      | rs1 << 15
+      | 0b011 << 12
+      | (imm & 0x1f) << 7
+      | STORE_FP
+  };
+
+  Some(match (raw & 0b11, raw >> 13 & 0b111) {
+    (0b00, 0b001) => load(rd, rs1, bits(12, 10) << 3 | bits(6, 5) << 6),
+    (0b00, 0b101) => store(rd, rs1, bits(12, 10) << 3 | bits(6, 5) << 6),
+    (0b10, 0b001) => {
+      let imm = bits(12, 12) << 5 | bits(6, 5) << 3 | bits(4, 2) << 6;
+      load(bits(11, 7), SP as u32, imm)
+    }
+    (0b10, 0b101) => {
+      store(bits(6, 2), SP as u32, bits(12, 10) << 3 | bits(9, 7) << 6)
+    }
+    _ => return None,
+  })
+}
+
+impl Machine {
+  pub(super) fn exec_c(
+    &mut self,
+    op: &COpcode,
+    inst: &Instruction,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    use COpcode::*;
+
+    let pc = self.cpu.pc;
+    let next = pc.wrapping_add(len);
+
+    let rd = inst.rd.unwrap_or(0);
+    // Stack-relative forms leave their base register implicit
+    let rs1 = self.cpu.x(inst.rs1.unwrap_or(SP));
+    let rs2 = self.cpu.x(inst.rs2.unwrap_or(0));
+    let imm = inst.imm.unwrap_or(0) as i64 as u64;
+
+    let addr = rs1.wrapping_add(imm);
+    let branch = |taken: bool| {
+      if taken { self.jump(pc.wrapping_add(imm)) } else { Ok(next) }
+    };
+
+    let val = match op {
+      NOP => return Ok(next),
+      ADDI4SPN | ADDI | ADDI16SP => rs1.wrapping_add(imm),
+      ADDIW => rs1.wrapping_add(imm) as i32 as u64,
+      LI | LUI => imm,
+
+      LW | LWSP => self.load(addr, 4)? as i32 as u64,
+      LD | LDSP => self.load(addr, 8)?,
+      SW | SWSP => {
+        self.store(addr, 4, rs2)?;
+        return Ok(next);
+      }
+      SD | SDSP => {
+        self.store(addr, 8, rs2)?;
+        return Ok(next);
+      }
+
+      SRLI => rs1 >> (imm & 0x3f),
+      SRAI => ((rs1 as i64) >> (imm & 0x3f)) as u64,
+      SLLI => rs1 << (imm & 0x3f),
+      ANDI => rs1 & imm,
+
+      MV => rs2,
+      ADD => rs1.wrapping_add(rs2),
+      SUB => rs1.wrapping_sub(rs2),
+      XOR => rs1 ^ rs2,
+      OR => rs1 | rs2,
+      AND => rs1 & rs2,
+      ADDW => rs1.wrapping_add(rs2) as i32 as u64,
+      SUBW => rs1.wrapping_sub(rs2) as i32 as u64,
+
+      J => return self.jump(pc.wrapping_add(imm)),
+      JAL => {
+        let target = self.jump(pc.wrapping_add(imm))?;
+        self.cpu.set_x(RA, next);
+        return Ok(target);
+      }
+      JR => return self.jump(rs1 & !1),
+      JALR => {
+        let target = self.jump(rs1 & !1)?;
+        self.cpu.set_x(RA, next);
+        return Ok(target);
+      }
+      BEQZ => return branch(rs1 == 0),
+      BNEZ => return branch(rs1 != 0),
+
+      EBREAK => return Err(Exception::Breakpoint(pc)),
+    };
+
+    self.cpu.set_x(rd, val);
+    Ok(next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Machine with the compressed `code` at `pc`.
+  fn with_halves(code: &[u16]) -> Machine {
+    let words: Vec<_> = (code.chunks(2))
+      .map(|pair| pair[0] as u32 | ((*pair.get(1).unwrap_or(&0) as u32) << 16))
+      .collect();
+    Machine::with_code(&words)
+  }
+
+  #[test]
+  fn arithmetic_and_stack() {
+    let mut machine = with_halves(&[
+      0x5575, // c.li a0, -3
+      0x0515, // c.addi a0, 5
+      0x3571, // c.addiw a0, -4
+      0x7585, // c.lui a1, 0xfffe1
+      0x8591, // c.srai a1, 4
+      0x862a, // c.mv a2, a0
+      0x962e, // c.add a2, a1
+      0x8e09, // c.sub a2, a0
+      0x7139, // c.addi16sp sp, -64
+      0x0814, // c.addi4spn a3, sp, 16
+      0xe42e, // c.sdsp a1, 8(sp)
+      0x6722, // c.ldsp a4, 8(sp)
+      0xc22a, // c.swsp a0, 4(sp)
+      0x4792, // c.lwsp a5, 4(sp)
+    ]);
+    let stack = Machine::RAM + 0x1000;
+    machine.cpu.xregs[SP] = stack;
+    for _ in 0..14 {
+      machine.step().unwrap();
+    }
+
+    let x = |idx| machine.cpu.x(idx);
+    assert_eq!(x(10), -2_i64 as u64);
+    assert_eq!(x(11), 0xffff_ffff_ffff_e100);
+    assert_eq!(x(12), 0xffff_ffff_ffff_e100);
+    assert_eq!(x(SP), stack - 64);
+    assert_eq!(x(13), stack - 48);
+    assert_eq!(x(14), 0xffff_ffff_ffff_e100);
+    assert_eq!(x(15), -2_i64 as u64);
+    assert_eq!(machine.cpu.pc, Machine::RAM + 28);
+  }
+
+  #[test]
+  fn branches_and_jumps() {
+    let mut machine = with_halves(&[
+      0xc501, // c.beqz a0, 8
+      0xe119, // c.bnez a0, 6
+      0x0001, // c.nop
+      0x0001, // c.nop
+      0x9602, // c.jalr a2
+      0xbfdd, // c.j -10
+      0x0001, // c.nop
+      0x0001, // c.nop
+      0x8082, // c.jr ra
+    ]);
+    machine.cpu.xregs[10] = 1;
+    machine.cpu.xregs[12] = Machine::RAM + 16;
+    let mut pcs = vec![];
+    for _ in 0..5 {
+      machine.step().unwrap();
+      pcs.push(machine.cpu.pc - Machine::RAM);
+    }
+    assert_eq!(pcs, [2, 8, 16, 10, 0]);
+    assert_eq!(machine.cpu.x(RA), Machine::RAM + 10);
+  }
+
+  #[test]
+  fn zero_is_illegal() {
+    let mut machine = with_halves(&[0x0000]);
+    assert_eq!(machine.step(), Err(Exception::IllegalInstruction(0)));
+  }
+
+  #[test]
+  fn expands_float_loads_and_stores() {
+    assert_eq!(expand_float(0x2508), Some(0x00853507)); // fld fa0, 8(a0)
+    assert_eq!(expand_float(0xa984), Some(0x0095b827)); // fsd fs1, 16(a1)
+    assert_eq!(expand_float(0x2462), Some(0x01813407)); // fld fs0, 24(sp)
+    assert_eq!(expand_float(0xb02e), Some(0x02b13027)); // fsd fa1, 32(sp)
+    assert_eq!(expand_float(0x0515), None); // c.addi a0, 5
+  }
+}
@@ -0,0 +1,24 @@
+use super::Machine;
+
+/// Load or store an instruction made, at its virtual address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemAccess {
+  Load { addr: u64, size: usize },
+  Store { addr: u64, size: usize, val: u64 },
+}
+
+/// Memory accesses of the current step, recorded while `enabled` for an
+/// execution trace.
+#[derive(Debug, Default, Clone)]
+pub struct Accesses {
+  pub enabled: bool,
+  pub list: Vec<MemAccess>,
+}
+
+impl Machine {
+  pub(super) fn accessed(&mut self, access: MemAccess) {
+    if self.accesses.enabled {
+      self.accesses.list.push(access);
+    }
+  }
+}
@@ -0,0 +1,304 @@
+use {
+  super::{Exception, Machine, csr::status::*},
+  raki::PrivOpcode,
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priv {
+  User = 0,
+  Supervisor = 1,
+  #[default]
+  Machine = 3,
+}
+
+impl Priv {
+  pub fn new(bits: u64) -> Option<Self> {
+    Some(match bits {
+      0 => Self::User,
+      1 => Self::Supervisor,
+      3 => Self::Machine,
+      _ => return None,
+    })
+  }
+}
+
+impl Exception {
+  /// Value of `mcause`/`scause` when raised from `mode`.
+  pub fn cause(&self, mode: Priv) -> u64 {
+    match self {
+      Self::InstructionAddressMisaligned(_) => 0,
+      Self::InstructionAccessFault(_) => 1,
+      Self::IllegalInstruction(_) => 2,
+      Self::Breakpoint(_) => 3,
+      Self::LoadAddressMisaligned(_) => 4,
+      Self::LoadAccessFault(_) => 5,
+      Self::StoreAddressMisaligned(_) => 6,
+      Self::StoreAccessFault(_) => 7,
+      Self::EnvironmentCall => 8 + mode as u64,
+      Self::InstructionPageFault(_) => 12,
+      Self::LoadPageFault(_) => 13,
+      Self::StorePageFault(_) => 15,
+    }
+  }
+
+  /// Value of `mtval`/`stval`.
+  pub fn tval(&self) -> u64 {
+    match *self {
+      Self::InstructionAddressMisaligned(val)
+      | Self::InstructionAccessFault(val)
+      | Self::IllegalInstruction(val)
+      | Self::Breakpoint(val)
+      | Self::LoadAddressMisaligned(val)
+      | Self::LoadAccessFault(val)
+      | Self::StoreAddressMisaligned(val)
+      | Self::StoreAccessFault(val)
+      | Self::InstructionPageFault(val)
+      | Self::LoadPageFault(val)
+      | Self::StorePageFault(val) => val,
+      Self::EnvironmentCall => 0,
+    }
+  }
+}
+
+/// Interrupt causes and their `mip` names, highest priority first.
+pub const INTERRUPTS: [(u64, &str); 6] =
+  [(11, "MEI"), (3, "MSI"), (7, "MTI"), (9, "SEI"), (1, "SSI"), (5, "STI")];
+
+const MSIP: u64 = 1 << 3;
+const MTIP: u64 = 1 << 7;
+const SEIP: u64 = 1 << 9;
+const MEIP: u64 = 1 << 11;
+
+/// Bit of `mcause`/`scause` telling interrupts from exceptions.
+const INTERRUPT: u64 = 1 << 63;
+
+impl Machine {
+  /// Enter the trap handler of `exc`, delegated to supervisor mode if asked.
+  ///
+  /// Without a handler installed the exception is handed back untouched, so
+  /// bare programs stop at the faulting instruction instead of jumping to 0.
+  pub(super) fn trap(&mut self, exc: Exception) -> Result<(), Exception> {
+    let cause = exc.cause(self.cpu.mode);
+    let csrs = &self.cpu.csrs;
+
+    let delegate =
+      self.cpu.mode <= Priv::Supervisor && csrs.medeleg >> cause & 1 != 0;
+    let tvec = if delegate { csrs.stvec } else { csrs.mtvec } & !0b11;
+    if tvec == 0 {
+      return Err(exc);
+    }
+    self.enter(cause, exc.tval(), delegate);
+    self.cpu.pc = tvec;
+    Ok(())
+  }
+
+  /// Interrupts raised by the devices or by software, SEIP included.
+  pub fn pending(&self) -> u64 {
+    let plic = if self.bus.plic.interrupt(1) { SEIP } else { 0 };
+    self.cpu.csrs.mip | plic
+  }
+
+  /// Take the highest priority interrupt that is pending and enabled.
+  ///
+  /// Interrupts without a handler stay pending instead of halting the run.
+  pub(super) fn interrupt(&mut self) -> bool {
+    let (clint, plic) = (&self.bus.clint, &self.bus.plic);
+    let device = if clint.msip { MSIP } else { 0 }
+      | if clint.timer() { MTIP } else { 0 }
+      | if plic.interrupt(0) { MEIP } else { 0 };
+    let csrs = &mut self.cpu.csrs;
+    csrs.mip = csrs.mip & !(MSIP | MTIP | MEIP) | device;
+
+    let (mode, status) = (self.cpu.mode, csrs.mstatus);
+    let pending = self.pending() & self.cpu.csrs.mie;
+    if pending == 0 {
+      return false;
+    }
+    let machine = mode < Priv::Machine || status & MIE != 0;
+    let supervisor =
+      mode < Priv::Supervisor || mode == Priv::Supervisor && status & SIE != 0;
+
+    for (code, _) in INTERRUPTS {
+      if pending >> code & 1 == 0 {
+        continue;
+      }
+      let csrs = &self.cpu.csrs;
+      let delegate = csrs.mideleg >> code & 1 != 0;
+      let enabled = if delegate { supervisor } else { machine };
+      let tvec = if delegate { csrs.stvec } else { csrs.mtvec };
+      let base = tvec & !0b11;
+      if !enabled || base == 0 {
+        continue;
+      }
+      self.enter(INTERRUPT | code, 0, delegate);
+      // Vectored mode jumps to a slot per cause
+      self.cpu.pc = if tvec & 1 != 0 { base + 4 * code } else { base };
+      return true;
+    }
+    false
+  }
+
+  /// Save the interrupted state and switch to the handler's mode.
+  fn enter(&mut self, cause: u64, tval: u64, delegate: bool) {
+    let (mode, pc) = (self.cpu.mode, self.cpu.pc);
+    let csrs = &mut self.cpu.csrs;
+    let status = csrs.mstatus;
+    if delegate {
+      (csrs.sepc, csrs.scause, csrs.stval) = (pc, cause, tval);
+      csrs.mstatus = status & !(SIE | SPIE | SPP)
+        | if status & SIE != 0 { SPIE } else { 0 }
+        | if mode == Priv::Supervisor { SPP } else { 0 };
+      self.cpu.mode = Priv::Supervisor;
+    } else {
+      (csrs.mepc, csrs.mcause, csrs.mtval) = (pc, cause, tval);
+      csrs.mstatus = status & !(MIE | MPIE | MPP)
+        | if status & MIE != 0 { MPIE } else { 0 }
+        | (mode as u64) << 11;
+      self.cpu.mode = Priv::Machine;
+    }
+  }
+
+  pub(super) fn exec_priv(
+    &mut self,
+    op: &PrivOpcode,
+    raw: u32,
+    len: u64,
+  ) -> Result<u64, Exception> {
+    let illegal = Exception::IllegalInstruction(raw as u64);
+    let (mode, status) = (self.cpu.mode, self.cpu.csrs.mstatus);
+    // Trapped by `mstatus` when executed from supervisor mode
+    let trapped = |bit: u64| {
+      mode == Priv::User || mode == Priv::Supervisor && status & bit != 0
+    };
+
+    match op {
+      PrivOpcode::MRET => {
+        if mode != Priv::Machine {
+          return Err(illegal);
+        }
+        let mpp = Priv::new(status >> 11 & 0b11).unwrap_or_default();
+        let mut status = status & !(MIE | MPP)
+          | MPIE
+          | if status & MPIE != 0 { MIE } else { 0 };
+        if mpp != Priv::Machine {
+          status &= !MPRV;
+        }
+        self.cpu.csrs.mstatus = status;
+        self.cpu.mode = mpp;
+        Ok(self.cpu.csrs.mepc)
+      }
+      PrivOpcode::SRET => {
+        if trapped(TSR) {
+          return Err(illegal);
+        }
+        let spp = if status & SPP != 0 { Priv::Supervisor } else { Priv::User };
+        self.cpu.csrs.mstatus = status & !(SIE | SPP | MPRV)
+          | SPIE
+          | if status & SPIE != 0 { SIE } else { 0 };
+        self.cpu.mode = spp;
+        Ok(self.cpu.csrs.sepc)
+      }
+      // Resume at once, the next step takes whatever interrupt is pending
+      PrivOpcode::WFI if !trapped(TW) => Ok(self.cpu.pc.wrapping_add(len)),
+      // Entries are not tagged by address space: drop them all
+      PrivOpcode::SFENCE_VMA if !trapped(TVM) => {
+        self.tlb.flush();
+        Ok(self.cpu.pc.wrapping_add(len))
+      }
+      _ => Err(illegal),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ECALL: u32 = 0x00000073;
+  const NOP: u32 = 0x00000013;
+
+  #[test]
+  fn ecall_and_mret() {
+    let mut machine = Machine::with_code(&[
+      ECALL, NOP, NOP, NOP, 0x34202573, // csrr a0, mcause
+      0x341025f3, // csrr a1, mepc
+      0x00458593, // addi a1, a1, 4
+      0x34159073, // csrw mepc, a1
+      0x30200073, // mret
+    ]);
+    machine.cpu.csrs.mtvec = Machine::RAM + 16;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.pc, Machine::RAM + 16);
+    assert_eq!(machine.cpu.csrs.mepc, Machine::RAM);
+    assert_eq!(machine.cpu.csrs.mstatus & MPP, MPP);
+
+    for _ in 0..5 {
+      machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.x(10), 11);
+    assert_eq!(machine.cpu.pc, Machine::RAM + 4);
+    assert_eq!(machine.cpu.mode, Priv::Machine);
+  }
+
+  #[test]
+  fn delegated_to_supervisor() {
+    let mut machine = Machine::with_code(&[ECALL, NOP, NOP, NOP, 0x10200073]);
+    machine.cpu.mode = Priv::User;
+    machine.cpu.csrs.medeleg = 1 << 8;
+    (machine.cpu.csrs.mtvec, machine.cpu.csrs.stvec) =
+      (0x1000, Machine::RAM + 16);
+    machine.step().unwrap();
+
+    let csrs = &machine.cpu.csrs;
+    assert_eq!(machine.cpu.mode, Priv::Supervisor);
+    assert_eq!((csrs.scause, csrs.sepc, csrs.mcause), (8, Machine::RAM, 0));
+    assert_eq!(csrs.mstatus & SPP, 0);
+
+    machine.cpu.csrs.sepc += 4;
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.mode, Priv::User);
+    assert_eq!(machine.cpu.pc, Machine::RAM + 4);
+  }
+
+  #[test]
+  fn illegal_below_machine_mode() {
+    let raw = 0x30200073; // mret
+    let mut machine = Machine::with_code(&[raw]);
+    machine.cpu.mode = Priv::User;
+    machine.cpu.csrs.mtvec = Machine::RAM + 0x100;
+    machine.step().unwrap();
+
+    let csrs = &machine.cpu.csrs;
+    assert_eq!((csrs.mcause, csrs.mtval), (2, raw as u64));
+    assert_eq!(csrs.mstatus & MPP, 0);
+    assert_eq!(machine.cpu.mode, Priv::Machine);
+  }
+
+  #[test]
+  fn without_handler() {
+    let mut machine = Machine::with_code(&[ECALL]);
+    assert_eq!(machine.step(), Err(Exception::EnvironmentCall));
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+    assert_eq!(machine.cpu.csrs.mcause, 0);
+  }
+
+  #[test]
+  fn vectored_interrupt() {
+    let mut machine = Machine::with_code(&[NOP, NOP]);
+    machine.bus.clint.msip = true;
+    machine.cpu.csrs.mie = MSIP;
+    machine.cpu.csrs.mtvec = (Machine::RAM + 0x100) | 1;
+
+    // Masked by `mstatus.MIE` while in machine mode
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.pc, Machine::RAM + 4);
+    assert_eq!(machine.pending() & MSIP, MSIP);
+
+    machine.cpu.csrs.mstatus |= MIE;
+    machine.step().unwrap();
+    let csrs = &machine.cpu.csrs;
+    assert_eq!(machine.cpu.pc, Machine::RAM + 0x100 + 4 * 3);
+    assert_eq!((csrs.mcause, csrs.mepc), (INTERRUPT | 3, Machine::RAM + 4));
+    assert_eq!(csrs.mstatus & (MIE | MPIE), MPIE);
+  }
+}
@@ -0,0 +1,208 @@
+use {concurrent_queue::ConcurrentQueue, std::sync::Arc};
+
+/// Bytes of the register window, registers are one byte apart.
+pub const UART_SIZE: u64 = 0x100;
+
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const LCR_DLAB: u8 = 1 << 7;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+/// Both directions of the serial line.
+///
+/// Shared by every clone of the machine, so the console keeps talking to a
+/// machine that runs on another thread.
+#[derive(Clone)]
+pub struct Serial {
+  pub tx: Arc<ConcurrentQueue<u8>>,
+  pub rx: Arc<ConcurrentQueue<u8>>,
+}
+
+impl Default for Serial {
+  fn default() -> Self {
+    Self {
+      tx: Arc::new(ConcurrentQueue::unbounded()),
+      rx: Arc::new(ConcurrentQueue::unbounded()),
+    }
+  }
+}
+
+/// 16550-compatible UART, transmitting instantly.
+#[derive(Default, Clone)]
+pub struct Uart {
+  pub serial: Serial,
+  /// Bytes taken from `rx` so far, input a replay cannot reproduce.
+  pub received: u64,
+  ier: u8,
+  lcr: u8,
+  mcr: u8,
+  fcr: u8,
+  scr: u8,
+  divisor: u16,
+  /// Transmitter empty interrupt, until `iir` reports it or `thr` is written.
+  thre: bool,
+}
+
+impl Uart {
+  fn rx_ready(&self) -> bool {
+    self.ier & IER_RDA != 0 && !self.serial.rx.is_empty()
+  }
+
+  /// Level of the interrupt line wired to the PLIC.
+  pub fn interrupt(&self) -> bool {
+    self.rx_ready() || self.thre && self.ier & IER_THRE != 0
+  }
+
+  pub fn read(&mut self, offset: u64) -> u8 {
+    let dlab = self.lcr & LCR_DLAB != 0;
+    match offset {
+      0 if dlab => self.divisor as u8,
+      0 => match self.serial.rx.pop() {
+        Ok(byte) => {
+          self.received += 1;
+          byte
+        }
+        Err(_) => 0,
+      },
+      1 if dlab => (self.divisor >> 8) as u8,
+      1 => self.ier,
+      2 => {
+        let fifo = if self.fcr & FCR_ENABLE != 0 { 0xc0 } else { 0 };
+        let id = if self.rx_ready() {
+          0x04
+        } else if self.thre && self.ier & IER_THRE != 0 {
+          self.thre = false;
+          0x02
+        } else {
+          0x01
+        };
+        fifo | id
+      }
+      3 => self.lcr,
+      4 => self.mcr,
+      5 => {
+        let ready = if self.serial.rx.is_empty() { 0 } else { LSR_DR };
+        ready | LSR_THRE | LSR_TEMT
+      }
+      // Modem lines report carrier, ready and clear to send
+      6 => 0xb0,
+      7 => self.scr,
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, offset: u64, val: u8) {
+    let dlab = self.lcr & LCR_DLAB != 0;
+    match offset {
+      0 if dlab => self.divisor = self.divisor & 0xff00 | val as u16,
+      0 => {
+        let _ = self.serial.tx.push(val);
+        self.thre = true;
+      }
+      1 if dlab => self.divisor = self.divisor & 0xff | (val as u16) << 8,
+      1 => {
+        // Enabling the interrupt raises it at once: nothing is ever queued
+        if val & IER_THRE != 0 && self.ier & IER_THRE == 0 {
+          self.thre = true;
+        }
+        self.ier = val & 0x0f;
+      }
+      2 => {
+        if val & FCR_CLEAR_RX != 0 {
+          while self.serial.rx.pop().is_ok() {
+            self.received += 1;
+          }
+        }
+        self.fcr = val;
+      }
+      3 => self.lcr = val,
+      4 => self.mcr = val,
+      7 => self.scr = val,
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const RBR: u64 = 0;
+  const IER: u64 = 1;
+  const IIR: u64 = 2;
+  const LCR: u64 = 3;
+  const LSR: u64 = 5;
+
+  #[test]
+  fn receives_in_order() {
+    let mut uart = Uart::default();
+    assert_eq!(uart.read(LSR), LSR_THRE | LSR_TEMT);
+    for byte in *b"hi" {
+      uart.serial.rx.push(byte).unwrap();
+    }
+    assert_eq!(uart.read(LSR) & LSR_DR, LSR_DR);
+    assert_eq!((uart.read(RBR), uart.read(RBR)), (b'h', b'i'));
+    assert_eq!(uart.read(LSR) & LSR_DR, 0);
+    assert_eq!((uart.read(RBR), uart.received), (0, 2));
+  }
+
+  #[test]
+  fn clears_the_fifo() {
+    let mut uart = Uart::default();
+    for byte in *b"abc" {
+      uart.serial.rx.push(byte).unwrap();
+    }
+    uart.write(IIR, FCR_ENABLE | FCR_CLEAR_RX);
+    assert!(uart.serial.rx.is_empty());
+    assert_eq!(uart.received, 3);
+    assert_eq!(uart.read(IIR), 0xc1);
+  }
+
+  #[test]
+  fn transmits() {
+    let mut uart = Uart::default();
+    uart.write(RBR, b'x');
+    assert_eq!(uart.serial.tx.pop(), Ok(b'x'));
+    // Disabled, the interrupt is not reported
+    assert!(!uart.interrupt());
+    assert_eq!(uart.read(IIR), 0x01);
+  }
+
+  #[test]
+  fn interrupts() {
+    let mut uart = Uart::default();
+    uart.write(IER, IER_RDA | IER_THRE);
+    assert_eq!(uart.read(IER), IER_RDA | IER_THRE);
+    // Empty transmitter at once, once
+    assert!(uart.interrupt());
+    assert_eq!(uart.read(IIR), 0x02);
+    assert!(!uart.interrupt());
+    assert_eq!(uart.read(IIR), 0x01);
+
+    // Received data goes first, as long as it is there
+    uart.write(RBR, b'x');
+    uart.serial.rx.push(b'y').unwrap();
+    assert_eq!(uart.read(IIR), 0x04);
+    assert_eq!(uart.read(IIR), 0x04);
+    uart.read(RBR);
+    assert_eq!(uart.read(IIR), 0x02);
+  }
+
+  #[test]
+  fn divisor_latch() {
+    let mut uart = Uart::default();
+    uart.write(LCR, LCR_DLAB | 3);
+    uart.write(RBR, 0x34);
+    uart.write(IER, 0x12);
+    assert_eq!((uart.read(RBR), uart.read(IER)), (0x34, 0x12));
+    assert!(uart.serial.tx.is_empty());
+
+    uart.write(LCR, 3);
+    assert_eq!((uart.divisor, uart.read(IER)), (0x1234, 0));
+  }
+}
@@ -0,0 +1,193 @@
+use {
+  super::Machine,
+  crate::repr::session::{WatchKind, Watchpoint},
+};
+
+impl WatchKind {
+  pub const ALL: [WatchKind; 3] = [Self::Read, Self::Write, Self::Access];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Read => "read",
+      Self::Write => "write",
+      Self::Access => "access",
+    }
+  }
+
+  fn matches(self, write: bool) -> bool {
+    match self {
+      Self::Read => !write,
+      Self::Write => write,
+      Self::Access => true,
+    }
+  }
+}
+
+/// Load or store that touched a watched range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+  /// Instruction that made the access.
+  pub pc: u64,
+  /// Physical address and width of the access.
+  pub addr: u64,
+  pub size: usize,
+  pub write: bool,
+  pub old: u64,
+  pub new: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Watchpoints {
+  pub list: Vec<Watchpoint>,
+  /// Left by the last step, taken by whoever drives the machine.
+  pub hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+  /// Watch `size` bytes at `addr`, or change how an equal range is watched.
+  pub fn add(&mut self, addr: u64, size: u64, kind: WatchKind) {
+    match self.list.iter_mut().find(|w| w.addr == addr && w.size == size) {
+      Some(watch) => watch.kind = kind,
+      None => self.list.push(Watchpoint { addr, size, kind }),
+    }
+  }
+
+  fn watched(&self, addr: u64, size: usize, write: bool) -> bool {
+    let end = addr.saturating_add(size as u64);
+    self.list.iter().any(|watch| {
+      watch.kind.matches(write)
+        && watch.addr < end
+        && addr < watch.addr.saturating_add(watch.size)
+    })
+  }
+}
+
+impl Machine {
+  /// [`super::Bus::load`] that trips read watchpoints.
+  pub(super) fn bus_load(&mut self, paddr: u64, size: usize) -> Option<u64> {
+    let val = self.bus.load(paddr, size)?;
+    if self.watch.hit.is_none() && self.watch.watched(paddr, size, false) {
+      self.watch.hit = Some(WatchHit {
+        pc: self.cpu.pc,
+        addr: paddr,
+        size,
+        write: false,
+        old: val,
+        new: val,
+      });
+    }
+    Some(val)
+  }
+
+  /// [`super::Bus::store`] that trips write watchpoints.
+  pub(super) fn bus_store(
+    &mut self,
+    paddr: u64,
+    size: usize,
+    val: u64,
+  ) -> Option<()> {
+    // A read hit of the same instruction, like an AMO, gives way to this one
+    let watched = !self.watch.hit.is_some_and(|hit| hit.write)
+      && self.watch.watched(paddr, size, true);
+    let old = if watched { self.bus.peek(paddr, size) } else { None };
+    self.journal(paddr, size);
+    self.bus.store(paddr, size, val)?;
+    if watched {
+      let mask = if size >= 8 { u64::MAX } else { (1 << (size * 8)) - 1 };
+      self.watch.hit = Some(WatchHit {
+        pc: self.cpu.pc,
+        addr: paddr,
+        size,
+        write: true,
+        old: old.unwrap_or_default(),
+        new: val & mask,
+      });
+    }
+    Some(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DATA: u64 = Machine::RAM + 0x100;
+
+  /// Machine about to run `raw` with `a1` on `DATA` and `a2` holding `val`.
+  fn with_access(raw: u32, val: u64) -> Machine {
+    let mut machine = Machine::with_code(&[raw]);
+    machine.bus.store(DATA, 8, 0x1122_3344_5566_7788).unwrap();
+    machine.bus.store(DATA + 8, 8, 0x99).unwrap();
+    (machine.cpu.xregs[11], machine.cpu.xregs[12]) = (DATA, val);
+    machine
+  }
+
+  fn hit(raw: u32, val: u64, watch: (u64, u64, WatchKind)) -> Option<WatchHit> {
+    let mut machine = with_access(raw, val);
+    let (addr, size, kind) = watch;
+    machine.watch.add(addr, size, kind);
+    machine.step().unwrap();
+    machine.watch.hit
+  }
+
+  #[test]
+  fn loads() {
+    let ld = 0x0005b503; // ld a0, 0(a1)
+    let read = hit(ld, 0, (DATA + 4, 4, WatchKind::Read)).unwrap();
+    let val = 0x1122_3344_5566_7788;
+    assert_eq!(
+      read,
+      WatchHit {
+        pc: Machine::RAM,
+        addr: DATA,
+        size: 8,
+        write: false,
+        old: val,
+        new: val
+      }
+    );
+    assert!(hit(ld, 0, (DATA, 8, WatchKind::Access)).is_some());
+    assert_eq!(hit(ld, 0, (DATA, 8, WatchKind::Write)), None);
+    // Past the end of the access
+    assert_eq!(hit(ld, 0, (DATA + 8, 8, WatchKind::Read)), None);
+  }
+
+  #[test]
+  fn stores() {
+    let sd = 0x00c5b423; // sd a2, 8(a1)
+    let write = hit(sd, 0x42, (DATA + 8, 1, WatchKind::Write)).unwrap();
+    assert_eq!((write.addr, write.size, write.write), (DATA + 8, 8, true));
+    assert_eq!((write.old, write.new), (0x99, 0x42));
+    assert!(hit(sd, 0x42, (DATA + 8, 8, WatchKind::Access)).is_some());
+    assert_eq!(hit(sd, 0x42, (DATA + 8, 8, WatchKind::Read)), None);
+
+    // Only the stored byte counts as the new value
+    let sb = 0x00c58023; // sb a2, 0(a1)
+    let write = hit(sb, 0x1ff, (DATA, 1, WatchKind::Write)).unwrap();
+    assert_eq!((write.old, write.new), (0x88, 0xff));
+  }
+
+  #[test]
+  fn amo_reports_its_write() {
+    let amoadd = 0x00c5b52f; // amoadd.d a0, a2, (a1)
+    let write = hit(amoadd, 1, (DATA, 8, WatchKind::Access)).unwrap();
+    assert!(write.write);
+    assert_eq!(
+      (write.old, write.new),
+      (0x1122_3344_5566_7788, 0x1122_3344_5566_7789)
+    );
+    // A read watchpoint still sees the load half
+    let read = hit(amoadd, 1, (DATA, 8, WatchKind::Read)).unwrap();
+    assert!(!read.write);
+  }
+
+  #[test]
+  fn same_range_changes_kind() {
+    let mut watch = Watchpoints::default();
+    watch.add(DATA, 8, WatchKind::Read);
+    watch.add(DATA, 8, WatchKind::Write);
+    watch.add(DATA, 4, WatchKind::Read);
+    assert_eq!(watch.list.len(), 2);
+    assert_eq!(watch.list[0].kind, WatchKind::Write);
+  }
+}
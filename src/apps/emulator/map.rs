@@ -0,0 +1,133 @@
+use {
+  super::machine::{Machine, MapError, validate, virt},
+  crate::{
+    repr::session::{Region, RegionKind},
+    widgets::HexEdit,
+  },
+  egui::{Button, Context, Grid, TextEdit, Window},
+};
+
+/// Edits the memory map on a copy, applied to the machine at once.
+#[derive(Default)]
+pub struct MemoryMap {
+  draft: Vec<Region>,
+  edits: Vec<(HexEdit, HexEdit)>,
+  /// The draft differs from the machine and is not followed anymore.
+  dirty: bool,
+  pub open: bool,
+}
+
+impl MemoryMap {
+  /// Returns whether the layout of the machine changed.
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    machine: &mut Machine,
+    running: bool,
+  ) -> bool {
+    if !self.dirty {
+      // Without the contents, which may be large
+      self.draft = (machine.bus.regions.iter())
+        .map(|&Region { ref name, kind, base, size, .. }| Region {
+          name: name.clone(),
+          kind,
+          base,
+          size,
+          data: vec![],
+        })
+        .collect();
+    }
+    self.edits.resize_with(self.draft.len(), Default::default);
+
+    let mut applied = false;
+    let mut open = self.open;
+    Window::new("Memory map").open(&mut open).show(ctx, |ui| {
+      ui.add_enabled_ui(!running, |ui| {
+        let mut remove = None;
+        Grid::new("memory-map").striped(true).show(ui, |ui| {
+          for label in ["name", "kind", "base", "size"] {
+            ui.label(label);
+          }
+          ui.end_row();
+
+          for (idx, (region, (base, size))) in
+            self.draft.iter_mut().zip(&mut self.edits).enumerate()
+          {
+            let old = (region.base, region.size);
+            let name = ui
+              .add(TextEdit::singleline(&mut region.name).desired_width(80.0));
+            ui.label(region.kind.name());
+            base.show(ui, &mut region.base);
+            if region.kind.is_memory() {
+              size.show(ui, &mut region.size);
+            } else {
+              ui.monospace(format!("{:#x}", region.size));
+            }
+            if ui.small_button("✖").on_hover_text("Remove").clicked() {
+              remove = Some(idx);
+            }
+            ui.end_row();
+            self.dirty |= name.changed() || old != (region.base, region.size);
+          }
+        });
+        if let Some(idx) = remove {
+          self.draft.remove(idx);
+          self.edits.remove(idx);
+          self.dirty = true;
+        }
+
+        ui.horizontal(|ui| {
+          ui.menu_button("Add", |ui| {
+            for kind in RegionKind::ALL {
+              if ui.button(kind.name()).clicked() {
+                let name = self.unique(kind);
+                self.draft.push(Region::new(kind, name, kind.default_base()));
+                self.dirty = true;
+                ui.close_menu();
+              }
+            }
+          });
+          if ui.button("virt").on_hover_text("QEMU `virt` layout").clicked() {
+            self.draft = virt(0x10_0000);
+            self.edits.clear();
+            self.dirty = true;
+          }
+        });
+        ui.separator();
+
+        let valid = validate(&self.draft);
+        if let Err(err) = &valid {
+          ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+        }
+        ui.horizontal(|ui| {
+          let apply = Button::new("Apply");
+          if ui.add_enabled(self.dirty && valid.is_ok(), apply).clicked() {
+            applied = self.apply(machine).is_ok();
+          }
+          if ui.add_enabled(self.dirty, Button::new("Revert")).clicked() {
+            self.dirty = false;
+          }
+        });
+      });
+    });
+    self.open = open;
+    applied
+  }
+
+  fn apply(&mut self, machine: &mut Machine) -> Result<(), MapError> {
+    machine.bus.remap(self.draft.clone())?;
+    // Translations may point into regions that moved
+    machine.tlb.flush();
+    self.dirty = false;
+    Ok(())
+  }
+
+  /// First free name like `ram1` for a new region of `kind`.
+  fn unique(&self, kind: RegionKind) -> String {
+    let prefix = kind.name().to_lowercase();
+    (0..)
+      .map(|idx| format!("{prefix}{idx}"))
+      .find(|name| self.draft.iter().all(|region| &region.name != name))
+      .unwrap()
+  }
+}
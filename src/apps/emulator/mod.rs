@@ -1,18 +1,36 @@
 use {
   crate::{
+    Arx,
     client::Result,
     login::Account,
     repr::session::{SessionInfo, SessionRepr},
     tx,
     widgets::Block,
-    Arx,
   },
   egui::{Align2, CollapsingHeader, Context, ScrollArea, Window},
   emu::EmulatorPanel,
 };
 
 mod asm;
+mod assembler;
+mod breakpoints;
+mod console;
+mod editor;
 mod emu;
+mod expr;
+mod gdb;
+mod highlight;
+mod history;
+mod irq;
+mod lines;
+mod machine;
+mod map;
+mod runner;
+mod source;
+mod symbols;
+mod trace;
+mod walk;
+mod watch;
 
 impl SessionInfo {
   pub fn ui(&self, ui: &mut egui::Ui, idx: usize) {
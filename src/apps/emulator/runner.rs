@@ -0,0 +1,200 @@
+use {
+  super::{
+    breakpoints::Breakpoints,
+    history::History,
+    machine::{Exception, Machine, WatchHit},
+    trace::Trace,
+  },
+  crate::Arx,
+  std::{
+    mem,
+    sync::{
+      Arc, Mutex,
+      atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+  },
+};
+
+/// How often the background loop checks for a pause request.
+const SLICE: Duration = Duration::from_millis(10);
+
+pub enum Halt {
+  Paused,
+  Reached,
+  Exception(Exception),
+  /// A watched range was touched, the access already done.
+  Watch(WatchHit),
+}
+
+/// Drives a [`Machine`] off the UI thread with an instruction budget.
+pub struct Runner {
+  /// Instructions per second.
+  pub ips: u64,
+  /// Every run stops at the enabled ones, once it has left them.
+  pub breakpoints: Breakpoints,
+  /// Steps recorded so far, away with the machine while it runs.
+  pub history: History,
+  pub trace: Trace,
+  stop: Arc<AtomicBool>,
+  /// Breakpoints edited during a run, taken by it at the next slice.
+  edits: Arc<Mutex<Option<Breakpoints>>>,
+  runx: Arx<(Machine, Halt, Breakpoints, History, Trace)>,
+}
+
+impl Default for Runner {
+  fn default() -> Self {
+    Self {
+      ips: 1_000_000,
+      breakpoints: Breakpoints::default(),
+      history: History::default(),
+      trace: Trace::default(),
+      stop: Default::default(),
+      edits: Default::default(),
+      runx: Default::default(),
+    }
+  }
+}
+
+impl Runner {
+  pub fn is_running(&mut self) -> bool {
+    self.runx.ready().is_some()
+  }
+
+  /// Run until paused, an exception, a watchpoint, or `pc` reaches `until` or
+  /// a breakpoint.
+  pub fn run(&mut self, mut machine: Machine, until: Option<u64>) {
+    let stop = Arc::new(AtomicBool::new(false));
+    self.stop = stop.clone();
+    let edits = Arc::new(Mutex::new(None));
+    self.edits = edits.clone();
+
+    let batch = (self.ips as f64 * SLICE.as_secs_f64()).ceil().max(1.0) as u64;
+    // Counts hits on a copy, taken back once the run ends
+    self.breakpoints.take_changed();
+    let mut breakpoints = self.breakpoints.clone();
+    let mut history = std::mem::take(&mut self.history);
+    let mut trace = std::mem::take(&mut self.trace);
+    let task = self.runx.task();
+    tokio::task::spawn_blocking(move || {
+      let halt = 'run: loop {
+        let deadline = Instant::now() + SLICE;
+        for _ in 0..batch {
+          if let Err(err) = trace.step(&mut machine, |m| history.step(m)) {
+            break 'run Halt::Exception(err);
+          }
+          if let Some(hit) = machine.watch.hit.take() {
+            break 'run Halt::Watch(hit);
+          }
+          let pc = machine.cpu.pc;
+          if Some(pc) == until || breakpoints.hit(&machine) {
+            break 'run Halt::Reached;
+          }
+        }
+        if let Some(edited) = edits.lock().unwrap().take() {
+          let ran = mem::replace(&mut breakpoints, edited);
+          breakpoints.merge(ran);
+        }
+        if stop.load(Ordering::Relaxed) {
+          break Halt::Paused;
+        }
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+      };
+      task.send((machine, halt, breakpoints, history, trace));
+    });
+  }
+
+  /// Step `machine` once, recorded like a run records it.
+  pub fn step(&mut self, machine: &mut Machine) -> Result<(), Exception> {
+    let history = &mut self.history;
+    self.trace.step(machine, |machine| history.step(machine))
+  }
+
+  /// The debugger wrote to a stopped machine, which none of the recorded
+  /// steps lead to.
+  pub fn edited(&mut self) {
+    self.history.clear();
+  }
+
+  /// Hand the breakpoints edited since the last call to the run, if any.
+  pub fn sync(&mut self) {
+    if self.breakpoints.take_changed() && self.is_running() {
+      *self.edits.lock().unwrap() = Some(self.breakpoints.clone());
+    }
+  }
+
+  pub fn pause(&self) {
+    self.stop.store(true, Ordering::Relaxed);
+  }
+
+  pub fn poll(&mut self) -> Option<(Machine, Halt)> {
+    let (machine, halt, ran, history, trace) =
+      self.runx.ready()?.try_recv().ok()?;
+    self.breakpoints.merge(ran);
+    self.history = history;
+    self.trace = trace;
+    Some((machine, halt))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LOOP: [u32; 2] = [
+    0x00150513, // addi a0, a0, 1
+    0xffdff06f, // j -4
+  ];
+
+  /// Wait for the run to end.
+  async fn halted(runner: &mut Runner) -> (Machine, Halt) {
+    for _ in 0..1000 {
+      if let Some(done) = runner.poll() {
+        return done;
+      }
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("the run did not stop");
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn stops_at_breakpoints() {
+    let mut runner = Runner::default();
+    runner.breakpoints.insert(Machine::RAM + 4);
+    runner.run(Machine::with_code(&LOOP), None);
+    let (machine, halt) = halted(&mut runner).await;
+    assert!(matches!(halt, Halt::Reached));
+    assert_eq!((machine.cpu.pc, machine.cpu.x(10)), (Machine::RAM + 4, 1));
+    assert_eq!(runner.history.end(), 1);
+
+    runner.run(machine, Some(Machine::RAM));
+    let (machine, halt) = halted(&mut runner).await;
+    assert!(matches!(halt, Halt::Reached));
+    assert_eq!(machine.cpu.pc, Machine::RAM);
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn takes_edits_while_running() {
+    let mut runner = Runner { ips: 1000, ..Runner::default() };
+    runner.run(Machine::with_code(&LOOP), None);
+    assert!(runner.is_running());
+
+    runner.breakpoints.insert(Machine::RAM + 4);
+    runner.sync();
+    let (machine, halt) = halted(&mut runner).await;
+    assert!(matches!(halt, Halt::Reached));
+    assert_eq!(machine.cpu.pc, Machine::RAM + 4);
+    assert!(!runner.breakpoints.take_changed());
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn pauses() {
+    let mut runner = Runner { ips: 1000, ..Runner::default() };
+    runner.run(Machine::with_code(&LOOP), None);
+    runner.pause();
+    let (machine, halt) = halted(&mut runner).await;
+    assert!(matches!(halt, Halt::Paused));
+    assert_eq!(runner.history.end(), machine.cpu.csrs.minstret);
+  }
+}
@@ -0,0 +1,73 @@
+use {
+  super::lines::Lines,
+  egui::{Align, Context, RichText, ScrollArea, Window},
+};
+
+/// Source file of the code at `pc`, with its line highlighted.
+#[derive(Default)]
+pub struct Source {
+  /// File and line shown last, kept while `pc` runs outside known code.
+  line: Option<(u32, u32)>,
+  pub open: bool,
+}
+
+impl Source {
+  /// Forget the shown line, its file may be gone with the executable.
+  pub fn clear(&mut self) {
+    self.line = None;
+  }
+
+  pub fn ui(&mut self, ctx: &Context, lines: &Lines, pc: u64) {
+    let current = lines.lookup(pc);
+    // Scroll along only when the line changes, not every frame
+    let moved = current.is_some() && current != self.line;
+    if moved {
+      self.line = current;
+    }
+
+    let mut open = self.open;
+    Window::new("Source").open(&mut open).default_size([480.0, 400.0]).show(
+      ctx,
+      |ui| {
+        let Some((file, line)) = self.line else {
+          ui.weak(if lines.is_empty() {
+            "Load an ELF file built with `-g` to see its source"
+          } else {
+            "No source for the current pc"
+          });
+          return;
+        };
+        ui.monospace(format!("{}:{line}", lines.path(file)));
+        if current.is_none() {
+          ui.weak("pc is outside of known code");
+        }
+        ui.separator();
+
+        let source = lines.source(file);
+        if source.is_empty() {
+          ui.weak("Source file not found next to the executable");
+          return;
+        }
+        ScrollArea::both().auto_shrink(false).show(ui, |ui| {
+          let width = source.len().to_string().len();
+          for (idx, text) in source.iter().enumerate() {
+            let text = format!("{:>width$}  {text}", idx + 1);
+            if idx + 1 == line as usize {
+              let text = RichText::new(text)
+                .monospace()
+                .strong()
+                .background_color(ui.visuals().selection.bg_fill);
+              let response = ui.label(text);
+              if moved {
+                response.scroll_to_me(Some(Align::Center));
+              }
+            } else {
+              ui.monospace(text);
+            }
+          }
+        });
+      },
+    );
+    self.open = open;
+  }
+}
@@ -0,0 +1,51 @@
+use {crate::repr::session::Symbol, std::collections::BTreeMap};
+
+/// Symbols of the loaded executable, one name per address.
+#[derive(Default)]
+pub struct Symbols {
+  by_addr: BTreeMap<u64, Symbol>,
+}
+
+impl Symbols {
+  pub fn new(symbols: Vec<Symbol>) -> Self {
+    let mut by_addr = BTreeMap::<u64, Symbol>::new();
+    for symbol in symbols {
+      // Sized functions and objects win over bare labels at their address
+      match by_addr.get(&symbol.addr) {
+        Some(old) if old.size != 0 || symbol.size == 0 => {}
+        _ => {
+          by_addr.insert(symbol.addr, symbol);
+        }
+      }
+    }
+    Self { by_addr }
+  }
+
+  pub fn repr(&self) -> Vec<Symbol> {
+    self.by_addr.values().cloned().collect()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+    self.by_addr.values()
+  }
+
+  /// Symbol starting exactly at `addr`.
+  pub fn at(&self, addr: u64) -> Option<&Symbol> {
+    self.by_addr.get(&addr)
+  }
+
+  /// Nearest symbol at or below `addr` along with the offset into it.
+  pub fn lookup(&self, addr: u64) -> Option<(&Symbol, u64)> {
+    let (_, symbol) = self.by_addr.range(..=addr).next_back()?;
+    let offset = addr - symbol.addr;
+    (symbol.size == 0 || offset < symbol.size).then_some((symbol, offset))
+  }
+
+  /// `<name+0x12>`, the way `objdump` annotates addresses.
+  pub fn annotate(&self, addr: u64) -> Option<String> {
+    self.lookup(addr).map(|(symbol, offset)| match offset {
+      0 => format!("<{}>", symbol.name),
+      offset => format!("<{}+{offset:#x}>", symbol.name),
+    })
+  }
+}
@@ -0,0 +1,333 @@
+use {
+  super::{
+    expr::Reg,
+    machine::{CSRS, Exception, FFLAGS, Machine, MemAccess, Priv},
+  },
+  crate::widgets::HexEdit,
+  egui::{Context, DragValue, ScrollArea, TextStyle, Window},
+  egui_file_dialog::FileDialog,
+  egui_toast::{Toast, ToastKind, Toasts},
+  raki::{OpcodeKind, ZicsrOpcode},
+  std::{collections::VecDeque, fmt::Write, fs},
+};
+
+/// A retired instruction and its effects.
+pub struct Commit {
+  pub pc: u64,
+  pub raw: u32,
+  pub len: u64,
+  pub mode: Priv,
+  /// Registers written, in the order Spike logs them.
+  pub writes: Vec<(Reg, u64)>,
+  pub accesses: Vec<MemAccess>,
+}
+
+impl Commit {
+  fn disassembly(&self) -> String {
+    Machine::disassemble(self.raw, self.len)
+      .unwrap_or_else(|| String::from("unknown instruction"))
+  }
+
+  /// Registers and memory like Spike prints them after an instruction.
+  fn effects(&self) -> String {
+    let mut out = String::new();
+    for &(reg, val) in &self.writes {
+      let _ = match reg {
+        Reg::X(idx) => write!(out, " x{idx:<2} 0x{val:016x}"),
+        Reg::F(idx) => write!(out, " f{idx:<2} 0x{val:016x}"),
+        Reg::Csr(addr) => {
+          let name = CSRS.iter().find(|&&(csr, _)| csr == addr);
+          let name = name.map_or("unknown", |&(_, name)| name);
+          write!(out, " c{addr}_{name} 0x{val:016x}")
+        }
+        Reg::Pc => Ok(()),
+      };
+    }
+    for access in &self.accesses {
+      let _ = match *access {
+        MemAccess::Load { addr, .. } => write!(out, " mem 0x{addr:016x}"),
+        MemAccess::Store { addr, size, val } => {
+          let val = if size >= 8 { val } else { val & ((1 << (size * 8)) - 1) };
+          write!(out, " mem 0x{addr:016x} 0x{val:0width$x}", width = size * 2)
+        }
+      };
+    }
+    out
+  }
+
+  /// The line of Spike's `--log-commits` for this instruction.
+  pub fn spike(&self) -> String {
+    let Self { pc, raw, len, mode, .. } = *self;
+    let width = len as usize * 2;
+    let effects = self.effects();
+    format!("core   0: {} 0x{pc:016x} (0x{raw:0width$x}){effects}", mode as u8)
+  }
+}
+
+/// Ring buffer of the last retired instructions.
+pub struct Trace {
+  pub enabled: bool,
+  /// Most instructions kept, the oldest are dropped beyond.
+  pub capacity: usize,
+  commits: VecDeque<Commit>,
+}
+
+impl Default for Trace {
+  fn default() -> Self {
+    Self { enabled: false, capacity: 100_000, commits: VecDeque::new() }
+  }
+}
+
+impl Trace {
+  pub fn clear(&mut self) {
+    self.commits.clear();
+  }
+
+  /// Run `step` on `machine`, recording the instruction if it retires.
+  pub fn step(
+    &mut self,
+    machine: &mut Machine,
+    step: impl FnOnce(&mut Machine) -> Result<(), Exception>,
+  ) -> Result<(), Exception> {
+    if !self.enabled {
+      return step(machine);
+    }
+
+    let cpu = &machine.cpu;
+    let (pc, mode, minstret) = (cpu.pc, cpu.mode, cpu.csrs.minstret);
+    let (xregs, fregs, fcsr) = (cpu.xregs, cpu.fregs, cpu.fcsr);
+    let fetched = machine.raw(pc);
+
+    machine.accesses.enabled = true;
+    let result = step(machine);
+    machine.accesses.enabled = false;
+    let accesses = machine.accesses.list.drain(..).collect();
+
+    // Traps and interrupts are taken without retiring anything
+    let cpu = &machine.cpu;
+    let Some((raw, len)) = fetched.filter(|_| cpu.csrs.minstret != minstret)
+    else {
+      return result;
+    };
+    let inst = Machine::decode(raw, len).ok();
+
+    let mut writes = vec![];
+    // Writes of an unchanged value show only in the destination register
+    let rd = inst.as_ref().and_then(|inst| inst.rd).filter(|&rd| rd != 0);
+    for (idx, (&val, old)) in cpu.xregs.iter().zip(xregs).enumerate().skip(1) {
+      if val != old || rd == Some(idx) {
+        writes.push((Reg::X(idx), val));
+      }
+    }
+    for (idx, (&val, old)) in cpu.fregs.iter().zip(fregs).enumerate() {
+      if val != old {
+        writes.push((Reg::F(idx), val));
+      }
+    }
+    match inst.as_ref().map(|inst| &inst.opc) {
+      Some(OpcodeKind::Zicsr(op)) => {
+        // Set and clear with `x0` or a zero immediate only read
+        let written = matches!(op, ZicsrOpcode::CSRRW | ZicsrOpcode::CSRRWI)
+          || raw >> 15 & 0x1f != 0;
+        let addr = (raw >> 20) as u16;
+        if let Some(val) = machine.csr_read(addr).filter(|_| written) {
+          writes.push((Reg::Csr(addr), val));
+        }
+      }
+      _ if (cpu.fcsr ^ fcsr) & 0x1f != 0 => {
+        writes.push((Reg::Csr(FFLAGS), (cpu.fcsr & 0x1f) as u64));
+      }
+      _ => {}
+    }
+
+    while self.commits.len() >= self.capacity {
+      self.commits.pop_front();
+    }
+    self.commits.push_back(Commit { pc, raw, len, mode, writes, accesses });
+    result
+  }
+}
+
+/// Shows the trace, filters it by address and exports it.
+pub struct TraceView {
+  pub open: bool,
+  /// Show only instructions within `from..to`.
+  filter: bool,
+  from: u64,
+  to: u64,
+  edits: [HexEdit; 2],
+  dialog: FileDialog,
+}
+
+impl Default for TraceView {
+  fn default() -> Self {
+    Self {
+      open: false,
+      filter: false,
+      from: 0,
+      to: u64::MAX,
+      edits: Default::default(),
+      dialog: FileDialog::new().default_file_name("trace.log"),
+    }
+  }
+}
+
+impl TraceView {
+  fn shown<'a>(&self, trace: &'a Trace) -> Vec<&'a Commit> {
+    let range = self.from..self.to;
+    (trace.commits.iter())
+      .filter(|commit| !self.filter || range.contains(&commit.pc))
+      .collect()
+  }
+
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    trace: &mut Trace,
+    running: bool,
+    toasts: &mut Toasts,
+  ) {
+    self.dialog.update(ctx);
+    if let Some(path) = self.dialog.take_selected() {
+      let mut text = String::new();
+      for commit in self.shown(trace) {
+        text.push_str(&commit.spike());
+        text.push('\n');
+      }
+      if let Err(err) = fs::write(&path, text) {
+        let text = format!("{}: {err}", path.display());
+        toasts.add(Toast::new().kind(ToastKind::Error).text(text));
+      }
+    }
+
+    let mut open = self.open;
+    Window::new("Trace").open(&mut open).default_size([640.0, 400.0]).show(
+      ctx,
+      |ui| {
+        if running {
+          ui.weak(if trace.enabled { "Recording…" } else { "Running" });
+          return;
+        }
+        ui.horizontal(|ui| {
+          ui.checkbox(&mut trace.enabled, "Record");
+          ui.add(
+            DragValue::new(&mut trace.capacity)
+              .range(1..=10_000_000)
+              .suffix(" instructions"),
+          )
+          .on_hover_text("The oldest are dropped beyond");
+          if ui.button("Clear").clicked() {
+            trace.clear();
+          }
+          if ui
+            .button("Export")
+            .on_hover_text("As Spike's --log-commits")
+            .clicked()
+          {
+            self.dialog.save_file();
+          }
+        });
+        ui.horizontal(|ui| {
+          ui.checkbox(&mut self.filter, "Only pc from");
+          let [from, to] = &mut self.edits;
+          from.show(ui, &mut self.from);
+          ui.label("to");
+          to.show(ui, &mut self.to);
+        });
+
+        let shown = self.shown(trace);
+        ui.weak(format!(
+          "{} of {} instructions",
+          shown.len(),
+          trace.commits.len()
+        ));
+        ui.separator();
+
+        let height = ui.text_style_height(&TextStyle::Monospace);
+        ScrollArea::both().auto_shrink(false).stick_to_bottom(true).show_rows(
+          ui,
+          height,
+          shown.len(),
+          |ui, rows| {
+            for commit in &shown[rows] {
+              ui.monospace(format!(
+                "{:#018x}  {:08x}  {:<28}{}",
+                commit.pc,
+                commit.raw,
+                commit.disassembly(),
+                commit.effects()
+              ));
+            }
+          },
+        );
+      },
+    );
+    self.open = open;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DATA: u64 = Machine::RAM + 0x100;
+
+  /// Spike's lines for every instruction of `code`.
+  fn spike(code: &[u32], steps: usize) -> Vec<String> {
+    let mut machine = Machine::with_code(code);
+    machine.cpu.xregs[10] = 0x88;
+    machine.cpu.xregs[11] = DATA;
+    let mut trace = Trace { enabled: true, ..Trace::default() };
+    for _ in 0..steps {
+      trace.step(&mut machine, Machine::step).unwrap();
+    }
+    trace.commits.iter().map(Commit::spike).collect()
+  }
+
+  #[test]
+  fn spike_lines() {
+    let lines = spike(
+      &[
+        0x00150513,  // addi a0, a0, 1
+        0x0001_0505, // c.addi a0, 1; c.nop
+        0x00a5a023,  // sw a0, 0(a1)
+        0x00a58023,  // sb a0, 0(a1)
+        0x0005a603,  // lw a2, 0(a1)
+        0x30051073,  // csrw mstatus, a0
+      ],
+      7,
+    );
+    let base = "core   0: 3 0x00000000800000";
+    assert_eq!(
+      lines,
+      [
+        format!("{base}00 (0x00150513) x10 0x0000000000000089"),
+        format!("{base}04 (0x0505) x10 0x000000000000008a"),
+        format!("{base}06 (0x0001)"),
+        format!("{base}08 (0x00a5a023) mem 0x0000000080000100 0x0000008a"),
+        format!("{base}0c (0x00a58023) mem 0x0000000080000100 0x8a"),
+        format!(
+          "{base}10 (0x0005a603) x12 0x000000000000008a mem 0x0000000080000100"
+        ),
+        format!("{base}14 (0x30051073) c768_mstatus 0x0000000a0000008a"),
+      ]
+    );
+  }
+
+  #[test]
+  fn unchanged_destinations_are_logged() {
+    let lines = spike(&[0x00050513], 1); // mv a0, a0
+    assert_eq!(
+      lines,
+      ["core   0: 3 0x0000000080000000 (0x00050513) x10 0x0000000000000088"]
+    );
+  }
+
+  #[test]
+  fn traps_retire_nothing() {
+    let mut machine = Machine::with_code(&[0x00000073]); // ecall
+    let mut trace = Trace { enabled: true, ..Trace::default() };
+    assert!(trace.step(&mut machine, Machine::step).is_err());
+    assert!(trace.commits.is_empty());
+  }
+}
@@ -0,0 +1,81 @@
+use {
+  super::machine::{Machine, Mode, pte},
+  egui::{Color32, Context, Grid, RichText, TextEdit, Window},
+};
+
+const FLAGS: [(u64, &str); 8] = [
+  (pte::V, "V"),
+  (pte::R, "R"),
+  (pte::W, "W"),
+  (pte::X, "X"),
+  (pte::U, "U"),
+  (pte::G, "G"),
+  (pte::A, "A"),
+  (pte::D, "D"),
+];
+
+/// Shows how `satp` translates a virtual address, level by level.
+#[derive(Default)]
+pub struct PageWalk {
+  addr: String,
+  pub open: bool,
+}
+
+impl PageWalk {
+  pub fn ui(&mut self, ctx: &Context, machine: &Machine) {
+    let mut open = self.open;
+    Window::new("Page walk").open(&mut open).show(ctx, |ui| {
+      ui.horizontal(|ui| {
+        ui.label("Virtual address:");
+        ui.add(TextEdit::singleline(&mut self.addr).hint_text("0000"))
+          .on_hover_text("Address to translate, written in hex like `AA`");
+      });
+      self.addr.retain(|c| c.is_ascii_hexdigit());
+
+      let Ok(vaddr) = u64::from_str_radix(&self.addr, 16) else {
+        return;
+      };
+      let walk = machine.walk(vaddr);
+
+      ui.label(match walk.mode {
+        Mode::Bare => format!("satp: {:?}", walk.mode),
+        mode => format!("satp: {mode:?}, root table at {:#x}", walk.root),
+      });
+      ui.separator();
+
+      Grid::new("page-walk").striped(true).show(ui, |ui| {
+        for level in &walk.levels {
+          let vpn = vaddr >> (12 + 9 * level.level) & 0x1ff;
+          ui.label(
+            RichText::new(format!("L{}", level.level))
+              .color(Color32::from_rgb(0, 140, 140)),
+          );
+          ui.monospace(format!("vpn {vpn:#05x}"));
+          ui.monospace(format!("pte @ {:#x}", level.addr));
+          ui.monospace(format!("0x{:016x}", level.pte));
+          ui.horizontal(|ui| {
+            for (bit, name) in FLAGS {
+              if level.pte & bit != 0 {
+                ui.monospace(name);
+              } else {
+                ui.weak(name);
+              }
+            }
+          });
+          ui.end_row();
+        }
+      });
+      ui.separator();
+
+      match walk.leaf {
+        Ok(leaf) => {
+          ui.label(format!("Physical address: {:#x}", leaf.paddr(vaddr)));
+        }
+        Err(err) => {
+          ui.colored_label(ui.visuals().error_fg_color, err.to_string());
+        }
+      }
+    });
+    self.open = open;
+  }
+}
@@ -0,0 +1,106 @@
+use {
+  super::{
+    machine::{Machine, WatchHit, Watchpoints},
+    symbols::Symbols,
+  },
+  crate::repr::session::WatchKind,
+  egui::{ComboBox, Context, Grid, RichText, ScrollArea, Window},
+};
+
+/// What `hit` did and which instruction did it, for a toast.
+pub fn describe(hit: &WatchHit, machine: &Machine) -> String {
+  let WatchHit { pc, addr, size, write, old, new } = *hit;
+  let by = match machine.instruction(pc) {
+    Some(inst) => format!("`{inst}` at {pc:#x}"),
+    None => format!("{pc:#x}"),
+  };
+  if write {
+    format!("write of {size} bytes at {addr:#x} by {by}: {old:#x} → {new:#x}")
+  } else {
+    format!("read of {size} bytes at {addr:#x} by {by}: {new:#x}")
+  }
+}
+
+/// Lists the watchpoints set from the memory window.
+#[derive(Default)]
+pub struct WatchList {
+  /// Last stop on a watchpoint, with its description.
+  last: Option<(WatchHit, String)>,
+  pub open: bool,
+}
+
+impl WatchList {
+  /// Remember `hit` and describe it.
+  pub fn hit(&mut self, hit: WatchHit, machine: &Machine) -> String {
+    let text = describe(&hit, machine);
+    self.last = Some((hit, text.clone()));
+    text
+  }
+
+  pub fn ui(
+    &mut self,
+    ctx: &Context,
+    watch: &mut Watchpoints,
+    symbols: &Symbols,
+    running: bool,
+  ) {
+    let mut open = self.open;
+    Window::new("Watchpoints").open(&mut open).show(ctx, |ui| {
+      if watch.list.is_empty() {
+        ui.weak("Right-click a byte in the memory window to add one");
+        return;
+      }
+
+      ui.add_enabled_ui(!running, |ui| {
+        let mut remove = None;
+        ScrollArea::vertical().show(ui, |ui| {
+          Grid::new("watchpoints").striped(true).show(ui, |ui| {
+            for label in ["range", "", "stops on"] {
+              ui.label(label);
+            }
+            ui.end_row();
+
+            for (idx, point) in watch.list.iter_mut().enumerate() {
+              let end = point.addr.saturating_add(point.size);
+              let hit = self.last.as_ref().is_some_and(|(hit, _)| {
+                hit.addr < end && point.addr < hit.addr + hit.size as u64
+              });
+              let range = format!("{:#x}..{end:#x}", point.addr);
+              let range = RichText::new(range).monospace();
+              ui.label(if hit { range.strong() } else { range });
+              ui.monospace(symbols.annotate(point.addr).unwrap_or_default());
+              ComboBox::from_id_salt(("watch", idx))
+                .selected_text(point.kind.name())
+                .show_ui(ui, |ui| {
+                  for kind in WatchKind::ALL {
+                    ui.selectable_value(&mut point.kind, kind, kind.name());
+                  }
+                });
+              if ui.small_button("✖").on_hover_text("Delete").clicked() {
+                remove = Some(idx);
+              }
+              ui.end_row();
+            }
+          });
+        });
+        if let Some(idx) = remove {
+          watch.list.remove(idx);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+          if ui.button("Delete all").clicked() {
+            watch.list.clear();
+          }
+        });
+      });
+
+      if let Some((_, text)) = &self.last {
+        ui.separator();
+        ui.label("Last hit:");
+        ui.monospace(text);
+      }
+    });
+    self.open = open;
+  }
+}
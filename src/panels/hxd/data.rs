@@ -1,5 +1,6 @@
 use {
   super::Address,
+  crate::repr::session::WatchKind,
   egui::{Color32, TextStyle},
   std::ops::Range,
 };
@@ -149,6 +150,11 @@ pub(crate) struct BetweenFrameData {
 
   pub goto_address_string: String,
   pub goto_address_line: Option<usize>,
+
+  /// Bytes covered by a watchpoint added from the context menu.
+  pub watch_size: usize,
+  /// Watchpoint asked for in the context menu, until the owner takes it.
+  pub watch_request: Option<(Range<Address>, WatchKind)>,
 }
 
 impl BetweenFrameData {
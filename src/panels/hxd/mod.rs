@@ -1,5 +1,7 @@
 use std::{collections::BTreeMap, ops::Range};
 
+use crate::repr::session::WatchKind;
+
 use egui::{
   Context, Label, Margin, RichText, ScrollArea, Sense, TextEdit, TextWrapMode,
   Ui, Vec2, Widget, Window,
@@ -33,6 +35,8 @@ pub struct MemoryEditor {
   pub(crate) frame_data: BetweenFrameData,
   /// The visible range of addresses from the last frame.
   visible_range: Range<Address>,
+  /// Names the goto field accepts and the data preview shows.
+  symbols: BTreeMap<Address, String>,
 }
 
 impl MemoryEditor {
@@ -58,6 +62,7 @@ impl MemoryEditor {
       options: Default::default(),
       frame_data: Default::default(),
       visible_range: Default::default(),
+      symbols: BTreeMap::new(),
     }
   }
 
@@ -88,9 +93,15 @@ impl MemoryEditor {
   ) {
     // This needs to exist due to the fact we want to use generics, and `Option` needs to know the size of its contents.
     type DummyWriteFunction<T> = fn(&mut T, Address, u8);
+    type DummySaveFunction = fn(Address);
 
-    // self.window_ui_impl(ctx, mem, read_fn, None::<DummyWriteFunction<T>>);
-    todo!()
+    self.window_ui_impl(
+      ctx,
+      mem,
+      read_fn,
+      None::<DummyWriteFunction<T>>,
+      None::<DummySaveFunction>,
+    );
   }
 
   /// Create a window and render the memory editor contents within.
@@ -381,6 +392,32 @@ impl MemoryEditor {
                             frame_data.set_highlight_address(memory_address);
                         }
 
+                        response.context_menu(|ui| {
+                            if frame_data.watch_size == 0 {
+                                frame_data.watch_size = 1;
+                            }
+                            ui.horizontal(|ui| {
+                                ui.label("Watch");
+                                for size in [1, 2, 4, 8] {
+                                    ui.selectable_value(&mut frame_data.watch_size, size, size.to_string());
+                                }
+                                ui.label("bytes");
+                            });
+                            let range = memory_address..memory_address + frame_data.watch_size;
+                            ui.weak(format!("0x{:X}..0x{:X}", range.start, range.end));
+                            ui.separator();
+                            for (text, kind) in [
+                                ("Break on reads", WatchKind::Read),
+                                ("Break on writes", WatchKind::Write),
+                                ("Break on reads and writes", WatchKind::Access),
+                            ] {
+                                if ui.button(text).clicked() {
+                                    frame_data.watch_request = Some((range.clone(), kind));
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+
                         // Left click depends on read only mode.
                         if response.clicked() {
                             if write_fn.is_some() {
@@ -536,6 +573,20 @@ impl MemoryEditor {
     }
   }
 
+  /// Watchpoint the user asked for from the context menu of a value, if any.
+  pub fn take_watch(&mut self) -> Option<(Range<Address>, WatchKind)> {
+    self.frame_data.watch_request.take()
+  }
+
+  /// Replace the symbols by their names.
+  pub fn set_symbols(
+    &mut self,
+    symbols: impl IntoIterator<Item = (String, Address)>,
+  ) {
+    self.symbols =
+      symbols.into_iter().map(|(name, address)| (address, name)).collect();
+  }
+
   /// Set the memory options, useful if you use the `persistence` feature.
   #[inline]
   #[must_use]
@@ -89,11 +89,15 @@ impl MemoryEditor {
               * An address like `0xAA` can be written as `AA`\n\
               * Offset from the base address, if the base is `0xFF00` \
               then one can enter `5` to go to `0xFF05`\n\
+              * A symbol name like `main`\n\
               Press enter to move to the address",
         );
       ui.label(format!("Goto: {:#X?}", current_address_range));
 
-      self.frame_data.goto_address_string.retain(|c| c.is_ascii_hexdigit());
+      self
+        .frame_data
+        .goto_address_string
+        .retain(|c| c.is_ascii_alphanumeric() || "_.$".contains(c));
 
       // For some reason egui is triggering response.clicked() when we press enter at the moment
       // (didn't used to do this).
@@ -106,6 +110,11 @@ impl MemoryEditor {
       if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))
       {
         let goto_address_string = &mut self.frame_data.goto_address_string;
+        let symbol = self
+          .symbols
+          .iter()
+          .find(|(_, name)| name.as_str() == goto_address_string.as_str())
+          .map(|(&address, _)| address);
 
         if goto_address_string.starts_with("0x")
           || goto_address_string.starts_with("0X")
@@ -113,8 +122,19 @@ impl MemoryEditor {
           *goto_address_string = goto_address_string[2..].to_string();
         }
 
-        let address = Address::from_str_radix(goto_address_string, 16)
-          .ok()
+        // Symbols may live in another region, which is selected for them
+        let symbol_range = symbol.and_then(|addr| {
+          let (name, range) = (self.address_ranges.iter())
+            .find(|(_, range)| range.contains(&addr))?;
+          self.options.selected_address_range = name.clone();
+          Some(range.clone())
+        });
+        let current_address_range =
+          symbol_range.as_ref().unwrap_or(current_address_range);
+
+        let address = symbol
+          .filter(|_| symbol_range.is_some())
+          .or_else(|| Address::from_str_radix(goto_address_string, 16).ok())
           .and_then(|addr| {
             if current_address_range.contains(&addr) {
               Some(addr)
@@ -236,6 +256,15 @@ impl MemoryEditor {
             ui.label(format!("Value at {:#X} (decimal): ", address))
               .on_hover_text(hover_text);
             ui.label(format!("0x{value}"));
+            ui.end_row();
+
+            let symbol = self.symbols.range(..=address).next_back();
+            ui.label("Symbol: ");
+            ui.label(match symbol {
+              Some((&start, name)) if start == address => name.clone(),
+              Some((&start, name)) => format!("{name}+{:#x}", address - start),
+              None => "None".into(),
+            });
           } else {
             ui.label("Value (decimal): ").on_hover_text(hover_text);
             ui.label("None");
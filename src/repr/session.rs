@@ -1,4 +1,7 @@
-use serde::{Deserialize, Serialize};
+use {
+  serde::{Deserialize, Serialize},
+  std::ops::Range,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SessionInfo {
@@ -17,21 +20,143 @@ pub struct SessionRepr {
   pub creation: String,
   pub modified: String,
   pub cpu: CpuRepr,
+  /// Missing from sessions saved before debug info was kept.
+  #[serde(default)]
+  pub debug: DebugRepr,
+}
+
+/// What the debugger knows about the loaded executable.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DebugRepr {
+  pub symbols: Vec<Symbol>,
+  #[serde(default)]
+  pub lines: LineTable,
+  #[serde(default)]
+  pub breakpoints: Vec<Breakpoint>,
+  #[serde(default)]
+  pub watchpoints: Vec<Watchpoint>,
+  /// Where the executable starts, to disassemble from.
+  #[serde(default)]
+  pub entry: Option<u64>,
+  /// Ranges the user marked as code.
+  #[serde(default)]
+  pub code: Vec<Range<u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Breakpoint {
+  pub addr: u64,
+  pub enabled: bool,
+  /// Expression that must be non-zero to stop, always stops when empty.
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub condition: String,
+  /// Hits passed over before the first stop.
+  #[serde(default)]
+  pub after: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+  Read,
+  Write,
+  Access,
+}
+
+/// Physical byte range whose loads or stores stop execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watchpoint {
+  pub addr: u64,
+  pub size: u64,
+  pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+  pub name: String,
+  pub addr: u64,
+  /// Zero when the object has no known size.
+  pub size: u64,
+  /// Names code rather than data.
+  #[serde(default)]
+  pub code: bool,
+}
+
+/// Source lines of addresses, from the DWARF line number programs.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LineTable {
+  /// Source paths, resolved against the executable once it is loaded.
+  pub files: Vec<String>,
+  /// `(addr, file, line)` sorted by address, line zero where code ends.
+  pub rows: Vec<(u64, u32, u32)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CpuRepr {
   pub pc: u64,
   pub xregs: Vec<u64>,
+  /// Values for older readers, zero where JSON numbers cannot hold them.
   pub fregs: Vec<f64>,
+  /// Every bit of the float registers, NaN-boxed singles included. Missing
+  /// from sessions saved before, which only have `fregs`.
+  #[serde(default)]
+  pub fbits: Vec<u64>,
   pub bus: Bus,
+  /// Missing from sessions saved before the CSR file existed.
+  #[serde(default)]
+  pub csr: CsrRepr,
 }
 
-use serde_with::{base64::Base64, serde_as};
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsrRepr {
+  /// Privilege level: 0 user, 1 supervisor, 3 machine.
+  pub mode: u8,
+  pub regs: BTreeMap<u16, u64>,
+}
+
+impl Default for CsrRepr {
+  fn default() -> Self {
+    Self { mode: 3, regs: BTreeMap::new() }
+  }
+}
+
+use {
+  serde_with::{base64::Base64, serde_as},
+  std::collections::BTreeMap,
+};
 
 #[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bus {
+  /// Missing from sessions saved before the memory map existed.
+  #[serde(default)]
+  pub regions: Vec<Region>,
+  /// Flat memory at address 0 of those older sessions, and of the memory
+  /// region at 0 since, which older readers require.
   #[serde_as(as = "Base64")]
+  #[serde(default)]
   pub dram: Vec<u8>,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+  Ram,
+  Rom,
+  Uart,
+  Clint,
+  Plic,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Region {
+  pub name: String,
+  pub kind: RegionKind,
+  pub base: u64,
+  pub size: u64,
+  /// Contents of memory regions, empty for devices.
+  #[serde_as(as = "Base64")]
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub data: Vec<u8>,
+}